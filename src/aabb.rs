@@ -0,0 +1,119 @@
+use num::Float;
+
+use crate::common::SVecElem;
+use crate::ray::*;
+use crate::vec3::*;
+
+/// Axis-aligned bounding box spanned by two corner points.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb<T: SVecElem> {
+    pub minimum: Point3<T>,
+    pub maximum: Point3<T>,
+}
+
+impl<T> Aabb<T>
+where
+    T: SVecElem + Float,
+{
+    pub fn new(a: Point3<T>, b: Point3<T>) -> Self {
+        Self {
+            minimum: Point3(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            maximum: Point3(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    /// Slab test against the ray, restricted to `[t_min, t_max]`.
+    pub fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> bool {
+        self.clip(ray, t_min, t_max).is_some()
+    }
+
+    /// The part of `[t_min, t_max]` the ray spends inside the box.
+    pub fn clip(&self, ray: &Ray<T>, mut t_min: T, mut t_max: T) -> Option<(T, T)> {
+        for a in 0..3 {
+            let inv_d = T::one() / ray.direction[a];
+            let mut t0 = (self.minimum[a] - ray.origin[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - ray.origin[a]) * inv_d;
+            if inv_d < T::zero() {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // Written so that a NaN from `0 * inf` keeps the previous bound.
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+
+    pub fn centroid(&self) -> Point3<T> {
+        (self.minimum + self.maximum) * T::from_f64(0.5).unwrap()
+    }
+
+    pub fn extent(&self) -> Vec3<T> {
+        self.maximum - self.minimum
+    }
+
+    pub fn surface_area(&self) -> T {
+        let d = self.extent();
+        (d.x() * d.y() + d.y() * d.z() + d.z() * d.x()) * T::from_f64(2.).unwrap()
+    }
+
+    /// Index of the axis along which the box is widest.
+    pub fn longest_axis(&self) -> usize {
+        let d = self.extent();
+        if d.x() > d.y() && d.x() > d.z() {
+            0
+        } else if d.y() > d.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn grow(&self, p: Point3<T>) -> Self {
+        Self {
+            minimum: Point3(self.minimum.x().min(p.x()), self.minimum.y().min(p.y()), self.minimum.z().min(p.z())),
+            maximum: Point3(self.maximum.x().max(p.x()), self.maximum.y().max(p.y()), self.maximum.z().max(p.z())),
+        }
+    }
+}
+
+pub fn surrounding_box<T>(box0: &Aabb<T>, box1: &Aabb<T>) -> Aabb<T>
+where
+    T: SVecElem + Float,
+{
+    box0.grow(box1.minimum).grow(box1.maximum)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hit() {
+        let bbox = Aabb::new(Point3(-1.0, -1.0, -1.0), Point3(1.0, 1.0, 1.0));
+        let towards = Ray { origin: Point3(0.0, 0.0, -5.0), direction: Vec3(0.0, 0.0, 1.0), time: 0., wavelengths: None };
+        let away = Ray { origin: Point3(0.0, 0.0, -5.0), direction: Vec3(0.0, 0.0, -1.0), time: 0., wavelengths: None };
+        let beside = Ray { origin: Point3(2.0, 0.0, -5.0), direction: Vec3(0.0, 0.0, 1.0), time: 0., wavelengths: None };
+
+        assert!(bbox.hit(&towards, 0.0, f64::INFINITY));
+        assert!(!bbox.hit(&towards, 0.0, 3.0));
+        assert!(!bbox.hit(&away, 0.0, f64::INFINITY));
+        assert!(!bbox.hit(&beside, 0.0, f64::INFINITY));
+        assert_eq!(bbox.clip(&towards, 0.0, f64::INFINITY), Some((4.0, 6.0)));
+        assert_eq!(bbox.clip(&towards, 5.0, 5.5), Some((5.0, 5.5)));
+    }
+
+    #[test]
+    fn surrounding() {
+        let a = Aabb::new(Point3(0.0, 0.0, 0.0), Point3(1.0, 1.0, 1.0));
+        let b = Aabb::new(Point3(-1.0, 0.5, 0.5), Point3(0.5, 1.5, 0.5));
+        let s = surrounding_box(&a, &b);
+        assert_eq!(s.minimum, Point3(-1.0, 0.0, 0.0));
+        assert_eq!(s.maximum, Point3(1.0, 1.5, 1.0));
+        assert_eq!(s.surface_area(), 2.0 * (2.0 * 1.5 + 1.5 * 1.0 + 1.0 * 2.0));
+        assert_eq!(s.longest_axis(), 0);
+    }
+}
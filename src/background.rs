@@ -0,0 +1,123 @@
+use num::Float;
+use std::sync::Arc;
+
+use crate::common::SVecElem;
+use crate::envmap::*;
+use crate::ray::*;
+use crate::sampler::*;
+use crate::sky::*;
+use crate::vec3::*;
+
+/// Radiance seen by rays that leave the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background<T: SVecElem> {
+    /// Black; the scene is lit by its emissive materials only.
+    None,
+    Solid(Color3<T>),
+    /// Blend from `bottom` (straight down) to `top` (straight up).
+    Gradient { bottom: Color3<T>, top: Color3<T> },
+    /// An HDR image all round, sampled as a light.
+    Environment(Arc<EnvironmentMap<T>>),
+    /// Analytic clear sky with a sun that is sampled as a light.
+    Daylight(Daylight<T>),
+}
+
+impl<T: SVecElem + Float> Background<T> {
+    /// The white to light blue sky of the original renderer.
+    pub fn sky() -> Self {
+        Background::Gradient { bottom: Color3::new(1.0, 1.0, 1.0), top: Color3::new(0.5, 0.7, 1.0) }
+    }
+
+    /// A clear day with the sun 45° up ahead, over a grey ground.
+    pub fn daylight() -> Self {
+        Background::Daylight(Daylight::new(45., 0., 3., Color3::new(0.3, 0.3, 0.3)))
+    }
+
+    pub fn color(&self, ray: &Ray<T>) -> Color3<T> {
+        match self {
+            Background::None => Color3::new(0.0, 0.0, 0.0),
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let unit_direction = ray.direction.to_unit();
+                let t = (unit_direction.y() + T::from_f64(1.0).unwrap()) * T::from_f64(0.5).unwrap();
+                *bottom * (T::from_f64(1.0).unwrap() - t) + *top * t
+            }
+            Background::Environment(map) => map.radiance(&ray.direction),
+            Background::Daylight(sky) => sky.radiance(&ray.direction),
+        }
+    }
+
+    /// A direction to gather light from, for backgrounds worth sampling.
+    pub fn sample_towards(&self, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        match self {
+            Background::Environment(map) => Some(map.sample(sampler).0),
+            Background::Daylight(sky) => Some(sky.sample_sun(sampler)),
+            _ => None,
+        }
+    }
+
+    /// Solid angle density of [`Self::sample_towards`] drawing the
+    /// direction of `ray`; zero for backgrounds that are not sampled.
+    pub fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        match self {
+            Background::Environment(map) => map.pdf(&ray.direction),
+            Background::Daylight(sky) => sky.sun_pdf(&ray.direction),
+            _ => T::zero(),
+        }
+    }
+
+    /// Parses `none`, `sky`, `daylight`, an `r,g,b` solid colour, six values
+    /// for a gradient from a top to a bottom colour, or the path of a `.hdr`
+    /// or `.pfm` environment map.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "none" => Ok(Background::None),
+            "sky" => Ok(Background::sky()),
+            "daylight" => Ok(Background::daylight()),
+            _ if s.ends_with(".hdr") || s.ends_with(".pfm") => {
+                let map = EnvironmentMap::load(s, 0., 1.).map_err(|e| e.to_string())?;
+                Ok(Background::Environment(Arc::new(map)))
+            }
+            _ => {
+                let values = s.split(',')
+                    .map(|v| v.trim().parse::<f64>().ok().filter(|v| v.is_finite() && *v >= 0.))
+                    .collect::<Option<Vec<f64>>>();
+                match values.as_deref() {
+                    Some(&[r, g, b]) => Ok(Background::Solid(Color3::new(r, g, b))),
+                    Some(&[tr, tg, tb, br, bg, bb]) => Ok(Background::Gradient { bottom: Color3::new(br, bg, bb), top: Color3::new(tr, tg, tb) }),
+                    _ => Err(format!("background must be `none`, `sky`, `daylight`, `r,g,b` or `r,g,b,r,g,b` (top, bottom) with non-negative values, or an .hdr/.pfm file, got `{}`", s)),
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(y: f64) -> Ray<f64> {
+        Ray { origin: Point3::new(0., 0., 0.), direction: Vec3::new(0., y, 0.), time: 0., wavelengths: None }
+    }
+
+    #[test]
+    fn gradient_ends() {
+        let sky = Background::<f64>::sky();
+        assert_eq!(sky.color(&ray(-2.)), Color3::new(1., 1., 1.));
+        assert_eq!(sky.color(&ray(3.)), Color3::new(0.5, 0.7, 1.));
+        assert_eq!(Background::None.color(&ray(1.)), Color3::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Background::<f64>::parse("none"), Ok(Background::None));
+        assert_eq!(Background::<f64>::parse("daylight"), Ok(Background::daylight()));
+        assert_eq!(Background::<f64>::parse("0.1, 0.2,3"), Ok(Background::Solid(Color3::new(0.1, 0.2, 3.))));
+        assert_eq!(Background::<f64>::parse("0.5,0.7,1, 1,1,1"), Ok(Background::sky()));
+        assert!(Background::<f64>::parse("1,2").is_err());
+        assert!(Background::<f64>::parse("1,1,1,1,1").is_err());
+        assert!(Background::<f64>::parse("-1,0,0").is_err());
+        assert!(Background::<f64>::parse("missing.hdr").unwrap_err().starts_with("missing.hdr: "));
+    }
+}
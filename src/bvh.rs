@@ -0,0 +1,345 @@
+use num::Float;
+
+use crate::aabb::*;
+use crate::common::SVecElem;
+use crate::hittable::*;
+use crate::hittable_list::*;
+use crate::ray::*;
+use crate::sampler::Sampler;
+
+type Object<'a, T> = Box<dyn Hittable<T> + 'a + Send + Sync>;
+
+const SAH_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+
+/// How a node's objects are divided between its two children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitMethod {
+    /// Binned surface area heuristic.
+    Sah,
+    /// Split at the median centroid along the widest axis.
+    Median,
+}
+
+pub enum BvhNode<'a, T: SVecElem + Float> {
+    Leaf {
+        bbox: Aabb<T>,
+        objects: HittableList<'a, T>,
+    },
+    Branch {
+        bbox: Aabb<T>,
+        left: Box<BvhNode<'a, T>>,
+        right: Box<BvhNode<'a, T>>,
+    },
+}
+
+/// Bounding volume hierarchy over a `HittableList`.
+///
+/// Objects without a bounding box (e.g. infinite planes) cannot be placed
+/// in the tree and are tested linearly alongside it.
+pub struct Bvh<'a, T: SVecElem + Float> {
+    root: Option<BvhNode<'a, T>>,
+    unbounded: HittableList<'a, T>,
+}
+
+impl<'a, T> Bvh<'a, T>
+where
+    T: 'a + SVecElem + Float,
+{
+    pub fn new(objects: HittableList<'a, T>) -> Self {
+        Self::with_split_method(objects, SplitMethod::Sah)
+    }
+
+    pub fn with_split_method(objects: HittableList<'a, T>, method: SplitMethod) -> Self {
+        let mut bounded = Vec::with_capacity(objects.len());
+        let mut unbounded = HittableList::new();
+        for object in objects {
+            match object.bounding_box() {
+                Some(bbox) => bounded.push((bbox, object)),
+                None => unbounded.push(object),
+            }
+        }
+
+        let root = if bounded.is_empty() {
+            None
+        } else {
+            Some(BvhNode::build(bounded, method))
+        };
+        Self { root, unbounded }
+    }
+}
+
+impl<'a, T> BvhNode<'a, T>
+where
+    T: 'a + SVecElem + Float,
+{
+    fn build(items: Vec<(Aabb<T>, Object<'a, T>)>, method: SplitMethod) -> Self {
+        let bbox = items.iter().skip(1).fold(items[0].0, |acc, (b, _)| surrounding_box(&acc, b));
+        if items.len() == 1 {
+            return Self::leaf(bbox, items);
+        }
+
+        let centroid_bounds = items.iter().skip(1)
+            .fold(Aabb::new(items[0].0.centroid(), items[0].0.centroid()), |acc, (b, _)| acc.grow(b.centroid()));
+        let axis = centroid_bounds.longest_axis();
+        let lo = centroid_bounds.minimum[axis];
+        let extent = centroid_bounds.maximum[axis] - lo;
+
+        if extent <= T::zero() {
+            // Every centroid coincides, so no split can separate them.
+            if items.len() <= MAX_LEAF_SIZE {
+                return Self::leaf(bbox, items);
+            }
+            return Self::split_at_median(bbox, items, axis, method);
+        }
+
+        match method {
+            SplitMethod::Median => Self::split_at_median(bbox, items, axis, method),
+            SplitMethod::Sah => {
+                let bin_of = |b: &Aabb<T>| {
+                    let rel = (b.centroid()[axis] - lo) / extent * T::from_usize(SAH_BINS).unwrap();
+                    rel.to_usize().unwrap_or(0).min(SAH_BINS - 1)
+                };
+
+                let mut counts = [0usize; SAH_BINS];
+                let mut bounds: [Option<Aabb<T>>; SAH_BINS] = [None; SAH_BINS];
+                for (b, _) in items.iter() {
+                    let i = bin_of(b);
+                    counts[i] += 1;
+                    bounds[i] = Some(bounds[i].map_or(*b, |acc| surrounding_box(&acc, b)));
+                }
+
+                // Cost of splitting after bin `i`, in units of the parent's area.
+                let mut best: Option<(usize, T)> = None;
+                for i in 0..SAH_BINS - 1 {
+                    let (n_left, area_left) = Self::bin_stats(&counts[..=i], &bounds[..=i]);
+                    let (n_right, area_right) = Self::bin_stats(&counts[i + 1..], &bounds[i + 1..]);
+                    if n_left == 0 || n_right == 0 {
+                        continue;
+                    }
+                    let cost = T::from_usize(n_left).unwrap() * area_left + T::from_usize(n_right).unwrap() * area_right;
+                    if best.is_none_or(|(_, c)| cost < c) {
+                        best = Some((i, cost));
+                    }
+                }
+
+                let leaf_cost = T::from_usize(items.len()).unwrap() * bbox.surface_area();
+                match best {
+                    Some((_, cost)) if items.len() <= MAX_LEAF_SIZE && leaf_cost <= cost => Self::leaf(bbox, items),
+                    Some((split, _)) => {
+                        let (left, right): (Vec<_>, Vec<_>) = items.into_iter().partition(|(b, _)| bin_of(b) <= split);
+                        Self::branch(bbox, left, right, method)
+                    }
+                    None => Self::split_at_median(bbox, items, axis, method),
+                }
+            }
+        }
+    }
+
+    fn bin_stats(counts: &[usize], bounds: &[Option<Aabb<T>>]) -> (usize, T) {
+        let n = counts.iter().sum();
+        let area = bounds.iter().flatten()
+            .fold(None, |acc: Option<Aabb<T>>, b| Some(acc.map_or(*b, |acc| surrounding_box(&acc, b))))
+            .map_or(T::zero(), |b| b.surface_area());
+        (n, area)
+    }
+
+    fn split_at_median(bbox: Aabb<T>, mut items: Vec<(Aabb<T>, Object<'a, T>)>, axis: usize, method: SplitMethod) -> Self {
+        let key = |b: &Aabb<T>| b.centroid()[axis].to_f64().unwrap();
+        items.sort_by(|(a, _), (b, _)| key(a).total_cmp(&key(b)));
+        let right = items.split_off(items.len() / 2);
+        Self::branch(bbox, items, right, method)
+    }
+
+    fn leaf(bbox: Aabb<T>, items: Vec<(Aabb<T>, Object<'a, T>)>) -> Self {
+        Self::Leaf {
+            bbox,
+            objects: items.into_iter().map(|(_, object)| object).collect(),
+        }
+    }
+
+    fn branch(
+        bbox: Aabb<T>,
+        left: Vec<(Aabb<T>, Object<'a, T>)>,
+        right: Vec<(Aabb<T>, Object<'a, T>)>,
+        method: SplitMethod,
+    ) -> Self {
+        Self::Branch {
+            bbox,
+            left: Box::new(Self::build(left, method)),
+            right: Box::new(Self::build(right, method)),
+        }
+    }
+
+    fn bbox(&self) -> &Aabb<T> {
+        match self {
+            Self::Leaf { bbox, .. } | Self::Branch { bbox, .. } => bbox,
+        }
+    }
+}
+
+impl<T> Hittable<T> for BvhNode<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        if !self.bbox().hit(ray, t_min, t_max) {
+            return None;
+        }
+        match self {
+            Self::Leaf { objects, .. } => objects.hit(ray, t_min, t_max),
+            Self::Branch { left, right, .. } => {
+                let hit_left = left.hit(ray, t_min, t_max);
+                let closest = hit_left.as_ref().map_or(t_max, |rec| rec.t);
+                right.hit(ray, t_min, closest).or(hit_left)
+            }
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray<T>, t_min: T, t_max: T, sampler: &mut Sampler) -> T {
+        if !self.bbox().hit(ray, t_min, t_max) {
+            return T::one();
+        }
+        match self {
+            Self::Leaf { objects, .. } => objects.transmittance(ray, t_min, t_max, sampler),
+            Self::Branch { left, right, .. } => match left.transmittance(ray, t_min, t_max, sampler) {
+                t if t <= T::zero() => T::zero(),
+                t => t * right.transmittance(ray, t_min, t_max, sampler),
+            },
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        Some(*self.bbox())
+    }
+}
+
+impl<T> Hittable<T> for Bvh<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        let hit_tree = self.root.as_ref().and_then(|root| root.hit(ray, t_min, t_max));
+        let closest = hit_tree.as_ref().map_or(t_max, |rec| rec.t);
+        self.unbounded.hit(ray, t_min, closest).or(hit_tree)
+    }
+
+    fn transmittance(&self, ray: &Ray<T>, t_min: T, t_max: T, sampler: &mut Sampler) -> T {
+        let tree = self.root.as_ref().map_or(T::one(), |root| root.transmittance(ray, t_min, t_max, sampler));
+        match tree <= T::zero() {
+            true => T::zero(),
+            false => tree * self.unbounded.transmittance(ray, t_min, t_max, sampler),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.root.as_ref().map(|root| *root.bbox())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::*;
+    use crate::vec3::*;
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use std::sync::Arc;
+
+    fn random_spheres(rng: &mut StdRng, n: usize) -> HittableList<'static, f64> {
+        let material: Arc<dyn Material<f64>> = Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5)));
+        let mut world = HittableList::new();
+        for _ in 0..n {
+            world.push(Box::new(Sphere {
+                center: Point3(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0)),
+                radius: rng.gen_range(0.05..1.5),
+                material: Arc::clone(&material),
+            }) as _);
+        }
+        world
+    }
+
+    fn assert_same_hits(method: SplitMethod, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let list = random_spheres(&mut rng, 500);
+        let bvh = Bvh::with_split_method(random_spheres(&mut StdRng::seed_from_u64(seed), 500), method);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray {
+                origin: Point3(rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0)),
+                direction: Vec3(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)),
+                time: 0.,
+                wavelengths: None,
+            };
+            let expected = list.hit(&ray, 0.001, f64::INFINITY);
+            let actual = bvh.hit(&ray, 0.001, f64::INFINITY);
+            match (expected, actual) {
+                (None, None) => {}
+                (Some(e), Some(a)) => {
+                    hits += 1;
+                    assert_eq!(e.t, a.t);
+                    assert_eq!(e.p, a.p);
+                    assert_eq!(e.normal, a.normal);
+                    assert_eq!(e.front_face, a.front_face);
+                }
+                (e, a) => panic!("list hit: {}, bvh hit: {}", e.is_some(), a.is_some()),
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn sah_matches_linear_scan() {
+        for seed in 0..4 {
+            assert_same_hits(SplitMethod::Sah, seed);
+        }
+    }
+
+    #[test]
+    fn median_matches_linear_scan() {
+        for seed in 0..4 {
+            assert_same_hits(SplitMethod::Median, seed);
+        }
+    }
+
+    /// Never hit, with a box unbounded both ways along x and so a NaN
+    /// centroid there.
+    struct EndlessSlab;
+
+    impl Hittable<f64> for EndlessSlab {
+        fn hit(&self, _ray: &Ray<f64>, _t_min: f64, _t_max: f64) -> Option<HitRecord<'_, f64>> {
+            None
+        }
+
+        fn bounding_box(&self) -> Option<Aabb<f64>> {
+            Some(Aabb::new(Point3(f64::NEG_INFINITY, -1., -1.), Point3(f64::INFINITY, 1., 1.)))
+        }
+    }
+
+    #[test]
+    fn nan_centroids() {
+        for method in [SplitMethod::Sah, SplitMethod::Median] {
+            let mut list = random_spheres(&mut StdRng::seed_from_u64(5), 20);
+            list.extend((0..8).map(|_| Box::new(EndlessSlab) as _));
+            let bvh = Bvh::with_split_method(list, method);
+            let ray = Ray { origin: Point3(0., 0., 0.), direction: Vec3(1., 0.3, 0.2), time: 0., wavelengths: None };
+            let expected = random_spheres(&mut StdRng::seed_from_u64(5), 20).hit(&ray, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(bvh.hit(&ray, 0.001, f64::INFINITY).map(|rec| rec.t), expected, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn bounding_box_covers_objects() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let list = random_spheres(&mut rng, 50);
+        let expected = list.bounding_box().unwrap();
+        let bbox = Bvh::new(list).bounding_box().unwrap();
+        assert_eq!(bbox.minimum, expected.minimum);
+        assert_eq!(bbox.maximum, expected.maximum);
+    }
+}
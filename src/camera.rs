@@ -1,8 +1,10 @@
 use num::Float;
+use rand::Rng;
 
 use crate::vec3::*;
 use crate::common::*;
 use crate::ray::*;
+use crate::sampler::Sampler;
 
 pub struct Camera<T> 
 where
@@ -15,8 +17,12 @@ where
 
     pub u: Vec3<T>,
     pub v: Vec3<T>,
-    pub w: Vec3<T>,
     pub lens_radius: T,
+
+    /// Interval over which the shutter is open; rays are spread evenly
+    /// across it.
+    pub shutter_open: T,
+    pub shutter_close: T,
 }
 
 impl<T: SVecElem + Float> Camera<T> {
@@ -57,8 +63,10 @@ impl<T: SVecElem + Float> Camera<T> {
             lower_left_corner,
             horizontal,
             vertical,
-            u, v, w,
+            u, v,
             lens_radius,
+            shutter_open: T::zero(),
+            shutter_close: T::zero(),
         }
     }
 }
@@ -68,18 +76,39 @@ impl<T> Camera<T>
 where
     T: SVecElem + Float,
 {
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray<T> {
+    /// Widens or narrows the viewport to `aspect_ratio`, keeping the
+    /// vertical field of view and the focus plane.
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f64) {
+        let two = T::from_f64(2.).unwrap();
+        let center = self.lower_left_corner + self.horizontal / two + self.vertical / two;
+        let width = self.vertical.length() * T::from_f64(aspect_ratio).unwrap();
+        self.horizontal = self.u * width;
+        self.lower_left_corner = center - self.horizontal / two - self.vertical / two;
+    }
+
+    pub fn set_shutter(&mut self, open: T, close: T) {
+        self.shutter_open = open;
+        self.shutter_close = close;
+    }
+
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut Sampler) -> Ray<T> {
         let s = T::from_f64(s).unwrap();
         let t = T::from_f64(t).unwrap();
 
-        let rd = random_in_unit_disk::<T>() * self.lens_radius;
+        let rd = random_in_unit_disk::<T>(sampler) * self.lens_radius;
         let offset = self.u * rd.x() + self.v * rd.y();
+        // Instantaneous shutters skip the draw so still renders are unchanged.
+        let time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + (self.shutter_close - self.shutter_open) * T::from_f64(sampler.gen()).unwrap()
+        } else {
+            self.shutter_open
+        };
 
-        let ray = Ray::<T> {
+        Ray::<T> {
             origin: self.origin + offset,
             direction: self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
-        };
-
-        ray
+            time,
+            wavelengths: None,
+        }
     }
 }
\ No newline at end of file
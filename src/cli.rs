@@ -0,0 +1,293 @@
+use clap::{Parser, ValueEnum};
+use num::Float;
+use std::path::PathBuf;
+
+use crate::background::Background;
+use crate::bvh::SplitMethod;
+use crate::common::{RenderSettings, SVecElem};
+use crate::exr::{ExrCompression, ExrPixelType};
+use crate::tonemap::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Precision {
+    F32,
+    F64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Binary PPM (P6)
+    Ppm,
+    /// Plain-text PPM (P3)
+    PpmAscii,
+    Png,
+    /// Radiance RGBE, linear
+    Hdr,
+    /// Portable float map, linear
+    Pfm,
+    /// OpenEXR, linear
+    Exr,
+}
+
+/// Scenes built into the binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BuiltinScene {
+    /// The "Ray Tracing in One Weekend" cover
+    Random,
+    /// The Cornell box, lit by a ceiling lamp
+    Cornell,
+}
+
+/// How the BVH divides objects between its children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BvhSplit {
+    /// Binned surface area heuristic
+    Sah,
+    /// Median centroid along the widest axis
+    Median,
+}
+
+impl From<BvhSplit> for SplitMethod {
+    fn from(split: BvhSplit) -> Self {
+        match split {
+            BvhSplit::Sah => SplitMethod::Sah,
+            BvhSplit::Median => SplitMethod::Median,
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Guesses the format from the output file extension.
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "ppm" => Some(OutputFormat::Ppm),
+            "png" => Some(OutputFormat::Png),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            "exr" => Some(OutputFormat::Exr),
+            _ => None,
+        }
+    }
+
+    /// Whether the format stores linear radiance rather than display pixels.
+    pub fn is_hdr(self) -> bool {
+        matches!(self, OutputFormat::Hdr | OutputFormat::Pfm | OutputFormat::Exr)
+    }
+}
+
+/// Renders a scene file, or the "Ray Tracing in One Weekend" cover scene.
+///
+/// Options given here override the `[render]` section of the scene file.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Args {
+    /// TOML scene description; the built-in random scene if omitted
+    #[arg(short, long)]
+    pub scene: Option<PathBuf>,
+
+    /// Built-in scene to render instead of a scene file
+    #[arg(long, value_enum, conflicts_with = "scene", default_value = "random")]
+    pub builtin: BuiltinScene,
+
+    /// Output image path, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: PathBuf,
+
+    /// Output format; guessed from the output extension if omitted
+    #[arg(short, long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Compression of OpenEXR output
+    #[arg(long, value_enum, default_value = "zip")]
+    pub exr_compression: ExrCompression,
+
+    /// Channel type of OpenEXR output
+    #[arg(long, value_enum, default_value = "half")]
+    pub exr_pixel_type: ExrPixelType,
+
+    /// Exposure adjustment in stops (8-bit output only)
+    #[arg(long, default_value_t = 0., allow_negative_numbers = true)]
+    pub exposure: f64,
+
+    /// Colour temperature in kelvin of the light to neutralise
+    #[arg(long)]
+    pub white_balance: Option<f64>,
+
+    /// Tone mapping operator (8-bit output only)
+    #[arg(long, value_enum, default_value = "none")]
+    pub tone_map: ToneMapOperator,
+
+    /// Luminance that maps to white with `reinhard-extended`
+    #[arg(long, default_value_t = 4.)]
+    pub white_point: f64,
+
+    /// Encode with a pure power law instead of the sRGB curve
+    #[arg(long)]
+    pub gamma: Option<f64>,
+
+    /// Dither before quantising to 8 bits
+    #[arg(long)]
+    pub dither: bool,
+
+    /// Background: `none`, `sky`, `daylight`, a solid `r,g,b` colour, a `r,g,b,r,g,b` top to bottom gradient or an `.hdr`/`.pfm` environment map [default: from the scene]
+    #[arg(short, long)]
+    pub background: Option<String>,
+
+    /// Image width in pixels
+    #[arg(short, long)]
+    pub width: Option<u32>,
+
+    /// Width divided by height
+    #[arg(short, long)]
+    pub aspect_ratio: Option<f64>,
+
+    /// Samples per pixel
+    #[arg(long)]
+    pub spp: Option<u32>,
+
+    /// Maximum number of bounces per path
+    #[arg(short = 'd', long)]
+    pub max_depth: Option<u32>,
+
+    /// Number of worker threads
+    #[arg(short = 'j', long)]
+    pub threads: Option<usize>,
+
+    /// Seed for the built-in random scene and for sampling
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Trace wavelengths instead of RGB, for dispersion
+    #[arg(long)]
+    pub spectral: bool,
+
+    /// How the BVH divides objects between its children
+    #[arg(long, value_enum, default_value = "sah")]
+    pub bvh_split: BvhSplit,
+
+    /// Floating point type used for rendering
+    #[arg(short, long, value_enum, default_value = "f64")]
+    pub precision: Precision,
+}
+
+impl Args {
+    /// Applies the command-line overrides on top of `settings`.
+    pub fn override_settings(&self, settings: RenderSettings) -> Result<RenderSettings, String> {
+        let settings = RenderSettings {
+            width: self.width.unwrap_or(settings.width),
+            aspect_ratio: self.aspect_ratio.unwrap_or(settings.aspect_ratio),
+            samples: self.spp.unwrap_or(settings.samples),
+            max_depth: self.max_depth.unwrap_or(settings.max_depth),
+            threads: self.threads.unwrap_or(settings.threads),
+            seed: self.seed.unwrap_or(settings.seed),
+            spectral: self.spectral || settings.spectral,
+        };
+        if settings.width < 2 || settings.height() < 2 {
+            return Err(format!("image must be at least 2x2 pixels, got {}x{}", settings.width, settings.height()));
+        }
+        if !(settings.aspect_ratio.is_finite() && settings.aspect_ratio > 0.) {
+            return Err(format!("aspect ratio must be positive, got {}", settings.aspect_ratio));
+        }
+        if settings.samples == 0 || settings.max_depth == 0 || settings.threads == 0 {
+            return Err("spp, max depth and thread count must be positive".into());
+        }
+        Ok(settings)
+    }
+
+    pub fn display_transform(&self) -> Result<DisplayTransform, String> {
+        if !self.exposure.is_finite() {
+            return Err(format!("exposure must be finite, got {}", self.exposure));
+        }
+        if let Some(kelvin) = self.white_balance {
+            if !(1667. ..=25000.).contains(&kelvin) {
+                return Err(format!("white balance must be between 1667 K and 25000 K, got {}", kelvin));
+            }
+        }
+        if self.white_point.is_nan() || self.white_point <= 0. {
+            return Err(format!("white point must be positive, got {}", self.white_point));
+        }
+        let transfer = match self.gamma {
+            Some(gamma) if gamma > 0. => TransferFunction::Gamma(gamma),
+            Some(gamma) => return Err(format!("gamma must be positive, got {}", gamma)),
+            None => TransferFunction::Srgb,
+        };
+        Ok(DisplayTransform {
+            exposure: self.exposure,
+            white_balance: self.white_balance.map(WhiteBalance::from_temperature),
+            operator: self.tone_map,
+            white_point: self.white_point,
+            transfer,
+            dither: self.dither,
+        })
+    }
+
+    pub fn background<T: SVecElem + Float>(&self) -> Result<Option<Background<T>>, String> {
+        self.background.as_deref().map(Background::parse).transpose()
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        self.format
+            .or_else(|| OutputFormat::from_path(&self.output))
+            .unwrap_or(OutputFormat::Ppm)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides() {
+        let args = Args::parse_from(["ray_tracing", "--spp", "16", "-w", "400", "-j", "2", "-p", "f32"]);
+        let settings = args.override_settings(RenderSettings::default()).unwrap();
+        assert_eq!(settings.samples, 16);
+        assert_eq!(settings.width, 400);
+        assert_eq!(settings.threads, 2);
+        assert_eq!(settings.max_depth, RenderSettings::default().max_depth);
+        assert!(!settings.spectral);
+        assert_eq!(args.precision, Precision::F32);
+        assert_eq!(args.bvh_split, BvhSplit::Sah);
+        assert_eq!(args.output_format(), OutputFormat::Ppm);
+        let args = Args::parse_from(["ray_tracing", "--spectral", "--bvh-split", "median"]);
+        assert!(args.override_settings(RenderSettings::default()).unwrap().spectral);
+        assert_eq!(SplitMethod::from(args.bvh_split), SplitMethod::Median);
+    }
+
+    #[test]
+    fn output_format() {
+        let args = Args::parse_from(["ray_tracing", "-o", "render.PNG"]);
+        assert_eq!(args.output_format(), OutputFormat::Png);
+        let args = Args::parse_from(["ray_tracing", "-o", "render.png", "-f", "ppm-ascii"]);
+        assert_eq!(args.output_format(), OutputFormat::PpmAscii);
+        let args = Args::parse_from(["ray_tracing", "-o", "render.exr"]);
+        assert!(args.output_format().is_hdr());
+    }
+
+    #[test]
+    fn display_transform() {
+        let args = Args::parse_from(["ray_tracing", "--exposure", "-1.5", "--tone-map", "aces", "--gamma", "2.2", "--dither"]);
+        let t = args.display_transform().unwrap();
+        assert_eq!(t.exposure, -1.5);
+        assert_eq!(t.operator, ToneMapOperator::Aces);
+        assert_eq!(t.transfer, TransferFunction::Gamma(2.2));
+        assert!(t.dither);
+        assert_eq!(Args::parse_from(["ray_tracing"]).display_transform().unwrap(), DisplayTransform::default());
+        assert!(Args::parse_from(["ray_tracing", "--white-balance", "100"]).display_transform().is_err());
+    }
+
+    #[test]
+    fn builtin_scene() {
+        assert_eq!(Args::parse_from(["ray_tracing"]).builtin, BuiltinScene::Random);
+        assert_eq!(Args::parse_from(["ray_tracing", "--builtin", "cornell"]).builtin, BuiltinScene::Cornell);
+        assert!(Args::try_parse_from(["ray_tracing", "--builtin", "cornell", "-s", "a.toml"]).is_err());
+    }
+
+    #[test]
+    fn invalid_overrides() {
+        let args = Args::parse_from(["ray_tracing", "--spp", "0"]);
+        assert!(args.override_settings(RenderSettings::default()).is_err());
+        let args = Args::parse_from(["ray_tracing", "-w", "1"]);
+        assert!(args.override_settings(RenderSettings::default()).is_err());
+    }
+}
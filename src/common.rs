@@ -2,9 +2,10 @@ use num::{Num, NumCast, FromPrimitive, Float, Signed, ToPrimitive};
 use rand::Rng;
 use std::ops::Neg;
 use crate::vec3::*;
+use crate::sampler::Sampler;
 
 // Type
-pub trait UVecElem: Num + ToPrimitive + FromPrimitive + NumCast + PartialOrd<Self> + Copy + Send + Sync {}
+pub trait UVecElem: Num + ToPrimitive + FromPrimitive + NumCast + PartialOrd<Self> + Copy + Send + Sync + 'static {}
 pub trait SVecElem: UVecElem + Neg<Output = Self> + Signed {}
 
 impl UVecElem for f64 {}
@@ -18,17 +19,49 @@ impl SVecElem for f32 {}
 // Image
 pub const ASPECT_RATIO: f64 = 3. / 2.0;
 pub const IMAGE_WIDTH: u32 = 1200;
-pub const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
 pub const AA_SAMPLES: u32 = 500;
+pub const MAX_DEPTH: u32 = 50;
 
 // Threads
 pub const NUM_THREADS: usize = 8;
 
-pub fn random_in_unit_sphere<T>() -> Vec3<T>
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub aspect_ratio: f64,
+    pub samples: u32,
+    pub max_depth: u32,
+    pub threads: usize,
+    /// Global seed of the per-sample random sequences.
+    pub seed: u64,
+    /// Trace wavelengths rather than RGB, converting to colour at the end.
+    pub spectral: bool,
+}
+
+impl RenderSettings {
+    pub fn height(&self) -> u32 {
+        (self.width as f64 / self.aspect_ratio) as u32
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: IMAGE_WIDTH,
+            aspect_ratio: ASPECT_RATIO,
+            samples: AA_SAMPLES,
+            max_depth: MAX_DEPTH,
+            threads: NUM_THREADS,
+            seed: 0,
+            spectral: false,
+        }
+    }
+}
+
+pub fn random_in_unit_sphere<T>(rng: &mut Sampler) -> Vec3<T>
 where
     T: SVecElem + Float,
 {
-    let mut rng = rand::thread_rng();
     let mut p: Vec3<T> = Vec3::new(1., 1., 1.);
     while dot(&p, &p) >= T::from_f64(1.0).unwrap() {
         p = Vec3::new(rng.gen::<f64>(), rng.gen::<f64>(),rng.gen::<f64>()) * T::from_f64(2.).unwrap() - Vec3::new(1., 1., 1.);
@@ -36,12 +69,10 @@ where
     p.to_unit()
 }
 
-pub fn random_in_unit_disk<T>() -> Vec3<T>
+pub fn random_in_unit_disk<T>(rng: &mut Sampler) -> Vec3<T>
 where
     T: SVecElem + Float,
 {
-    let mut rng = rand::thread_rng();
-
     loop {
         let p = Vec3::<T>::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.);
         if dot(&p, &p) < T::from_f64(1.0).unwrap() {
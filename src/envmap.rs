@@ -0,0 +1,248 @@
+use num::Float;
+use rand::Rng;
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::Path;
+
+use crate::common::SVecElem;
+use crate::image::*;
+use crate::sampler::*;
+use crate::tonemap::luminance;
+use crate::transform::*;
+use crate::vec3::*;
+
+/// Piecewise-constant density on [0, 1) with one step per entry of `func`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    /// An all-zero `func` gives the uniform density.
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len() as f64;
+        let mut cdf = vec![0.; func.len() + 1];
+        for i in 0..func.len() {
+            cdf[i + 1] = cdf[i] + func[i] / n;
+        }
+        let integral = cdf[func.len()];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0. { *c / integral } else { i as f64 / n };
+        }
+        Self { func, cdf, integral }
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Inverts the CDF at `u`, giving the point, its density and its step.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let i = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.func.len() - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0. { (u - self.cdf[i]) / width } else { 0. };
+        let x = ((i as f64 + offset.clamp(0., 1.)) / self.func.len() as f64).min(1. - f64::EPSILON);
+        (x, self.pdf(i), i)
+    }
+
+    /// Density on step `i`.
+    pub fn pdf(&self, i: usize) -> f64 {
+        match self.integral > 0. {
+            true => self.func[i] / self.integral,
+            false => 1.,
+        }
+    }
+}
+
+/// Piecewise-constant density on the unit square, given as rows of steps: a
+/// row is picked from the marginal, then a column from that row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize) -> Self {
+        let rows: Vec<_> = func.chunks(width).map(|row| Distribution1D::new(row.to_vec())).collect();
+        let marginal = Distribution1D::new(rows.iter().map(Distribution1D::integral).collect());
+        Self { rows, marginal }
+    }
+
+    /// A point `(x, y)` and its density.
+    pub fn sample(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (y, row_pdf, row) = self.marginal.sample(u.1);
+        let (x, column_pdf, _) = self.rows[row].sample(u.0);
+        ((x, y), row_pdf * column_pdf)
+    }
+
+    pub fn pdf(&self, (x, y): (f64, f64)) -> f64 {
+        let row = ((y * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        let step = &self.rows[row];
+        let column = ((x * step.func.len() as f64) as usize).min(step.func.len() - 1);
+        self.marginal.pdf(row) * step.pdf(column)
+    }
+}
+
+/// Radiance from all directions, read from an equirectangular (latitude
+/// longitude) image whose centre faces -z with +y at the top row.
+///
+/// Directions are drawn in proportion to texel luminance times the solid
+/// angle the texel covers, so small bright features such as the sun are
+/// found by light sampling.
+#[derive(Clone, PartialEq)]
+pub struct EnvironmentMap<T: SVecElem> {
+    width: usize,
+    height: usize,
+    /// Radiance, already scaled by the intensity, top row first.
+    texels: Vec<Color3<T>>,
+    /// From map space to world space.
+    rotation: Transform<T>,
+    distribution: Distribution2D,
+}
+
+impl<T: SVecElem + Float> EnvironmentMap<T> {
+    /// `rotation` turns the map about +y, in degrees.
+    pub fn new(image: &HdrImage, rotation: f64, intensity: f64) -> Self {
+        let (width, height) = (image.width as usize, image.height as usize);
+        let texels: Vec<Color3<T>> = image.pixels.iter()
+            .map(|&Color3(r, g, b)| Color3::new(r as f64 * intensity, g as f64 * intensity, b as f64 * intensity))
+            .collect();
+        let func: Vec<f64> = texels.iter().enumerate()
+            .map(|(i, c)| {
+                let theta = PI * ((i / width) as f64 + 0.5) / height as f64;
+                let c = [c.x(), c.y(), c.z()].map(|v| v.to_f64().unwrap().max(0.));
+                luminance(c) * theta.sin()
+            })
+            .collect();
+        let rotation = Transform::rotate_y(T::from_f64(rotation).unwrap());
+        Self { width, height, texels, rotation, distribution: Distribution2D::new(&func, width) }
+    }
+
+    pub fn load(path: impl AsRef<Path>, rotation: f64, intensity: f64) -> Result<Self, ImageError> {
+        Ok(Self::new(&read_hdr_image(path)?, rotation, intensity))
+    }
+
+    /// Image coordinates of `direction`, both in [0, 1] from the top left.
+    fn uv_of(&self, direction: &Vec3<T>) -> (f64, f64) {
+        let d = self.rotation.inverted().vector(direction).to_unit();
+        let [x, y, z] = [d.x(), d.y(), d.z()].map(|v| v.to_f64().unwrap());
+        let u = 0.5 + x.atan2(-z) / (2. * PI);
+        (u.rem_euclid(1.), y.clamp(-1., 1.).acos() / PI)
+    }
+
+    fn direction_at(&self, (u, v): (f64, f64)) -> Vec3<T> {
+        let (phi, theta) = (2. * PI * (u - 0.5), PI * v);
+        let d = Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+        self.rotation.vector(&d)
+    }
+
+    /// Radiance arriving from `direction`, the nearest texel's.
+    pub fn radiance(&self, direction: &Vec3<T>) -> Color3<T> {
+        let (u, v) = self.uv_of(direction);
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.texels[y * self.width + x]
+    }
+
+    /// A unit direction drawn by radiance, with its solid angle density.
+    pub fn sample(&self, sampler: &mut Sampler) -> (Vec3<T>, T) {
+        let (uv, pdf) = self.distribution.sample((sampler.gen(), sampler.gen()));
+        (self.direction_at(uv), T::from_f64(solid_angle_pdf(pdf, uv.1)).unwrap())
+    }
+
+    /// Solid angle density of [`Self::sample`] drawing `direction`.
+    pub fn pdf(&self, direction: &Vec3<T>) -> T {
+        let uv = self.uv_of(direction);
+        T::from_f64(solid_angle_pdf(self.distribution.pdf(uv), uv.1)).unwrap()
+    }
+}
+
+/// Converts a density over the image to one over directions: a texel row
+/// at polar angle θ covers 2π² sin θ steradians per unit area.
+fn solid_angle_pdf(pdf: f64, v: f64) -> f64 {
+    let sin_theta = (PI * v).sin();
+    match sin_theta > 0. {
+        true => pdf / (2. * PI * PI * sin_theta),
+        false => 0.,
+    }
+}
+
+impl<T: SVecElem> fmt::Debug for EnvironmentMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EnvironmentMap({}x{})", self.width, self.height)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An 8x4 map lit dimly all round, with one bright texel.
+    fn sun_map(rotation: f64) -> EnvironmentMap<f64> {
+        let mut pixels = vec![Color3(0.1, 0.2, 0.3); 32];
+        pixels[8 + 5] = Color3(500., 400., 300.);
+        EnvironmentMap::new(&HdrImage { width: 8, height: 4, pixels }, rotation, 2.)
+    }
+
+    #[test]
+    fn distribution() {
+        let d = Distribution1D::new(vec![0., 3., 1.]);
+        assert_eq!(d.integral(), 4. / 3.);
+        assert_eq!(d.sample(0.), (1. / 3., 2.25, 1));
+        assert_eq!(d.sample(0.375), (0.5, 2.25, 1));
+        let (x, pdf, i) = d.sample(0.875);
+        assert!((x - 5. / 6.).abs() < 1e-12 && pdf == 0.75 && i == 2);
+        assert_eq!(Distribution1D::new(vec![0., 0.]).sample(0.75), (0.75, 1., 1));
+
+        let d = Distribution2D::new(&[1., 0., 1., 2.], 2);
+        let ((x, y), pdf) = d.sample((0.5, 0.5));
+        assert!((x - 0.625).abs() < 1e-12 && (y - 2. / 3.).abs() < 1e-12);
+        assert!((pdf - 2.).abs() < 1e-12 && d.pdf((x, y)) == pdf);
+        assert_eq!(d.pdf((0.9, 0.1)), 0.);
+    }
+
+    #[test]
+    fn mapping() {
+        let map = sun_map(0.);
+        let (u, v) = map.uv_of(&Vec3::new(0., 0., -1.));
+        assert!((u - 0.5).abs() < 1e-12 && (v - 0.5).abs() < 1e-12);
+        assert_eq!(map.uv_of(&Vec3::new(0., 1., 0.)).1, 0.);
+        for uv in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+            let (u, v) = map.uv_of(&map.direction_at(uv));
+            assert!((u - uv.0).abs() < 1e-9 && (v - uv.1).abs() < 1e-9, "{:?}", uv);
+        }
+        // Turning the map a quarter turn brings what was at +x round to -z.
+        let (u, v) = sun_map(90.).uv_of(&Vec3::new(0., 0., -1.));
+        assert!((u - 0.75).abs() < 1e-9 && (v - 0.5).abs() < 1e-9);
+        let sun = map.direction_at((5.5 / 8., 1.5 / 4.));
+        assert_eq!(map.radiance(&sun), Color3::new(1000., 800., 600.));
+        assert_eq!(sun_map(90.).radiance(&Transform::rotate_y(90.).vector(&sun)), map.radiance(&sun));
+    }
+
+    #[test]
+    fn sampling() {
+        // The estimate of the total power must be exact up to noise, and
+        // the sun must take most of the samples.
+        let map = sun_map(30.);
+        let mut sampler = Sampler::new(1, 0);
+        let n = 20000;
+        let (mut estimate, mut sun) = (0., 0);
+        for _ in 0..n {
+            let (direction, pdf) = map.sample(&mut sampler);
+            assert!((direction.length() - 1.).abs() < 1e-9);
+            assert!((pdf - map.pdf(&direction)).abs() < 1e-9 * pdf);
+            let radiance = map.radiance(&direction);
+            estimate += radiance.y() / pdf / n as f64;
+            sun += (radiance.x() == 1000.) as u32;
+        }
+        // Texel rows are θ bands a quarter of π wide.
+        let band = |row: f64| 2. * PI / 8. * ((PI * row / 4.).cos() - (PI * (row + 1.) / 4.).cos());
+        let expected = 0.4 * (4. * PI - band(1.)) + 800. * band(1.);
+        assert!((estimate - expected).abs() < 0.01 * expected, "{} vs {}", estimate, expected);
+        assert!(sun as f64 > 0.95 * n as f64);
+    }
+}
@@ -0,0 +1,262 @@
+use clap::ValueEnum;
+use std::io::{self, Write};
+
+use crate::image::*;
+use crate::vec3::*;
+use crate::zlib::*;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExrCompression {
+    None,
+    /// zlib over blocks of 16 scanlines, after OpenEXR's byte reordering
+    /// and delta predictor.
+    Zip,
+}
+
+impl ExrCompression {
+    fn id(self) -> u8 {
+        match self {
+            ExrCompression::None => 0,
+            ExrCompression::Zip => 3,
+        }
+    }
+
+    fn lines_per_block(self) -> usize {
+        match self {
+            ExrCompression::None => 1,
+            ExrCompression::Zip => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+impl ExrPixelType {
+    fn id(self) -> i32 {
+        match self {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        }
+    }
+}
+
+/// Single-part scanline OpenEXR with R, G and B channels.
+pub struct Exr {
+    pub compression: ExrCompression,
+    pub pixel_type: ExrPixelType,
+}
+
+/// Rounds an `f32` to the nearest IEEE 754 half, ties to even.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exp == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    let (half, rest, shift) = if e <= 0 {
+        if e < -10 {
+            return sign;
+        }
+        // Subnormal half: make the implicit bit explicit and shift it in.
+        let m = mantissa | 0x80_0000;
+        let shift = (14 - e) as u32;
+        (m >> shift, m & ((1 << shift) - 1), shift)
+    } else {
+        (((e as u32) << 10) | (mantissa >> 13), mantissa & 0x1fff, 13)
+    };
+    let halfway = 1 << (shift - 1);
+    // A carry out of the mantissa correctly bumps the exponent.
+    let rounded = if rest > halfway || (rest == halfway && half & 1 == 1) { half + 1 } else { half };
+    sign | rounded as u16
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(width: u32, height: u32) -> Vec<u8> {
+    [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// OpenEXR's ZIP preprocessing: split even and odd bytes, then delta-encode.
+fn zip_predict(data: &[u8]) -> Vec<u8> {
+    let mut reordered: Vec<u8> = data.iter().step_by(2).copied().collect();
+    reordered.extend(data.iter().skip(1).step_by(2));
+    let mut previous = reordered.first().copied().unwrap_or(0);
+    for byte in reordered.iter_mut().skip(1) {
+        let current = *byte;
+        *byte = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+    reordered
+}
+
+impl Exr {
+    fn header(&self, width: u32, height: u32) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&2u32.to_le_bytes());
+
+        // Channels must be listed in alphabetical order.
+        let mut channels = Vec::new();
+        for name in ["B", "G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&self.pixel_type.id().to_le_bytes());
+            channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+            channels.extend_from_slice(&1i32.to_le_bytes());
+            channels.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channels.push(0);
+
+        attribute(&mut header, "channels", "chlist", &channels);
+        attribute(&mut header, "compression", "compression", &[self.compression.id()]);
+        attribute(&mut header, "dataWindow", "box2i", &box2i(width, height));
+        attribute(&mut header, "displayWindow", "box2i", &box2i(width, height));
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0u8; 8]);
+        attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+        header.push(0);
+        header
+    }
+
+    fn scanline(&self, row: &[Color3<f32>], out: &mut Vec<u8>) {
+        let channels: [fn(&Color3<f32>) -> f32; 3] = [|c| c.2, |c| c.1, |c| c.0];
+        for channel in channels {
+            for c in row {
+                match self.pixel_type {
+                    ExrPixelType::Half => out.extend_from_slice(&f32_to_f16(channel(c)).to_le_bytes()),
+                    ExrPixelType::Float => out.extend_from_slice(&channel(c).to_le_bytes()),
+                }
+            }
+        }
+    }
+}
+
+impl HdrImageWriter for Exr {
+    fn write(&self, out: &mut dyn Write, pixels: &[Color3<f32>], width: u32, height: u32) -> io::Result<()> {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        let header = self.header(width, height);
+        let lines_per_block = self.compression.lines_per_block();
+
+        let mut chunks = Vec::new();
+        for (i, rows) in pixels.chunks(width as usize * lines_per_block).enumerate() {
+            let mut data = Vec::new();
+            for row in rows.chunks(width as usize) {
+                self.scanline(row, &mut data);
+            }
+            if self.compression == ExrCompression::Zip {
+                let compressed = compress(&zip_predict(&data));
+                // Readers take a block as raw data when it did not shrink.
+                if compressed.len() < data.len() {
+                    data = compressed;
+                }
+            }
+            let mut chunk = Vec::with_capacity(data.len() + 8);
+            chunk.extend_from_slice(&((i * lines_per_block) as i32).to_le_bytes());
+            chunk.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            chunk.extend_from_slice(&data);
+            chunks.push(chunk);
+        }
+
+        let mut offset = (header.len() + chunks.len() * 8) as u64;
+        out.write_all(&header)?;
+        for chunk in chunks.iter() {
+            out.write_all(&offset.to_le_bytes())?;
+            offset += chunk.len() as u64;
+        }
+        for chunk in chunks.iter() {
+            out.write_all(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_conversion() {
+        assert_eq!(f32_to_f16(0.), 0x0000);
+        assert_eq!(f32_to_f16(-0.), 0x8000);
+        assert_eq!(f32_to_f16(1.), 0x3c00);
+        assert_eq!(f32_to_f16(-2.), 0xc000);
+        assert_eq!(f32_to_f16(0.1), 0x2e66);
+        assert_eq!(f32_to_f16(65504.), 0x7bff);
+        assert_eq!(f32_to_f16(65520.), 0x7c00);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7c00, 0x7c00);
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(2f32.powi(-14)), 0x0400);
+        assert_eq!(f32_to_f16(2f32.powi(-26)), 0x0000);
+    }
+
+    #[test]
+    fn zip_predictor() {
+        assert_eq!(zip_predict(&[1, 10, 2, 20, 4, 40]), [1, 129, 130, 134, 138, 148]);
+    }
+
+    #[test]
+    fn uncompressed_layout() {
+        let exr = Exr { compression: ExrCompression::None, pixel_type: ExrPixelType::Float };
+        let pixels = [Color3(1., 2., 3.), Color3(4., 5., 6.)];
+        let mut out = Vec::new();
+        exr.write(&mut out, &pixels, 1, 2).unwrap();
+
+        let header = exr.header(1, 2);
+        assert_eq!(&out[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        assert_eq!(&out[..header.len()], header);
+
+        let table = &out[header.len()..header.len() + 16];
+        let first = u64::from_le_bytes(table[..8].try_into().unwrap()) as usize;
+        let second = u64::from_le_bytes(table[8..].try_into().unwrap()) as usize;
+        assert_eq!(first, header.len() + 16);
+        assert_eq!(second, first + 8 + 12);
+
+        let floats = |bytes: &[u8]| bytes.chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect::<Vec<_>>();
+        assert_eq!(&out[first..first + 8], [0, 0, 0, 0, 12, 0, 0, 0]);
+        assert_eq!(floats(&out[first + 8..second]), [3., 2., 1.]);
+        assert_eq!(&out[second..second + 8], [1, 0, 0, 0, 12, 0, 0, 0]);
+        assert_eq!(floats(&out[second + 8..]), [6., 5., 4.]);
+    }
+
+    #[test]
+    fn zip_blocks() {
+        let exr = Exr { compression: ExrCompression::Zip, pixel_type: ExrPixelType::Half };
+        let pixels = vec![Color3(0.5, 0.25, 0.125); 64 * 40];
+        let mut out = Vec::new();
+        exr.write(&mut out, &pixels, 64, 40).unwrap();
+
+        let header_len = exr.header(64, 40).len();
+        // 40 lines in blocks of 16 gives three chunks, each much smaller than raw.
+        let offsets: Vec<usize> = out[header_len..header_len + 24].chunks(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize).collect();
+        let ys: Vec<i32> = offsets.iter().map(|&o| i32::from_le_bytes(out[o..o + 4].try_into().unwrap())).collect();
+        assert_eq!(ys, [0, 16, 32]);
+        let size = i32::from_le_bytes(out[offsets[0] + 4..offsets[0] + 8].try_into().unwrap()) as usize;
+        assert!(size < 16 * 64 * 3 * 2 / 10);
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::{self, Write};
+
+use crate::image::*;
+use crate::vec3::*;
+
+/// Shared-exponent RGBE encoding used by Radiance `.hdr` files.
+pub fn to_rgbe(c: Color3<f32>) -> [u8; 4] {
+    let Color3(r, g, b) = c;
+    let (r, g, b) = (r.max(0.), g.max(0.), b.max(0.));
+    let v = r.max(g).max(b);
+    if v.is_nan() || v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    let v = v.min(f32::MAX);
+    // frexp: v = mantissa * 2^exponent with mantissa in [0.5, 1).
+    let bits = v.to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32 - 126;
+    let mantissa = f32::from_bits((bits & 0x807f_ffff) | (126 << 23));
+    let scale = mantissa * 256. / v;
+    [(r * scale) as u8, (g * scale) as u8, (b * scale) as u8, (exponent + 128) as u8]
+}
+
+/// Inverse of [`to_rgbe`].
+pub fn from_rgbe([r, g, b, e]: [u8; 4]) -> Color3<f32> {
+    if e == 0 {
+        return Color3(0., 0., 0.);
+    }
+    let scale = 2f32.powi(e as i32 - 136);
+    Color3(r as f32 * scale, g as f32 * scale, b as f32 * scale)
+}
+
+/// Decodes a Radiance `.hdr` file in the usual `-Y h +X w` orientation, with
+/// flat or run-length encoded scanlines.
+pub fn decode_hdr(bytes: &[u8]) -> Result<HdrImage, String> {
+    if !bytes.starts_with(b"#?") {
+        return Err("missing Radiance signature".into());
+    }
+    // Header lines up to a blank one, then the resolution line.
+    let mut pos = 0;
+    let mut line = || {
+        let end = bytes[pos..].iter().position(|&b| b == b'\n').ok_or("truncated Radiance header")?;
+        let text = String::from_utf8_lossy(&bytes[pos..pos + end]).into_owned();
+        pos += end + 1;
+        Ok::<_, String>(text)
+    };
+    loop {
+        let text = line()?;
+        if text.is_empty() {
+            break;
+        }
+        if let Some(format) = text.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported Radiance format `{}`", format));
+            }
+        }
+    }
+    let resolution = line()?;
+    let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", h, "+X", w] => (w.parse::<u32>().ok(), h.parse::<u32>().ok()),
+        _ => (None, None),
+    };
+    let (Some(width), Some(height)) = (width, height) else {
+        return Err(format!("unsupported Radiance resolution `{}`", resolution));
+    };
+    if width == 0 || height == 0 {
+        return Err(format!("empty {}x{} Radiance image", width, height));
+    }
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for _ in 0..height {
+        let rle = (8..0x8000).contains(&width)
+            && bytes.get(pos..pos + 4) == Some(&[2, 2, (width >> 8) as u8, width as u8][..]);
+        if rle {
+            pos += 4;
+            // Each channel is stored separately as runs and literal spans.
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < scanline.len() {
+                    let count = *bytes.get(pos).ok_or("truncated Radiance scanline")? as usize;
+                    let (count, run) = if count > 128 { (count - 128, true) } else { (count, false) };
+                    if count == 0 || x + count > scanline.len() {
+                        return Err("bad run length in Radiance scanline".into());
+                    }
+                    let span = bytes.get(pos + 1..pos + 1 + if run { 1 } else { count }).ok_or("truncated Radiance scanline")?;
+                    for i in 0..count {
+                        scanline[x + i][channel] = if run { span[0] } else { span[i] };
+                    }
+                    pos += 1 + span.len();
+                    x += count;
+                }
+            }
+        } else {
+            let flat = bytes.get(pos..pos + scanline.len() * 4).ok_or("truncated Radiance scanline")?;
+            for (texel, rgbe) in scanline.iter_mut().zip(flat.chunks(4)) {
+                texel.copy_from_slice(rgbe);
+            }
+            pos += flat.len();
+        }
+        pixels.extend(scanline.iter().map(|&rgbe| from_rgbe(rgbe)));
+    }
+    Ok(HdrImage { width, height, pixels })
+}
+
+/// Decodes a colour (`PF`) or greyscale (`Pf`) portable float map of either
+/// byte order.
+pub fn decode_pfm(bytes: &[u8]) -> Result<HdrImage, String> {
+    let mut pos = 0;
+    let channels = match pfm_token(bytes, &mut pos) {
+        "PF" => 3,
+        "Pf" => 1,
+        magic => return Err(format!("unsupported PFM variant `{}`", magic)),
+    };
+    let width = pfm_token(bytes, &mut pos).parse::<u32>();
+    let height = pfm_token(bytes, &mut pos).parse::<u32>();
+    let scale = pfm_token(bytes, &mut pos).parse::<f32>();
+    let (Ok(width), Ok(height), Ok(scale)) = (width, height, scale) else {
+        return Err("invalid PFM header".into());
+    };
+    if width == 0 || height == 0 || scale == 0. || !scale.is_finite() {
+        return Err(format!("invalid PFM header: {}x{}, scale {}", width, height, scale));
+    }
+
+    // A single whitespace byte separates the header from the raster.
+    let count = width as usize * height as usize * channels;
+    let raster = bytes.get(pos + 1..pos + 1 + count * 4).ok_or("truncated PFM raster")?;
+    let floats: Vec<f32> = raster.chunks(4)
+        .map(|b| {
+            let b = b.try_into().unwrap();
+            if scale < 0. { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }
+        })
+        .collect();
+    let mut pixels = Vec::with_capacity(count / channels);
+    for row in floats.chunks(width as usize * channels).rev() {
+        pixels.extend(row.chunks(channels).map(|c| match *c {
+            [r, g, b] => Color3(r, g, b),
+            [v] => Color3(v, v, v),
+            _ => unreachable!(),
+        }));
+    }
+    Ok(HdrImage { width, height, pixels })
+}
+
+/// Next whitespace-separated PFM header token, empty at the end of the data.
+fn pfm_token<'b>(bytes: &'b [u8], pos: &mut usize) -> &'b str {
+    while bytes.get(*pos).is_some_and(|b| b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    std::str::from_utf8(&bytes[start..*pos]).unwrap_or("")
+}
+
+/// Radiance RGBE (`.hdr`) with flat, non-RLE scanlines.
+pub struct RadianceHdr;
+
+impl HdrImageWriter for RadianceHdr {
+    fn write(&self, out: &mut dyn Write, pixels: &[Color3<f32>], width: u32, height: u32) -> io::Result<()> {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+        let bytes: Vec<u8> = pixels.iter().flat_map(|&c| to_rgbe(c)).collect();
+        out.write_all(&bytes)
+    }
+}
+
+/// Portable float map (`.pfm`), little endian, rows stored bottom up.
+pub struct Pfm;
+
+impl HdrImageWriter for Pfm {
+    fn write(&self, out: &mut dyn Write, pixels: &[Color3<f32>], width: u32, height: u32) -> io::Result<()> {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+        let mut bytes = Vec::with_capacity(pixels.len() * 12);
+        for row in pixels.chunks(width as usize).rev() {
+            for &Color3(r, g, b) in row {
+                bytes.extend_from_slice(&r.to_le_bytes());
+                bytes.extend_from_slice(&g.to_le_bytes());
+                bytes.extend_from_slice(&b.to_le_bytes());
+            }
+        }
+        out.write_all(&bytes)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgbe() {
+        assert_eq!(to_rgbe(Color3(0., 0., 0.)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(Color3(1., 0.5, 0.25)), [128, 64, 32, 129]);
+        assert_eq!(to_rgbe(Color3(1000., 0., -1.)), [250, 0, 0, 138]);
+        assert_eq!(to_rgbe(Color3(0.75, 0.75, 0.75)), [192, 192, 192, 128]);
+    }
+
+    #[test]
+    fn radiance_header() {
+        let mut out = Vec::new();
+        RadianceHdr.write(&mut out, &[Color3(1., 0.5, 0.25), Color3(0., 0., 0.)], 2, 1).unwrap();
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..], [128, 64, 32, 129, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn decode_radiance() {
+        let pixels = [Color3(1., 0.5, 0.25), Color3(0., 0., 0.), Color3(3., 2., 1.), Color3(0.125, 0.125, 0.125)];
+        let mut out = Vec::new();
+        RadianceHdr.write(&mut out, &pixels, 2, 2).unwrap();
+        let image = decode_hdr(&out).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixels, pixels);
+
+        // One run-length encoded scanline of eight pixels: a run of red,
+        // green and blue mantissas and a literal span of exponents.
+        let mut rle = b"#?RADIANCE\n\n-Y 1 +X 8\n\x02\x02\x00\x08".to_vec();
+        rle.extend_from_slice(&[136, 128, 136, 64, 136, 0, 8, 129, 129, 129, 129, 128, 128, 128, 128]);
+        let image = decode_hdr(&rle).unwrap();
+        assert_eq!(image.pixels[0], Color3(1., 0.5, 0.));
+        assert_eq!(image.pixels[7], Color3(0.5, 0.25, 0.));
+
+        assert!(decode_hdr(b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n\0\0\0\0").is_err());
+        assert!(decode_hdr(b"#?RADIANCE\n\n+Y 1 +X 1\n\0\0\0\0").is_err());
+        assert!(decode_hdr(&out[..out.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn decode_float_map() {
+        let pixels = [Color3(1., 2., 3.), Color3(4., 5., 6.)];
+        let mut out = Vec::new();
+        Pfm.write(&mut out, &pixels, 1, 2).unwrap();
+        assert_eq!(decode_pfm(&out).unwrap().pixels, pixels);
+
+        // Big endian greyscale.
+        let mut grey = b"Pf\n2 1\n1.0\n".to_vec();
+        grey.extend_from_slice(&[0.5f32.to_be_bytes(), 2f32.to_be_bytes()].concat());
+        assert_eq!(decode_pfm(&grey).unwrap().pixels, [Color3(0.5, 0.5, 0.5), Color3(2., 2., 2.)]);
+        assert!(decode_pfm(&grey[..grey.len() - 1]).is_err());
+        assert!(decode_pfm(b"PF\n1 1\n0\n").is_err());
+    }
+
+    #[test]
+    fn pfm_rows_bottom_up() {
+        let mut out = Vec::new();
+        Pfm.write(&mut out, &[Color3(1., 2., 3.), Color3(4., 5., 6.)], 1, 2).unwrap();
+        let header = b"PF\n1 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        let floats: Vec<f32> = out[header.len()..].chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        assert_eq!(floats, [4., 5., 6., 1., 2., 3.]);
+    }
+}
@@ -2,23 +2,105 @@ use crate::common::SVecElem;
 use crate::vec3::*;
 use crate::ray::*;
 use crate::materials::*;
+use crate::aabb::*;
+use crate::sampler::Sampler;
 
 use std::sync::Arc;
 use num::Float;
+use rand::Rng;
 
 pub struct HitRecord<'a, T: SVecElem> {
     pub p: Point3<T>,
     pub normal: Vec3<T>,
     pub material: Arc<dyn Material<T> + 'a>,
     pub t: T,
+    pub u: T,
+    pub v: T,
     pub front_face: bool,
 }
 
+/// Nearest ray parameter counted as a hit, so a ray does not hit the
+/// surface it leaves.
+pub const RAY_EPSILON: f64 = 0.001;
+
 pub trait Hittable<T>
 where
     T: SVecElem,
 {
-    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<T>>;
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>>;
+
+    /// Box enclosing the object, or `None` if it is unbounded.
+    fn bounding_box(&self) -> Option<Aabb<T>>;
+
+    /// Direction from `origin` towards a random point of the object, for
+    /// sampling it as a light; `None` if it cannot be sampled.
+    fn sample_towards(&self, _origin: &Point3<T>, _time: T, _sampler: &mut Sampler) -> Option<Vec3<T>> {
+        None
+    }
+
+    /// Solid-angle density with which `sample_towards` picks the direction
+    /// of `ray` from its origin; zero where the ray misses.
+    fn pdf_towards(&self, _ray: &Ray<T>) -> T {
+        T::zero()
+    }
+
+    /// Fraction of light along `ray` between `t_min` and `t_max` that gets
+    /// through the object, for shadow rays: none if the ray hits it. Media
+    /// return an unbiased estimate instead, so light fades through them.
+    fn transmittance(&self, ray: &Ray<T>, t_min: T, t_max: T, _sampler: &mut Sampler) -> T {
+        match self.hit(ray, t_min, t_max) {
+            Some(_) => T::zero(),
+            None => T::one(),
+        }
+    }
+}
+
+impl<T, H> Hittable<T> for Arc<H>
+where
+    T: SVecElem,
+    H: Hittable<T> + ?Sized,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        self.as_ref().hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        self.as_ref().bounding_box()
+    }
+
+    fn sample_towards(&self, origin: &Point3<T>, time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        self.as_ref().sample_towards(origin, time, sampler)
+    }
+
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        self.as_ref().pdf_towards(ray)
+    }
+
+    fn transmittance(&self, ray: &Ray<T>, t_min: T, t_max: T, sampler: &mut Sampler) -> T {
+        self.as_ref().transmittance(ray, t_min, t_max, sampler)
+    }
+}
+
+/// Solid-angle density of directions to uniform samples on a surface of
+/// `area`, for a sample seen at `distance` along `ray` with the unit
+/// surface `normal`.
+pub fn area_to_solid_angle<T: SVecElem + Float>(ray: &Ray<T>, distance: T, normal: &Vec3<T>, area: T) -> T {
+    let length = ray.direction.length();
+    let cosine = dot(&ray.direction, normal).abs() / length;
+    if cosine <= T::zero() {
+        return T::zero();
+    }
+    let distance = distance * length;
+    distance * distance / (cosine * area)
+}
+
+/// Longitude/latitude coordinates of a point on the unit sphere: `u` goes
+/// round from -x through +z, +x and -z, `v` from the bottom pole to the top.
+pub fn sphere_uv<T: SVecElem + Float>(p: &Point3<T>) -> (T, T) {
+    let pi = T::from_f64(std::f64::consts::PI).unwrap();
+    let theta = (-p.y()).max(-T::one()).min(T::one()).acos();
+    let phi = (-p.z()).atan2(p.x()) + pi;
+    (phi / (pi + pi), theta / pi)
 }
 
 pub struct Sphere<'a, T: SVecElem + Float> {
@@ -27,41 +109,235 @@ pub struct Sphere<'a, T: SVecElem + Float> {
     pub material: Arc<dyn Material<T> + 'a>
 }
 
-impl<T> Hittable<T> for Sphere<'_, T>
+/// Intersection with the sphere of `radius` around `center`.
+fn hit_sphere<'a, T>(center: Point3<T>, radius: T, material: &Arc<dyn Material<T> + 'a>, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'a, T>>
 where
     T: SVecElem + Float,
 {
-    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<T>> {
-        let oc = ray.origin - self.center;
-        let a = dot(&ray.direction, &ray.direction);
-        let half_b = dot(&oc, &ray.direction);
-        let c = dot(&oc, &oc) - self.radius * self.radius;
-
-        let discriminant: T = half_b * half_b - a * c;
-        if discriminant < T::from_f64(0.).unwrap() {
+    let oc = ray.origin - center;
+    let a = dot(&ray.direction, &ray.direction);
+    let half_b = dot(&oc, &ray.direction);
+    let c = dot(&oc, &oc) - radius * radius;
+
+    let discriminant: T = half_b * half_b - a * c;
+    if discriminant < T::from_f64(0.).unwrap() {
+        return None;
+    }
+    let sqrtd = discriminant.sqrt();
+
+    let mut root = (-half_b - sqrtd) / a;
+    if root < t_min || t_max < root {
+        root = (-half_b + sqrtd) / a;
+        if root < t_min || t_max < root {
             return None;
         }
-        let sqrtd = discriminant.sqrt();
+    }
+    let p = ray.at(root);
+    let mut normal = (p - center) / radius;
+    let (u, v) = sphere_uv(&normal);
+    let front_face = dot(&ray.direction, &normal) < T::from_f64(0.).unwrap();
 
-        let mut root = (-half_b - sqrtd) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + sqrtd) / a;
-            if root < t_min || t_max < root {
-                return None;
-            }
+    if !front_face { normal = - normal; }
+
+    Some(HitRecord {
+        t: root,
+        u,
+        v,
+        p,
+        material: Arc::clone(material),
+        normal,
+        front_face,
+    })
+}
+
+impl<T> Hittable<T> for Sphere<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        hit_sphere(self.center, self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        let r = self.radius.abs();
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+
+    fn sample_towards(&self, origin: &Point3<T>, _time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        Some(sample_sphere_cone(self.center, self.radius, origin, sampler))
+    }
+
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        if self.hit(ray, T::from_f64(RAY_EPSILON).unwrap(), T::infinity()).is_none() {
+            return T::zero();
         }
-        let p = ray.at(root);
-        let mut normal = (p - self.center) / self.radius;
-        let front_face = dot(&ray.direction, &normal) < T::from_f64(0.).unwrap();
+        sphere_cone_pdf(self.center, self.radius, &ray.origin)
+    }
+}
+
+/// Cosine of the half-angle the sphere fills seen from `origin`; -1 from
+/// inside, where it surrounds every direction.
+fn cos_theta_max<T: SVecElem + Float>(center: Point3<T>, radius: T, origin: &Point3<T>) -> T {
+    let r = radius.abs();
+    let d2 = dot(&(center - *origin), &(center - *origin));
+    if d2 <= r * r {
+        return -T::one();
+    }
+    (T::one() - r * r / d2).sqrt()
+}
+
+/// Uniform over the cone a sphere fills as seen from `origin`, or over all
+/// directions from inside it.
+fn sample_sphere_cone<T: SVecElem + Float>(center: Point3<T>, radius: T, origin: &Point3<T>, sampler: &mut Sampler) -> Vec3<T> {
+    let one = T::one();
+    let cos_max = cos_theta_max(center, radius, origin);
+    let cos_theta = one - T::from_f64(sampler.gen()).unwrap() * (one - cos_max);
+    let sin_theta = (one - cos_theta * cos_theta).max(T::zero()).sqrt();
+    let phi = T::from_f64(sampler.gen_range(0.0..2. * std::f64::consts::PI)).unwrap();
+    let axis = (center - *origin).to_unit();
+    let axis = if axis.length() > T::zero() { axis } else { Vec3(T::zero(), T::zero(), one) };
+    let (t, b) = orthonormal_basis(&axis);
+    axis * cos_theta + (t * phi.cos() + b * phi.sin()) * sin_theta
+}
 
-        if !front_face { normal = - normal; }
+/// Density of `sample_sphere_cone` picking any direction that meets the
+/// sphere.
+fn sphere_cone_pdf<T: SVecElem + Float>(center: Point3<T>, radius: T, origin: &Point3<T>) -> T {
+    let solid_angle = T::from_f64(2. * std::f64::consts::PI).unwrap() * (T::one() - cos_theta_max(center, radius, origin));
+    T::one() / solid_angle
+}
 
-        Some(HitRecord {
-            t: root,
-            p,
-            material: Arc::clone(&self.material),
-            normal,
-            front_face,
-        })
+/// Sphere moving in a straight line from `center0` at `time0` to `center1`
+/// at `time1`, and resting at those ends outside that interval.
+pub struct MovingSphere<'a, T: SVecElem + Float> {
+    pub center0: Point3<T>,
+    pub center1: Point3<T>,
+    pub time0: T,
+    pub time1: T,
+    pub radius: T,
+    pub material: Arc<dyn Material<T> + 'a>
+}
+
+impl<T: SVecElem + Float> MovingSphere<'_, T> {
+    pub fn center(&self, time: T) -> Point3<T> {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+        let s = ((time - self.time0) / (self.time1 - self.time0)).max(T::zero()).min(T::one());
+        self.center0 + (self.center1 - self.center0) * s
     }
 }
+
+impl<T> Hittable<T> for MovingSphere<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        hit_sphere(self.center(ray.time), self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    /// Covers the whole path, and so every shutter interval.
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        let r = self.radius.abs();
+        let start = Aabb::new(self.center0 - r, self.center0 + r);
+        let end = Aabb::new(self.center1 - r, self.center1 + r);
+        Some(surrounding_box(&start, &end))
+    }
+
+    /// As for a `Sphere` standing where this one is at `time`.
+    fn sample_towards(&self, origin: &Point3<T>, time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        Some(sample_sphere_cone(self.center(time), self.radius, origin, sampler))
+    }
+
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        if self.hit(ray, T::from_f64(RAY_EPSILON).unwrap(), T::infinity()).is_none() {
+            return T::zero();
+        }
+        sphere_cone_pdf(self.center(ray.time), self.radius, &ray.origin)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_uvs() {
+        let uv = |x, y, z| sphere_uv(&Point3::<f64>::new(x, y, z));
+        let close = |(u, v): (f64, f64), (eu, ev): (f64, f64)| (u - eu).abs() < 1e-12 && (v - ev).abs() < 1e-12;
+        assert!(close(uv(1., 0., 0.), (0.5, 0.5)));
+        assert!(close(uv(0., 1., 0.), (0.5, 1.)));
+        assert!(close(uv(0., -1., 0.), (0.5, 0.)));
+        assert!(close(uv(-1., 0., 0.), (0., 0.5)));
+        assert!(close(uv(0., 0., 1.), (0.25, 0.5)));
+        assert!(close(uv(0., 0., -1.), (0.75, 0.5)));
+    }
+
+    #[test]
+    fn sphere_hit_fills_uv() {
+        let sphere = Sphere { center: Point3::new(0., 0., -3.), radius: 2., material: Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5))) as _ };
+        let ray = Ray { origin: Point3::new(0., 0., 0.), direction: Vec3::new(0., 0., -1.), time: 0., wavelengths: None };
+        let hit = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.u - 0.25).abs() < 1e-12 && (hit.v - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn moving_sphere() {
+        let sphere = MovingSphere {
+            center0: Point3(0., 0., -3.),
+            center1: Point3(4., 0., -3.),
+            time0: 0.,
+            time1: 1.,
+            radius: 1.,
+            material: Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5))) as _,
+        };
+        let ray = |x, time| Ray { origin: Point3(x, 0., 0.), direction: Vec3(0., 0., -1.), time, wavelengths: None };
+        assert!(sphere.hit(&ray(0., 0.), 0.001, f64::INFINITY).is_some());
+        assert!(sphere.hit(&ray(0., 1.), 0.001, f64::INFINITY).is_none());
+        let hit = sphere.hit(&ray(2., 0.5), 0.001, f64::INFINITY).unwrap();
+        assert!(hit.p.is_close(Point3(2., 0., -2.)));
+        // Held at the ends outside the keyframes, so the box stays valid.
+        assert_eq!(sphere.center(-1.), sphere.center0);
+        assert_eq!(sphere.center(2.), sphere.center1);
+
+        let bbox = sphere.bounding_box().unwrap();
+        assert_eq!((bbox.minimum, bbox.maximum), (Point3(-1., -1., -4.), Point3(5., 1., -2.)));
+    }
+
+    /// Monte Carlo estimate of the solid angle `light` covers from `origin`,
+    /// checking every sample against `pdf_towards`.
+    pub fn sampled_solid_angle(light: &dyn Hittable<f64>, origin: Point3<f64>) -> f64 {
+        let mut sampler = Sampler::new(11, 12);
+        let n = 20000;
+        (0..n)
+            .map(|_| {
+                let direction = light.sample_towards(&origin, 0., &mut sampler).unwrap();
+                let pdf = light.pdf_towards(&Ray { origin, direction: direction * 3., time: 0., wavelengths: None });
+                assert!(pdf > 0., "sampled direction {:?} has zero density", direction);
+                1. / pdf
+            })
+            .sum::<f64>() / n as f64
+    }
+
+    #[test]
+    fn sphere_light_sampling() {
+        let sphere = Sphere { center: Point3(0., 0., -4.), radius: 2., material: Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5))) as _ };
+        // The cone has half-angle 30 degrees.
+        let expected = 2. * std::f64::consts::PI * (1. - 0.75f64.sqrt());
+        let estimate = sampled_solid_angle(&sphere, Point3(0., 0., 0.));
+        assert!((estimate - expected).abs() < 1e-9, "{} vs {}", estimate, expected);
+        assert_eq!(sphere.pdf_towards(&Ray { origin: Point3(0., 0., 0.), direction: Vec3(0., 1., 0.), time: 0., wavelengths: None }), 0.);
+
+        let inside = sampled_solid_angle(&sphere, Point3(0.5, 0., -4.));
+        assert!((inside - 4. * std::f64::consts::PI).abs() < 1e-9);
+
+        // Sampled where it is at the ray's time, here the start.
+        let moving = MovingSphere { center0: sphere.center, center1: Point3(10., 0., -4.), time0: 0., time1: 1., radius: 2., material: Arc::clone(&sphere.material) };
+        let estimate = sampled_solid_angle(&moving, Point3(0., 0., 0.));
+        assert!((estimate - expected).abs() < 1e-9, "{} vs {}", estimate, expected);
+        let ahead = |time| Ray { origin: Point3(0., 0., 0.), direction: Vec3(0., 0., -1.), time, wavelengths: None };
+        assert!(moving.pdf_towards(&ahead(0.)) > 0.);
+        assert_eq!(moving.pdf_towards(&ahead(1.)), 0.);
+    }
+}
+
@@ -1,15 +1,21 @@
 use crate::common::SVecElem;
 use crate::ray::*;
 use crate::hittable::*;
+use crate::aabb::*;
+use crate::sampler::Sampler;
+use crate::vec3::*;
+
+use num::Float;
+use rand::Rng;
 
 
 pub type HittableList<'a, T> = Vec<Box<dyn Hittable<T> + 'a + Send + Sync>>;
 
 impl<T> Hittable<T> for HittableList<'_, T>
 where
-    T: SVecElem,
+    T: SVecElem + Float,
 {
-    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<T>> {
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
         let mut closest_so_far = t_max;
         let mut result = None;
 
@@ -21,4 +27,40 @@ where
         }
         result
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        let mut objects = self.iter();
+        let first = objects.next()?.bounding_box()?;
+        objects.try_fold(first, |acc, object| {
+            object.bounding_box().map(|bbox| surrounding_box(&acc, &bbox))
+        })
+    }
+
+    /// Picks one of the objects uniformly and samples it.
+    fn sample_towards(&self, origin: &Point3<T>, time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        if self.is_empty() {
+            return None;
+        }
+        self[sampler.gen_range(0..self.len())].sample_towards(origin, time, sampler)
+    }
+
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        if self.is_empty() {
+            return T::zero();
+        }
+        let sum = self.iter().fold(T::zero(), |sum, object| sum + object.pdf_towards(ray));
+        sum / T::from_usize(self.len()).unwrap()
+    }
+
+    /// The product over the objects, stopping at the first that blocks.
+    fn transmittance(&self, ray: &Ray<T>, t_min: T, t_max: T, sampler: &mut Sampler) -> T {
+        let mut transmittance = T::one();
+        for object in self {
+            transmittance = transmittance * object.transmittance(ray, t_min, t_max, sampler);
+            if transmittance <= T::zero() {
+                return T::zero();
+            }
+        }
+        transmittance
+    }
+}
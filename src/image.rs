@@ -1,39 +1,268 @@
-use num::{Float, clamp};
+use num::Float;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
 use crate::vec3::*;
 use crate::common::*;
+use crate::tonemap::*;
+use crate::hdr::{decode_hdr, decode_pfm};
+use crate::png::decode_png;
 
 pub type Image<T> = Box<Vec<Color3<T>>>;
 
-pub fn float_to_rgb_vec<T>(r: T, g: T, b: T) -> Color3<u8>
+/// Averages an accumulated radiance buffer and runs it through the display
+/// transform to get 8-bit pixels.
+pub fn to_rgb8<T>(image: &[Color3<T>], samples: u32, transform: &DisplayTransform) -> Vec<Color3<u8>>
 where
     T: SVecElem + Float,
 {
-    let scale = T::from_f64(1.0 / AA_SAMPLES as f64).unwrap();
-    let clamp_min = T::from_f64(0.0).unwrap();
-    let clamp_max = T::from_f64(0.9999).unwrap();
+    let scale = 1.0 / samples as f64;
+    image.iter().enumerate()
+        .map(|(i, c)| {
+            let c = [c.x(), c.y(), c.z()].map(|v| v.to_f64().unwrap() * scale);
+            transform.quantize(c, i)
+        })
+        .collect()
+}
 
-    let r = clamp((scale * r).sqrt(), clamp_min, clamp_max) * T::from_i32(256).unwrap();
-    let g = clamp((scale * g).sqrt(), clamp_min, clamp_max) * T::from_i32(256).unwrap();
-    let b = clamp((scale * b).sqrt(), clamp_min, clamp_max) * T::from_i32(256).unwrap();
+/// Encoder for 8-bit RGB images stored top row first.
+pub trait ImageWriter {
+    fn write(&self, out: &mut dyn Write, pixels: &[Color3<u8>], width: u32, height: u32) -> io::Result<()>;
 
-    Color3(r.to_u8().unwrap(), g.to_u8().unwrap(), b.to_u8().unwrap())
+    fn write_to_path(&self, path: &Path, pixels: &[Color3<u8>], width: u32, height: u32) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out, pixels, width, height)?;
+        out.flush()
+    }
 }
 
-pub fn write_color<T>(pixel_color: Color3<T>)
+/// Averages an accumulated radiance buffer into linear `f32` pixels,
+/// without any clamping or display transform.
+pub fn to_linear_f32<T>(image: &[Color3<T>], samples: u32) -> Vec<Color3<f32>>
 where
     T: SVecElem + Float,
 {
-    let Color3(r, g, b) = float_to_rgb_vec(pixel_color.x(), pixel_color.y(), pixel_color.z());
-    println!("{} {} {}", r, g, b);
+    let scale = 1.0 / samples as f64;
+    image.iter()
+        .map(|c| Color3::new(c.x().to_f64().unwrap() * scale, c.y().to_f64().unwrap() * scale, c.z().to_f64().unwrap() * scale))
+        .collect()
 }
 
+/// Encoder for linear floating point RGB images stored top row first.
+pub trait HdrImageWriter {
+    fn write(&self, out: &mut dyn Write, pixels: &[Color3<f32>], width: u32, height: u32) -> io::Result<()>;
 
-pub fn write_image<T>(image: &[Color3<T>]) 
-where 
-    T: SVecElem + Float,
-{
-    for pixel_color in image.iter() {
-        write_color(*pixel_color);
+    fn write_to_path(&self, path: &Path, pixels: &[Color3<f32>], width: u32, height: u32) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out, pixels, width, height)?;
+        out.flush()
+    }
+}
+
+/// 8-bit RGB pixels read from a file, top row first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color3<u8>>,
+}
+
+/// Linear floating point RGB pixels read from a file, top row first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color3<f32>>,
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io { path: PathBuf, source: io::Error },
+    Decode { path: PathBuf, message: String },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ImageError::Decode { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Reads a PPM (P3 or P6) or PNG file, recognised by its magic bytes.
+pub fn read_image(path: impl AsRef<Path>) -> Result<RgbImage, ImageError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|source| ImageError::Io { path: path.to_owned(), source })?;
+    let decoded = match bytes.get(..2) {
+        Some(b"P3") | Some(b"P6") => decode_ppm(&bytes),
+        Some(&[0x89, b'P']) => decode_png(&bytes),
+        _ => Err("unknown image format, expected PPM or PNG".into()),
+    };
+    decoded.map_err(|message| ImageError::Decode { path: path.to_owned(), message })
+}
+
+/// Reads a Radiance `.hdr` or PFM file, recognised by its magic bytes.
+pub fn read_hdr_image(path: impl AsRef<Path>) -> Result<HdrImage, ImageError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|source| ImageError::Io { path: path.to_owned(), source })?;
+    let decoded = match bytes.get(..2) {
+        Some(b"#?") => decode_hdr(&bytes),
+        Some(b"PF") | Some(b"Pf") => decode_pfm(&bytes),
+        _ => Err("unknown image format, expected Radiance HDR or PFM".into()),
+    };
+    decoded.map_err(|message| ImageError::Decode { path: path.to_owned(), message })
+}
+
+/// Next whitespace-separated PPM header token, skipping `#` comments.
+fn ppm_token<'b>(bytes: &'b [u8], pos: &mut usize) -> Result<&'b [u8], String> {
+    loop {
+        match bytes.get(*pos) {
+            Some(b'#') => while bytes.get(*pos).is_some_and(|&b| b != b'\n') { *pos += 1 },
+            Some(b) if b.is_ascii_whitespace() => *pos += 1,
+            Some(_) => break,
+            None => return Err("unexpected end of PPM data".into()),
+        }
+    }
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    Ok(&bytes[start..*pos])
+}
+
+fn ppm_number(bytes: &[u8], pos: &mut usize, what: &str) -> Result<u32, String> {
+    let t = ppm_token(bytes, pos)?;
+    std::str::from_utf8(t).ok()
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| format!("invalid PPM {} `{}`", what, String::from_utf8_lossy(t)))
+}
+
+/// Decodes plain (P3) or binary (P6) PPM, rescaling other maxvals to 255.
+pub fn decode_ppm(bytes: &[u8]) -> Result<RgbImage, String> {
+    let mut pos = 0;
+    let magic = ppm_token(bytes, &mut pos)?;
+    if magic != b"P3" && magic != b"P6" {
+        return Err(format!("unsupported PPM variant `{}`", String::from_utf8_lossy(magic)));
+    }
+    let width = ppm_number(bytes, &mut pos, "width")?;
+    let height = ppm_number(bytes, &mut pos, "height")?;
+    let maxval = ppm_number(bytes, &mut pos, "maxval")?;
+    if width == 0 || height == 0 {
+        return Err(format!("empty {}x{} PPM image", width, height));
+    }
+    if maxval == 0 || maxval > 65535 {
+        return Err(format!("PPM maxval must be in 1..=65535, got {}", maxval));
     }
-}
\ No newline at end of file
+
+    let count = width as usize * height as usize * 3;
+    let samples: Vec<u32> = if magic == b"P6" {
+        // Exactly one whitespace byte separates the header from the raster.
+        pos += 1;
+        let size = if maxval < 256 { 1 } else { 2 };
+        let raster = bytes.get(pos..pos + count * size).ok_or("truncated PPM raster")?;
+        if size == 1 {
+            raster.iter().map(|&b| b as u32).collect()
+        } else {
+            raster.chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as u32).collect()
+        }
+    } else {
+        (0..count).map(|_| ppm_number(bytes, &mut pos, "sample")).collect::<Result<_, _>>()?
+    };
+    if samples.iter().any(|&s| s > maxval) {
+        return Err(format!("PPM sample above maxval {}", maxval));
+    }
+
+    let scale = |s: u32| ((s * 255 + maxval / 2) / maxval) as u8;
+    let pixels = samples.chunks(3).map(|c| Color3(scale(c[0]), scale(c[1]), scale(c[2]))).collect();
+    Ok(RgbImage { width, height, pixels })
+}
+
+/// Plain-text PPM (P3).
+pub struct PpmAscii;
+
+impl ImageWriter for PpmAscii {
+    fn write(&self, out: &mut dyn Write, pixels: &[Color3<u8>], width: u32, height: u32) -> io::Result<()> {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        writeln!(out, "P3")?;
+        writeln!(out, "{} {}", width, height)?;
+        writeln!(out, "255")?;
+        for Color3(r, g, b) in pixels.iter() {
+            writeln!(out, "{} {} {}", r, g, b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Binary PPM (P6).
+pub struct PpmBinary;
+
+impl ImageWriter for PpmBinary {
+    fn write(&self, out: &mut dyn Write, pixels: &[Color3<u8>], width: u32, height: u32) -> io::Result<()> {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        write!(out, "P6\n{} {}\n255\n", width, height)?;
+        let bytes: Vec<u8> = pixels.iter().flat_map(|&Color3(r, g, b)| [r, g, b]).collect();
+        out.write_all(&bytes)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiny_image() -> Vec<Color3<u8>> {
+        vec![Color3(255, 0, 0), Color3(0, 255, 0), Color3(0, 0, 255), Color3(1, 2, 3)]
+    }
+
+    #[test]
+    fn ppm_ascii() {
+        let mut out = Vec::new();
+        PpmAscii.write(&mut out, &tiny_image(), 2, 2).unwrap();
+        assert_eq!(out, b"P3\n2 2\n255\n255 0 0\n0 255 0\n0 0 255\n1 2 3\n");
+    }
+
+    #[test]
+    fn ppm_binary() {
+        let mut out = Vec::new();
+        PpmBinary.write(&mut out, &tiny_image(), 2, 2).unwrap();
+        assert_eq!(out, b"P6\n2 2\n255\n\xff\x00\x00\x00\xff\x00\x00\x00\xff\x01\x02\x03");
+    }
+
+    #[test]
+    fn quantise() {
+        let image = [Color3(0.0, 0.25, 4.0), Color3(0.0, 1.0, 0.5)];
+        let pixels = to_rgb8(&image, 4, &DisplayTransform::default());
+        assert_eq!(pixels, vec![Color3(0, 71, 255), Color3(0, 137, 99)]);
+
+        let legacy = DisplayTransform { transfer: TransferFunction::Gamma(2.), ..Default::default() };
+        assert_eq!(to_rgb8(&image, 4, &legacy), vec![Color3(0, 64, 255), Color3(0, 128, 90)]);
+    }
+
+    #[test]
+    fn decode_ppm_roundtrip() {
+        let mut out = Vec::new();
+        PpmBinary.write(&mut out, &tiny_image(), 2, 2).unwrap();
+        let image = decode_ppm(&out).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixels, tiny_image());
+
+        let mut out = Vec::new();
+        PpmAscii.write(&mut out, &tiny_image(), 1, 4).unwrap();
+        assert_eq!(decode_ppm(&out).unwrap().pixels, tiny_image());
+    }
+
+    #[test]
+    fn decode_ppm_header() {
+        let image = decode_ppm(b"P3 # comment\n1 1 # size\n15\n15 0 7\n").unwrap();
+        assert_eq!(image.pixels, [Color3(255, 0, 119)]);
+        let image = decode_ppm(b"P6 1 1 65535\n\xff\xff\x00\x00\x80\x00").unwrap();
+        assert_eq!(image.pixels, [Color3(255, 0, 128)]);
+        assert!(decode_ppm(b"P6 2 2 255\n\x00\x00").is_err());
+        assert!(decode_ppm(b"P3 1 1 255 0 0 256").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use num::Float;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::common::SVecElem;
+use crate::hittable::*;
+use crate::ray::*;
+use crate::sampler::Sampler;
+use crate::transform::*;
+use crate::vec3::*;
+
+/// A shared object placed in the world by an affine transform, so many
+/// copies can reuse one set of geometry. With a `motion` the object is
+/// first placed by `transform` and then moved along the keyframes by each
+/// ray's time.
+pub struct Instance<'a, T: SVecElem + Float> {
+    pub object: Arc<dyn Hittable<T> + 'a + Send + Sync>,
+    pub transform: Transform<T>,
+    pub motion: Option<Motion<T>>,
+    bbox: Option<Aabb<T>>,
+}
+
+impl<'a, T: SVecElem + Float> Instance<'a, T> {
+    pub fn new(object: Arc<dyn Hittable<T> + 'a + Send + Sync>, transform: Transform<T>) -> Self {
+        let bbox = object.bounding_box().map(|bbox| transform.bbox(&bbox));
+        Self { object, transform, motion: None, bbox }
+    }
+
+    /// The bounding box covers the whole motion, so any shutter interval.
+    pub fn keyframed(object: Arc<dyn Hittable<T> + 'a + Send + Sync>, transform: Transform<T>, motion: Motion<T>) -> Self {
+        let bbox = object.bounding_box().map(|bbox| motion.bbox(&transform.bbox(&bbox)));
+        Self { object, transform, motion: Some(motion), bbox }
+    }
+
+    /// Placement of the object at `time`.
+    fn transform_at(&self, time: T) -> Transform<T> {
+        match &self.motion {
+            Some(motion) => self.transform.then(&motion.at(time)),
+            None => self.transform,
+        }
+    }
+}
+
+impl<T> Hittable<T> for Instance<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        let transform = self.transform_at(ray.time);
+        // The direction is left unnormalised so `t` means the same in both spaces.
+        let local = Ray {
+            origin: transform.inverse.mul_point(&ray.origin),
+            direction: transform.inverse.mul_vector(&ray.direction),
+            time: ray.time,
+            wavelengths: ray.wavelengths,
+        };
+        let mut rec = self.object.hit(&local, t_min, t_max)?;
+        rec.p = transform.point(&rec.p);
+        rec.normal = transform.normal(&rec.normal).to_unit();
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        self.bbox
+    }
+
+    fn sample_towards(&self, origin: &Point3<T>, time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        let transform = self.transform_at(time);
+        let local = self.object.sample_towards(&transform.inverse.mul_point(origin), time, sampler)?;
+        Some(transform.vector(&local))
+    }
+
+    /// The object's density, rescaled for how the transform stretches
+    /// directions: by |det M| |M⁻¹ω|³ for the unit world direction ω.
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        let transform = self.transform_at(ray.time);
+        let local = Ray {
+            origin: transform.inverse.mul_point(&ray.origin),
+            direction: transform.inverse.mul_vector(&ray.direction),
+            time: ray.time,
+            wavelengths: ray.wavelengths,
+        };
+        let pdf = self.object.pdf_towards(&local);
+        if pdf <= T::zero() {
+            return T::zero();
+        }
+        let stretch = local.direction.length() / ray.direction.length();
+        pdf / (transform.matrix.linear_determinant().abs() * stretch * stretch * stretch)
+    }
+
+    fn transmittance(&self, ray: &Ray<T>, t_min: T, t_max: T, sampler: &mut Sampler) -> T {
+        let transform = self.transform_at(ray.time);
+        let local = Ray {
+            origin: transform.inverse.mul_point(&ray.origin),
+            direction: transform.inverse.mul_vector(&ray.direction),
+            time: ray.time,
+            wavelengths: ray.wavelengths,
+        };
+        self.object.transmittance(&local, t_min, t_max, sampler)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::*;
+    use crate::hittable::tests::sampled_solid_angle;
+    use crate::quad::*;
+
+    fn gray() -> Arc<dyn Material<f64>> {
+        Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn scaled_sphere() {
+        let sphere = Arc::new(Sphere { center: Point3(0., 0., 0.), radius: 1., material: gray() });
+        let t = Transform::scale(Vec3(1., 1., 2.)).then(&Transform::translate(Vec3(0., 0., -10.)));
+        let ellipsoid = Instance::new(sphere, t);
+
+        let ray = Ray { origin: Point3(0., 0., 0.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let hit = ellipsoid.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.t - 8.).abs() < 1e-12);
+        assert!(hit.p.is_close(Point3(0., 0., -8.)));
+        assert!(hit.normal.is_close(Vec3(0., 0., 1.)));
+        assert!(hit.front_face);
+
+        // Off-axis the normal follows the stretched surface, not the sphere's.
+        let ray = Ray { origin: Point3(0.6, 0., 0.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let hit = ellipsoid.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let expected = Vec3(0.6, 0., (hit.p.z() + 10.) / 4.).to_unit();
+        assert!(hit.normal.is_close(expected));
+
+        let bbox = ellipsoid.bounding_box().unwrap();
+        assert!(bbox.minimum.is_close(Point3(-1., -1., -12.)));
+        assert!(bbox.maximum.is_close(Point3(1., 1., -8.)));
+    }
+
+    #[test]
+    fn rotated_box_is_shared() {
+        let unit = Arc::new(Cuboid::new(Point3(0., 0., 0.), Point3(1., 1., 1.), gray()));
+        let copies: Vec<_> = (0..3)
+            .map(|i| Instance::new(unit.clone(), Transform::rotate_y(45.).then(&Transform::translate(Vec3(3. * i as f64, 0., 0.)))))
+            .collect();
+        assert_eq!(Arc::strong_count(&unit), 4);
+
+        // The cube's +z face turns to face (1, 0, 1) and meets the ray along -x.
+        let h = 0.5f64.sqrt();
+        let ray = Ray { origin: Point3(10., 0.5, 0.2), direction: Vec3(-1., 0., 0.), time: 0., wavelengths: None };
+        let hit = copies[2].hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(hit.p.is_close(Point3(6. + 2. * h - 0.2, 0.5, 0.2)));
+        assert!(hit.normal.is_close(Vec3(h, 0., h)));
+        assert!(hit.front_face);
+        assert!(copies[0].hit(&ray, 0.001, 5.).is_none());
+    }
+
+    #[test]
+    fn keyframed_instance() {
+        let sphere = Arc::new(Sphere { center: Point3(0., 0., 0.), radius: 1., material: gray() });
+        let end = Keyframe { translation: Vec3(10., 0., 0.), ..Keyframe::new(1.) };
+        let motion = Motion::new(vec![Keyframe::new(0.), end]).unwrap();
+        let moving = Instance::keyframed(sphere, Transform::translate(Vec3(0., 0., -5.)), motion);
+
+        let ray = |time| Ray { origin: Point3(7.5, 0., 0.), direction: Vec3(0., 0., -1.), time, wavelengths: None };
+        assert!(moving.hit(&ray(0.), 0.001, f64::INFINITY).is_none());
+        let hit = moving.hit(&ray(0.75), 0.001, f64::INFINITY).unwrap();
+        assert!(hit.p.is_close(Point3(7.5, 0., -4.)));
+        assert!(hit.normal.is_close(Vec3(0., 0., 1.)));
+
+        let bbox = moving.bounding_box().unwrap();
+        assert!(bbox.minimum.is_close(Point3(-1., -1., -6.)) && bbox.maximum.is_close(Point3(11., 1., -4.)));
+    }
+
+    #[test]
+    fn light_sampling_through_transform() {
+        // A unit square sheared, stretched and turned into place is sampled
+        // with the same densities as the quad it becomes.
+        let square = Arc::new(Quad::new(Point3(0., 0., 0.), Vec3(1., 0., 0.), Vec3(0., 1., 0.), gray()));
+        let shear = Mat4::affine([[1., 0.5, 0.], [0., 1., 0.], [0., 0., 1.]], Vec3(0., 0., 0.));
+        let t = Transform::from_matrix(shear).unwrap()
+            .then(&Transform::scale(Vec3(2., 3., 1.)))
+            .then(&Transform::rotate_x(30.))
+            .then(&Transform::translate(Vec3(-1., 0.5, -4.)));
+        let placed = Instance::new(square, t);
+        let direct = Quad::new(t.point(&Point3(0., 0., 0.)), t.vector(&Vec3(1., 0., 0.)), t.vector(&Vec3(0., 1., 0.)), gray());
+
+        let origin = Point3(0.3, -0.2, 1.);
+        for (u, v) in [(0.5, 0.5), (0.1, 0.9), (0.8, 0.3)] {
+            let ray = Ray { origin, direction: t.point(&Point3(u, v, 0.)) - origin, time: 0., wavelengths: None };
+            let (a, b) = (placed.pdf_towards(&ray), direct.pdf_towards(&ray));
+            assert!(b > 0. && (a - b).abs() < 1e-9 * b, "{} vs {}", a, b);
+        }
+        let expected = sampled_solid_angle(&direct, origin);
+        let estimate = sampled_solid_angle(&placed, origin);
+        assert!((estimate - expected).abs() < 0.02 * expected, "{} vs {}", estimate, expected);
+    }
+}
@@ -1,3 +1,4 @@
+
 mod common;
 mod vec3;
 mod camera;
@@ -6,83 +7,70 @@ mod hittable;
 mod hittable_list;
 mod image;
 mod materials;
+mod microfacet;
+mod principled;
+mod texture;
+mod perlin;
+mod zlib;
+mod png;
+mod hdr;
+mod exr;
+mod tonemap;
+mod aabb;
+mod bvh;
+mod triangle;
+mod quad;
+mod transform;
+mod instance;
+mod medium;
+mod volume;
+mod obj;
+mod scene;
+mod cli;
+mod background;
+mod envmap;
+mod sky;
+mod render;
+mod sampler;
+mod spectrum;
 
 use common::*;
 use num::Float;
 use vec3::*;
 use camera::*;
-use ray::*;
 use hittable_list::*;
 use hittable::*;
+use quad::*;
+use transform::*;
+use instance::*;
 use image::*;
+use png::*;
+use hdr::*;
+use exr::*;
 use materials::*;
+use bvh::*;
+use scene::*;
+use cli::*;
+use background::*;
+use render::*;
 
-use std::f64::INFINITY;
-use threadpool::ThreadPool;
-use std::sync::{mpsc, Arc, Mutex};
-use rand::Rng;
-
-
-fn ray_color<T>(ray: Ray<T>, world: &HittableList<T>, depth: u32) -> Color3<T>
-where
-    T: SVecElem + Float,
-{   
-    if depth == 0 {
-        return Color3::new(0.0, 0.0, 0.0);
-    }
-    let t = world.hit(&ray, T::from_f64(0.001).unwrap(), T::from_f64(INFINITY).unwrap());
-    match t {
-        Some(hit) => {
-            let hit_result = hit.material.scatter(&ray, &hit);
-            match hit_result {
-                Some((attenuation, scattered)) => {
-                    attenuation * ray_color(scattered, world, depth - 1)
-                },
-            None => Color3::new(0.0, 0.0, 0.0)
-            }
-        },
-        None => {
-            let unit_direction = ray.direction.to_unit();
-            let t = (unit_direction.y() + T::from_f64(1.0).unwrap()) * T::from_f64(0.5).unwrap();
-            Color3::new(1.0, 1.0, 1.0) * (T::from_f64(1.0).unwrap() - t) + Color3::new(0.5, 0.7, 1.0) * t
-        }
-    }
-}
-
-fn render_single<T> (world: &HittableList<T>, cam: &Camera<T>) -> Image<T>
-where
-    T: SVecElem + Float,
-{   
-    let length = (IMAGE_WIDTH * IMAGE_HEIGHT) as usize;
-    let mut image = Vec::with_capacity(length);
-    const MAX_BOUNCE: u32 = 50;
-
-    let mut rng = rand::thread_rng();
-
-    for j in (0..IMAGE_HEIGHT).rev() {
-        for i in 0..IMAGE_WIDTH {
-            let u = (i as f64 + rng.gen_range(-0.5..0.5)) / (IMAGE_WIDTH - 1) as f64;
-            let v = (j as f64 + rng.gen_range(-0.5..0.5)) / (IMAGE_HEIGHT - 1) as f64;
-            
-            let ray = cam.get_ray(u, v);
-
-            let pixel_color = ray_color(ray, &world, MAX_BOUNCE);
-            image.push(pixel_color);
-        }
-    }
-    Box::new(image)
-}
+use std::sync::Arc;
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::Path;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use clap::Parser;
 
 
 macro_rules! create_material {
     ("lambertian", $generic:ty, ($r:expr, $g:expr, $b:expr)) => {
-        Arc::new(Lambertian::<$generic> {albedo: Color3::new($r, $g, $b)})
+        Arc::new(Lambertian::<$generic>::new(Color3::new($r, $g, $b)))
     };
     ("metal", $generic:ty, ($r:expr, $g:expr, $b:expr), $f:expr) => {
-        Arc::new(Metal::<$generic> {albedo: Color3::new($r, $g, $b), fuzz: <$generic>::from_f64($f).unwrap()})
+        Arc::new(Metal::<$generic>::new(Color3::new($r, $g, $b), <$generic>::from_f64($f).unwrap()))
     };
     ("dielectric", $generic:ty, $ir:expr) => {
-        Arc::new(Dielectric::<$generic> {ir: <$generic>::from_f64($ir).unwrap()})
+        Arc::new(Dielectric::<$generic>::new(<$generic>::from_f64($ir).unwrap()))
     }
 }
 
@@ -96,12 +84,13 @@ macro_rules! create_object {
     };
 }
 
-fn random_scene<'a, T>() -> HittableList<'a, T>
+/// The book's final scene, laid out the same way for the same `seed`.
+fn random_scene<'a, T>(seed: u64) -> HittableList<'a, T>
 where
     T: 'a + SVecElem + Float,
 {
-    let mut rng = rand::thread_rng();
-    
+    let mut rng = StdRng::seed_from_u64(seed);
+
     let mut world = HittableList::<T>::new();
 
     let ground_material = create_material!("lambertian", T, (0.5, 0.5, 0.5));
@@ -142,56 +131,117 @@ where
     world
 }
 
+/// The Cornell box from "Ray Tracing: The Next Week", with two turned white
+/// boxes standing on the floor.
+fn cornell_box<'a, T>() -> Scene<'a, T>
+where
+    T: 'a + SVecElem + Float,
+{
+    let red = create_material!("lambertian", T, (0.65, 0.05, 0.05));
+    let white = create_material!("lambertian", T, (0.73, 0.73, 0.73));
+    let green = create_material!("lambertian", T, (0.12, 0.45, 0.15));
+    let light = Arc::new(DiffuseLight::<T> { emit: Color3::new(15., 15., 15.) });
+    let lamp = Arc::new(Quad::new(Point3::new(343., 554., 332.), Vec3::new(-130., 0., 0.), Vec3::new(0., 0., -105.), light));
+
+    let p = |x, y, z| Point3::<T>::new(x, y, z);
+    let turned_box = |size: Point3<T>, degrees: f64, offset: Point3<T>| {
+        let cuboid = Arc::new(Cuboid::new(p(0., 0., 0.), size, white.clone()));
+        let place = Transform::rotate_y(T::from_f64(degrees).unwrap()).then(&Transform::translate(offset));
+        Box::new(Instance::new(cuboid, place))
+    };
+    let world: HittableList<T> = vec![
+        Box::new(Quad::new(p(555., 0., 0.), p(0., 555., 0.), p(0., 0., 555.), green)) as _,
+        Box::new(Quad::new(p(0., 0., 0.), p(0., 555., 0.), p(0., 0., 555.), red)) as _,
+        Box::new(Arc::clone(&lamp)) as _,
+        Box::new(Quad::new(p(0., 0., 0.), p(555., 0., 0.), p(0., 0., 555.), white.clone())) as _,
+        Box::new(Quad::new(p(555., 555., 555.), p(-555., 0., 0.), p(0., 0., -555.), white.clone())) as _,
+        Box::new(Quad::new(p(0., 0., 555.), p(555., 0., 0.), p(0., 555., 0.), white.clone())) as _,
+        turned_box(p(165., 330., 165.), 15., p(265., 0., 295.)) as _,
+        turned_box(p(165., 165., 165.), -18., p(130., 0., 65.)) as _,
+    ];
+
+    Scene {
+        world,
+        lights: vec![Box::new(lamp) as _],
+        camera: Camera::new((278., 278., -800.), (278., 278., 0.), (0., 1., 0.), 40., 1., 0., 10.),
+        settings: RenderSettings { width: 600, aspect_ratio: 1., samples: 200, max_depth: 50, ..Default::default() },
+        background: Background::None,
+    }
+}
+
 fn main() {
+    let args = Args::parse();
+    let result = match args.precision {
+        Precision::F32 => run::<f32>(&args),
+        Precision::F64 => run::<f64>(&args),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run<T>(args: &Args) -> Result<(), Box<dyn Error>>
+where
+    T: 'static + SVecElem + Float,
+{
     // World
     eprintln!("Creating world...");
-    let world = random_scene();
+    let Scene { world, lights, camera: mut cam, settings, background } = match (&args.scene, args.builtin) {
+        (Some(path), _) => load_scene::<T>(path)?,
+        (None, BuiltinScene::Cornell) => cornell_box(),
+        (None, BuiltinScene::Random) => Scene {
+            world: random_scene(args.seed.unwrap_or(RenderSettings::default().seed)),
+            lights: HittableList::new(),
+            camera: Camera::new((13., 2., 3.), (0., 0., 0.), (0., 1., 0.), 20.0, ASPECT_RATIO, 0.1, 10.),
+            settings: RenderSettings::default(),
+            background: Background::sky(),
+        },
+    };
+    let settings = args.override_settings(settings)?;
+    let display = args.display_transform()?;
+    let background = args.background()?.unwrap_or(background);
+    cam.set_aspect_ratio(settings.aspect_ratio);
+    let world = Bvh::with_split_method(world, args.bvh_split.into());
     eprintln!("World created!");
 
-    // Camera
-    let cam = Camera::<f64>::new((13., 2., 3.), (0., 0., 0.), (0., 1., 0.), 20.0, ASPECT_RATIO, 0.1, 10.);
-    
-    println!("P3");
-    println!("{} {}", IMAGE_WIDTH, IMAGE_HEIGHT);
-    println!("255");
-
     eprintln!("Rendering begins...");
-    let pool = ThreadPool::new(NUM_THREADS);
-    let (tx, rx) = mpsc::channel::<Image<f64>>();
-
-    let job_left = Arc::new(Mutex::new(AA_SAMPLES));
-
-    let world = Arc::new(world);
-    let cam = Arc::new(cam);
-    for _ in 0..AA_SAMPLES {
-        let thread_tx = tx.clone();
-        let thread_world = Arc::clone(&world);
-        let thread_cam = Arc::clone(&cam);
-        let thread_job_left = Arc::clone(&job_left);
-
-        pool.execute(move || {
-                let image = render_single(&thread_world, &thread_cam);
-                let mut thread_job_left = thread_job_left.lock().unwrap();
-                *thread_job_left -= 1;
-                eprint!("{} jobs left\r", *thread_job_left);
-                drop(thread_job_left);
-                thread_tx.send(image).unwrap();
-            }
-        );
-    }
-
-    let mut image = vec![Color3::<f64>::new(0.0, 0.0, 0.0); (IMAGE_WIDTH * IMAGE_HEIGHT) as usize];
-
-    for _ in 0..AA_SAMPLES {
-        let single = rx.recv().unwrap();
-        assert_eq!(single.len(), image.len());
-        for i in 0..single.len() {
-            image[i] += single[i];
-        }
-    }
+    let image = render(Arc::new(world), Arc::new(lights), background, Arc::new(cam), &settings);
     eprintln!("Rendering finishes...");
 
     eprintln!("Writing image...");
-    write_image(&image);
+    let (width, height) = (settings.width, settings.height());
+    let to_stdout = args.output == Path::new("-");
+    let format = args.output_format();
+    if format.is_hdr() {
+        let writer: Box<dyn HdrImageWriter> = match format {
+            OutputFormat::Hdr => Box::new(RadianceHdr),
+            OutputFormat::Pfm => Box::new(Pfm),
+            _ => Box::new(Exr { compression: args.exr_compression, pixel_type: args.exr_pixel_type }),
+        };
+        let pixels = to_linear_f32(&image, settings.samples);
+        if to_stdout {
+            let mut out = io::stdout().lock();
+            writer.write(&mut out, &pixels, width, height)?;
+            out.flush()?;
+        } else {
+            writer.write_to_path(&args.output, &pixels, width, height)?;
+        }
+    } else {
+        let writer: Box<dyn ImageWriter> = match format {
+            OutputFormat::PpmAscii => Box::new(PpmAscii),
+            OutputFormat::Png => Box::new(Png),
+            _ => Box::new(PpmBinary),
+        };
+        let pixels = to_rgb8(&image, settings.samples, &display);
+        if to_stdout {
+            let mut out = io::stdout().lock();
+            writer.write(&mut out, &pixels, width, height)?;
+            out.flush()?;
+        } else {
+            writer.write_to_path(&args.output, &pixels, width, height)?;
+        }
+    }
     eprintln!("All completed!");
+    Ok(())
 }
@@ -1,24 +1,79 @@
 use num::Float;
 use rand::Rng;
+use serde::Deserialize;
+use std::sync::Arc;
 
 use crate::hittable::*;
 use crate::ray::*;
 use crate::vec3::*;
 use crate::common::{SVecElem, random_in_unit_sphere};
+use crate::microfacet::*;
+use crate::sampler::Sampler;
+use crate::spectrum::Dispersion;
+use crate::texture::*;
+
+/// Outcome of a bounce: `ray` carries on weighted by `attenuation`, which
+/// is `eval / pdf` for the sampled direction. `pdf` is the solid-angle
+/// density `ray` was drawn with, or `None` for a specular (delta) bounce
+/// that no other sampling strategy can reach.
+pub struct ScatterRecord<T: SVecElem> {
+    pub attenuation: Color3<T>,
+    pub ray: Ray<T>,
+    pub pdf: Option<T>,
+}
+
+impl<T: SVecElem> ScatterRecord<T> {
+    pub fn specular(attenuation: Color3<T>, ray: Ray<T>) -> Self {
+        Self { attenuation, ray, pdf: None }
+    }
+
+    pub fn is_specular(&self) -> bool {
+        self.pdf.is_none()
+    }
+}
 
 pub trait Material<T: SVecElem>: Send + Sync {
-    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>) -> Option<(Color3<T>, Ray<T>)>;
+    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, sampler: &mut Sampler) -> Option<ScatterRecord<T>>;
+
+    /// BSDF times the cosine (or the phase function, in a medium) for light
+    /// leaving along `direction`. Black for specular materials.
+    fn eval(&self, _ray_in: &Ray<T>, _rec: &HitRecord<T>, _direction: &Vec3<T>) -> Color3<T> {
+        Color3(T::zero(), T::zero(), T::zero())
+    }
+
+    /// Solid-angle density with which `scatter` picks `direction`; zero
+    /// for specular materials.
+    fn pdf(&self, _ray_in: &Ray<T>, _rec: &HitRecord<T>, _direction: &Vec3<T>) -> T {
+        T::zero()
+    }
+
+    /// Radiance given off at the hit point; black for anything but lights.
+    fn emitted(&self, _rec: &HitRecord<T>) -> Color3<T> {
+        Color3(T::zero(), T::zero(), T::zero())
+    }
+
+    /// Whether `emitted` can be non-black, so that objects made of the
+    /// material are worth sampling as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 pub struct Lambertian<T: SVecElem + Float> {
-    pub albedo: Color3<T>,
+    pub albedo: Arc<dyn Texture<T>>,
+}
+
+impl<T: SVecElem + Float> Lambertian<T> {
+    pub fn new(albedo: Color3<T>) -> Self {
+        Self { albedo: Arc::new(SolidColor { color: albedo }) }
+    }
 }
 
 impl<T> Material<T> for Lambertian<T>
 where
     T: SVecElem + Float,
 {
-    fn scatter(&self, _ray_in: &Ray<T>, rec: &HitRecord<T>) -> Option<(Color3<T>, Ray<T>)> {
-        let mut scatter_direction: Vec3<T> = rec.normal + random_in_unit_sphere();
+    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, sampler: &mut Sampler) -> Option<ScatterRecord<T>> {
+        let mut scatter_direction: Vec3<T> = rec.normal + random_in_unit_sphere(sampler);
 
         if scatter_direction.is_close(T::from_f64(0.).unwrap()) {
             scatter_direction = rec.normal;
@@ -27,43 +82,114 @@ where
         let scattered = Ray::<T> {
             origin: rec.p,
             direction: scatter_direction,
+            time: ray_in.time,
+            wavelengths: ray_in.wavelengths,
         };
-        let attenuation = self.albedo;
+        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        let pdf = self.pdf(ray_in, rec, &scattered.direction);
 
-        Some((attenuation, scattered))
+        Some(ScatterRecord { attenuation, ray: scattered, pdf: Some(pdf) })
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> Color3<T> {
+        self.albedo.value(rec.u, rec.v, &rec.p) * self.pdf(ray_in, rec, direction)
+    }
+
+    /// Cosine-weighted over the hemisphere.
+    fn pdf(&self, _ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> T {
+        let cosine = dot(&rec.normal, &direction.to_unit());
+        cosine.max(T::zero()) / T::from_f64(std::f64::consts::PI).unwrap()
     }
 }
 
 pub struct Metal<T: SVecElem + Float> {
-    pub albedo: Color3<T>,
+    pub albedo: Arc<dyn Texture<T>>,
     pub fuzz: T,
 }
 
+impl<T: SVecElem + Float> Metal<T> {
+    pub fn new(albedo: Color3<T>, fuzz: T) -> Self {
+        Self { albedo: Arc::new(SolidColor { color: albedo }), fuzz }
+    }
+
+    /// Density of the unit `direction` among `reflected + fuzz * s` for `s`
+    /// uniform on the unit sphere: that sphere of radius `fuzz` around the
+    /// unit mirror direction, seen from its origin, where the ray meets it
+    /// once or twice.
+    fn fuzz_pdf(&self, reflected: &Vec3<T>, direction: &Vec3<T>) -> T {
+        let (zero, one, f) = (T::zero(), T::one(), self.fuzz);
+        let w = direction.to_unit();
+        // |t w - reflected| = fuzz, a quadratic in t.
+        let b = dot(&w, reflected);
+        let root = (b * b - one + f * f).sqrt();
+        if root.is_nan() || root <= zero {
+            return zero;
+        }
+        // Both crossings meet the sphere at cosine root / fuzz.
+        let area = T::from_f64(4. * std::f64::consts::PI).unwrap() * f;
+        [b - root, b + root].into_iter()
+            .filter(|&t| t > zero)
+            .fold(zero, |pdf, t| pdf + t * t / (root * area))
+    }
+}
+
 impl<T> Material<T> for Metal<T>
 where
     T: SVecElem + Float,
 {
-    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>) -> Option<(Color3<T>, Ray<T>)> {
+    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, sampler: &mut Sampler) -> Option<ScatterRecord<T>> {
         let reflected = reflect(&ray_in.direction.to_unit(), &rec.normal);
         let scattered = Ray::<T> {
             origin: rec.p,
-            direction: reflected + random_in_unit_sphere() * self.fuzz,
+            direction: reflected + random_in_unit_sphere(sampler) * self.fuzz,
+            time: ray_in.time,
+            wavelengths: ray_in.wavelengths,
         };
-        let attenuation = self.albedo;
+        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
 
-        if dot(&scattered.direction, &rec.normal) > T::from_i8(0).unwrap() {
-            Some((attenuation, scattered))
-        } else {
-            None
+        if dot(&scattered.direction, &rec.normal) <= T::zero() {
+            return None;
+        }
+        if self.fuzz <= T::zero() {
+            return Some(ScatterRecord::specular(attenuation, scattered));
+        }
+        let pdf = self.fuzz_pdf(&reflected, &scattered.direction);
+        Some(ScatterRecord { attenuation, ray: scattered, pdf: Some(pdf) })
+    }
+
+    /// Directions fuzzed below the surface are absorbed.
+    fn eval(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> Color3<T> {
+        if dot(direction, &rec.normal) <= T::zero() {
+            return Color3(T::zero(), T::zero(), T::zero());
+        }
+        self.albedo.value(rec.u, rec.v, &rec.p) * self.pdf(ray_in, rec, direction)
+    }
+
+    fn pdf(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> T {
+        if self.fuzz <= T::zero() {
+            return T::zero();
         }
+        self.fuzz_pdf(&reflect(&ray_in.direction.to_unit(), &rec.normal), direction)
     }
 }
 
+/// Smooth glass. With `dispersion`, spectral renders bend each wavelength
+/// by its own index; RGB renders always use `ir`.
 pub struct Dielectric<T: SVecElem + Float> {
     pub ir: T,
+    pub dispersion: Option<Dispersion>,
 }
 
 impl<T: SVecElem + Float> Dielectric<T> {
+    pub fn new(ir: T) -> Self {
+        Self { ir, dispersion: None }
+    }
+
+    /// Dispersive glass, with `ir` its index at the sodium D line.
+    pub fn dispersive(dispersion: Dispersion) -> Self {
+        Self { ir: T::from_f64(dispersion.ior(Dispersion::D_LINE)).unwrap(), dispersion: Some(dispersion) }
+    }
+
     fn reflectance(cosine: T, ref_idx: T) -> T {
         let r0 = (T::from_i8(1).unwrap() - ref_idx) / (T::from_i8(1).unwrap() + ref_idx);
         let r0 = r0 * r0;
@@ -75,13 +201,20 @@ impl<T> Material<T> for Dielectric<T>
 where
     T: SVecElem + Float,
 {
-    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>) -> Option<(Color3<T>, Ray<T>)> {
+    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, sampler: &mut Sampler) -> Option<ScatterRecord<T>> {
         let attenuation = Color3::new(1., 1.,1.);
 
+        // Only the hero wavelength can follow a dispersed path.
+        let (ir, wavelengths) = match (self.dispersion, ray_in.wavelengths) {
+            (Some(dispersion), Some(wavelengths)) => {
+                (T::from_f64(dispersion.ior(wavelengths.hero())).unwrap(), Some(wavelengths.terminate_secondary()))
+            }
+            _ => (self.ir, ray_in.wavelengths),
+        };
         let refraction_ratio = if rec.front_face {
-            T::from_f64(1.).unwrap() / self.ir
+            T::from_f64(1.).unwrap() / ir
         } else {
-            self.ir
+            ir
         };
 
         let unit_direction = ray_in.direction.to_unit();
@@ -90,7 +223,7 @@ where
         let sin_theta = T::sqrt(T::from_i8(1).unwrap() - cos_theta * cos_theta);
         let cannot_refract = refraction_ratio * sin_theta > T::from_i8(1).unwrap();
         
-        let direction = if cannot_refract || Dielectric::<T>::reflectance(cos_theta, refraction_ratio).to_f64().unwrap() > rand::thread_rng().gen() {
+        let direction = if cannot_refract || Dielectric::<T>::reflectance(cos_theta, refraction_ratio).to_f64().unwrap() > sampler.gen() {
             reflect(&unit_direction, &rec.normal)
         } else {
             refract(&unit_direction, &rec.normal, refraction_ratio)
@@ -98,8 +231,501 @@ where
 
         let scattered = Ray::<T> {
             origin: rec.p,
-            direction: direction,
+            direction,
+            time: ray_in.time,
+            wavelengths,
+        };
+        Some(ScatterRecord::specular(attenuation, scattered))
+    }
+}
+
+/// Complex refractive indices `(eta, k)` of common metals, fitted to red,
+/// green and blue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetalPreset {
+    Gold,
+    Copper,
+    Aluminium,
+}
+
+impl MetalPreset {
+    pub fn ior(self) -> ([f64; 3], [f64; 3]) {
+        match self {
+            MetalPreset::Gold => ([0.143119, 0.374957, 1.44248], [3.98316, 2.38572, 1.60322]),
+            MetalPreset::Copper => ([0.200438, 0.924033, 1.10221], [3.91295, 2.45285, 2.14219]),
+            MetalPreset::Aluminium => ([1.65746, 0.880369, 0.521229], [9.22387, 6.26952, 4.83700]),
+        }
+    }
+}
+
+/// Shading-space directions for a hit: `wo` back along the incoming ray and
+/// `wi` along `direction`, with the normal facing the incoming ray as +z.
+fn local_directions<T: SVecElem + Float>(ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> (Frame<T>, Vec3<T>, Vec3<T>) {
+    let frame = Frame::new(rec.normal);
+    let (wo, wi) = (frame.to_local(&-ray_in.direction.to_unit()), frame.to_local(&direction.to_unit()));
+    (frame, wo, wi)
+}
+
+/// Rough metal: a GGX microfacet conductor with complex-IOR Fresnel.
+/// `roughness` is perceptual, 0 for a mirror.
+pub struct Conductor<T: SVecElem + Float> {
+    pub eta: Color3<T>,
+    pub k: Color3<T>,
+    pub roughness: T,
+}
+
+impl<T: SVecElem + Float> Conductor<T> {
+    pub fn new(eta: Color3<T>, k: Color3<T>, roughness: T) -> Self {
+        Self { eta, k, roughness }
+    }
+
+    pub fn preset(metal: MetalPreset, roughness: T) -> Self {
+        let (eta, k) = metal.ior();
+        Self::new(Color3::new(eta[0], eta[1], eta[2]), Color3::new(k[0], k[1], k[2]), roughness)
+    }
+
+    fn fresnel(&self, cos_i: T) -> Color3<T> {
+        Color3(
+            fresnel_conductor(cos_i, self.eta.x(), self.k.x()),
+            fresnel_conductor(cos_i, self.eta.y(), self.k.y()),
+            fresnel_conductor(cos_i, self.eta.z(), self.k.z()),
+        )
+    }
+}
+
+impl<T> Material<T> for Conductor<T>
+where
+    T: SVecElem + Float,
+{
+    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, sampler: &mut Sampler) -> Option<ScatterRecord<T>> {
+        let frame = Frame::new(rec.normal);
+        let wo = frame.to_local(&-ray_in.direction.to_unit());
+        if wo.z() <= T::zero() {
+            return None;
+        }
+        let ggx = Ggx::from_roughness(self.roughness);
+        let normal = Vec3(T::zero(), T::zero(), T::one());
+        let m = if ggx.is_smooth() {
+            normal
+        } else {
+            ggx.sample_visible(&wo, T::from_f64(sampler.gen()).unwrap(), T::from_f64(sampler.gen()).unwrap())
         };
-        Some((attenuation, scattered))
+        let wi = reflect_about(&wo, &m);
+        if wi.z() <= T::zero() {
+            return None;
+        }
+        let ray = Ray { origin: rec.p, direction: frame.to_world(&wi), time: ray_in.time, wavelengths: ray_in.wavelengths };
+        let fresnel = self.fresnel(dot(&wo, &m));
+        if ggx.is_smooth() {
+            return Some(ScatterRecord::specular(fresnel, ray));
+        }
+        let pdf = ggx.g1(&wo) * ggx.d(&m) / (T::from_f64(4.).unwrap() * wo.z());
+        Some(ScatterRecord { attenuation: fresnel * (ggx.g2(&wo, &wi) / ggx.g1(&wo)), ray, pdf: Some(pdf) })
     }
-}
\ No newline at end of file
+
+    fn eval(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> Color3<T> {
+        let (_, wo, wi) = local_directions(ray_in, rec, direction);
+        let ggx = Ggx::from_roughness(self.roughness);
+        if ggx.is_smooth() || wo.z() <= T::zero() || wi.z() <= T::zero() {
+            return Color3(T::zero(), T::zero(), T::zero());
+        }
+        let h = (wo + wi).to_unit();
+        self.fresnel(dot(&wo, &h)) * (ggx.d(&h) * ggx.g2(&wo, &wi) / (T::from_f64(4.).unwrap() * wo.z()))
+    }
+
+    /// Covers every direction reflected off a visible normal, including
+    /// those below the surface that `scatter` then absorbs.
+    fn pdf(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> T {
+        let (_, wo, wi) = local_directions(ray_in, rec, direction);
+        let ggx = Ggx::from_roughness(self.roughness);
+        let h = (wo + wi).to_unit();
+        if ggx.is_smooth() || wo.z() <= T::zero() || dot(&wo, &h) <= T::zero() {
+            return T::zero();
+        }
+        // Reflecting about `h` maps visible normals to directions with
+        // Jacobian 1 / (4 wo·h).
+        ggx.pdf_visible(&wo, &h) / (T::from_f64(4.).unwrap() * dot(&wo, &h))
+    }
+}
+
+/// Frosted glass: a GGX microfacet dielectric that reflects and refracts
+/// through rough microfacets (Walter et al. 2007). Like `Dielectric` it
+/// leaves radiance unscaled across the interface.
+pub struct RoughDielectric<T: SVecElem + Float> {
+    pub ir: T,
+    pub roughness: T,
+}
+
+impl<T: SVecElem + Float> RoughDielectric<T> {
+    /// Index of the far side relative to the side `rec` was hit from.
+    pub(crate) fn eta(&self, rec: &HitRecord<T>) -> T {
+        if rec.front_face { self.ir } else { T::one() / self.ir }
+    }
+
+    /// Half vector of a refraction from `wo` to `wi`, facing +z, or `None`
+    /// if no microfacet refracts one into the other.
+    fn refraction_half_vector(wo: &Vec3<T>, wi: &Vec3<T>, eta: T) -> Option<Vec3<T>> {
+        let h = -(*wo + *wi * eta);
+        let h = if h.z() < T::zero() { -h } else { h };
+        let h = h.to_unit();
+        (dot(wo, &h) > T::zero() && dot(wi, &h) < T::zero()).then_some(h)
+    }
+
+    /// Reflects or refracts `wo` off a sampled visible normal, choosing in
+    /// proportion to the Fresnel terms. `None` when the result leaves on the
+    /// wrong side of the surface.
+    pub(crate) fn sample(&self, wo: &Vec3<T>, eta: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        let ggx = Ggx::from_roughness(self.roughness);
+        let m = if ggx.is_smooth() {
+            Vec3(T::zero(), T::zero(), T::one())
+        } else {
+            ggx.sample_visible(wo, T::from_f64(sampler.gen()).unwrap(), T::from_f64(sampler.gen()).unwrap())
+        };
+        let reflected = T::from_f64(sampler.gen()).unwrap() < fresnel_dielectric(dot(wo, &m), eta);
+        let wi = match reflected {
+            true => reflect_about(wo, &m),
+            false => refract_through(wo, &m, eta)?,
+        };
+        ((wi.z() > T::zero()) == reflected).then_some(wi)
+    }
+
+    /// `(BSDF * cosine, pdf)` for the direction pair in shading space.
+    pub(crate) fn eval_pdf(&self, wo: &Vec3<T>, wi: &Vec3<T>, eta: T) -> (T, T) {
+        let (zero, four) = (T::zero(), T::from_f64(4.).unwrap());
+        let ggx = Ggx::from_roughness(self.roughness);
+        if ggx.is_smooth() || wo.z() <= zero || wi.z() == zero {
+            return (zero, zero);
+        }
+        if wi.z() > zero {
+            let h = (*wo + *wi).to_unit();
+            let fresnel = fresnel_dielectric(dot(wo, &h), eta);
+            let d = ggx.d(&h);
+            return (fresnel * d * ggx.g2(wo, wi) / (four * wo.z()), fresnel * ggx.pdf_visible(wo, &h) / (four * dot(wo, &h)));
+        }
+        let Some(h) = Self::refraction_half_vector(wo, wi, eta) else {
+            return (zero, zero);
+        };
+        let (cos_o, cos_i) = (dot(wo, &h), dot(wi, &h));
+        let transmitted = T::one() - fresnel_dielectric(cos_o, eta);
+        let denom = cos_o + eta * cos_i;
+        // Jacobian from the half vector to the refracted direction.
+        let jacobian = eta * eta * cos_i.abs() / (denom * denom);
+        let d = ggx.d(&h);
+        (transmitted * d * ggx.g2(wo, wi) * cos_o * jacobian / wo.z(), transmitted * ggx.pdf_visible(wo, &h) * jacobian)
+    }
+}
+
+impl<T> Material<T> for RoughDielectric<T>
+where
+    T: SVecElem + Float,
+{
+    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, sampler: &mut Sampler) -> Option<ScatterRecord<T>> {
+        let frame = Frame::new(rec.normal);
+        let wo = frame.to_local(&-ray_in.direction.to_unit());
+        if wo.z() <= T::zero() {
+            return None;
+        }
+        let eta = self.eta(rec);
+        let wi = self.sample(&wo, eta, sampler)?;
+        let ray = Ray { origin: rec.p, direction: frame.to_world(&wi), time: ray_in.time, wavelengths: ray_in.wavelengths };
+        let ggx = Ggx::from_roughness(self.roughness);
+        if ggx.is_smooth() {
+            return Some(ScatterRecord::specular(Color3::new(1., 1., 1.), ray));
+        }
+        let (_, pdf) = self.eval_pdf(&wo, &wi, eta);
+        let weight = ggx.g2(&wo, &wi) / ggx.g1(&wo);
+        Some(ScatterRecord { attenuation: Color3(weight, weight, weight), ray, pdf: Some(pdf) })
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> Color3<T> {
+        let (_, wo, wi) = local_directions(ray_in, rec, direction);
+        let (f, _) = self.eval_pdf(&wo, &wi, self.eta(rec));
+        Color3(f, f, f)
+    }
+
+    fn pdf(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> T {
+        let (_, wo, wi) = local_directions(ray_in, rec, direction);
+        self.eval_pdf(&wo, &wi, self.eta(rec)).1
+    }
+}
+
+/// Emits `emit` from both sides and absorbs everything that hits it.
+pub struct DiffuseLight<T: SVecElem + Float> {
+    pub emit: Color3<T>,
+}
+
+impl<T> Material<T> for DiffuseLight<T>
+where
+    T: SVecElem + Float,
+{
+    fn scatter(&self, _ray_in: &Ray<T>, _rec: &HitRecord<T>, _sampler: &mut Sampler) -> Option<ScatterRecord<T>> {
+        None
+    }
+
+    fn emitted(&self, _rec: &HitRecord<T>) -> Color3<T> {
+        self.emit
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emit != Color3(T::zero(), T::zero(), T::zero())
+    }
+}
+
+/// Phase function scattering equally in every direction, for media.
+pub struct Isotropic<T: SVecElem + Float> {
+    pub albedo: Arc<dyn Texture<T>>,
+}
+
+impl<T: SVecElem + Float> Isotropic<T> {
+    #[allow(dead_code, reason = "solid-colour shorthand like `Lambertian::new`; scene files pass textures")]
+    pub fn new(albedo: Color3<T>) -> Self {
+        Self { albedo: Arc::new(SolidColor { color: albedo }) }
+    }
+}
+
+impl<T> Material<T> for Isotropic<T>
+where
+    T: SVecElem + Float,
+{
+    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, sampler: &mut Sampler) -> Option<ScatterRecord<T>> {
+        let scattered = Ray::<T> {
+            origin: rec.p,
+            direction: random_in_unit_sphere(sampler),
+            time: ray_in.time,
+            wavelengths: ray_in.wavelengths,
+        };
+        let pdf = self.pdf(ray_in, rec, &scattered.direction);
+        Some(ScatterRecord { attenuation: self.albedo.value(rec.u, rec.v, &rec.p), ray: scattered, pdf: Some(pdf) })
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> Color3<T> {
+        self.albedo.value(rec.u, rec.v, &rec.p) * self.pdf(ray_in, rec, direction)
+    }
+
+    fn pdf(&self, _ray_in: &Ray<T>, _rec: &HitRecord<T>, _direction: &Vec3<T>) -> T {
+        T::from_f64(0.25 / std::f64::consts::PI).unwrap()
+    }
+}
+
+/// Henyey–Greenstein phase function value for the cosine between the
+/// incoming propagation direction and the scattered one.
+pub fn henyey_greenstein<T: SVecElem + Float>(cos_theta: T, g: T) -> T {
+    let one = T::one();
+    let denom = one + g * g - (g + g) * cos_theta;
+    (one - g * g) / (T::from_f64(4. * std::f64::consts::PI).unwrap() * denom * denom.sqrt())
+}
+
+/// Anisotropic phase function: `g` > 0 scatters mostly forwards (clouds,
+/// haze), `g` < 0 backwards, and 0 is isotropic. `g` is the mean cosine of
+/// the scattering angle.
+pub struct HenyeyGreenstein<T: SVecElem + Float> {
+    pub albedo: Arc<dyn Texture<T>>,
+    pub g: T,
+}
+
+impl<T: SVecElem + Float> HenyeyGreenstein<T> {
+    #[allow(dead_code, reason = "solid-colour shorthand like `Lambertian::new`; scene files pass textures")]
+    pub fn new(albedo: Color3<T>, g: T) -> Self {
+        Self { albedo: Arc::new(SolidColor { color: albedo }), g }
+    }
+
+    /// Cosine of a scattering angle drawn from the phase function.
+    pub fn sample_cos_theta(&self, u: T) -> T {
+        let (one, two, g) = (T::one(), T::from_f64(2.).unwrap(), self.g);
+        if g.abs() < T::from_f64(1e-3).unwrap() {
+            return one - two * u;
+        }
+        let s = (one - g * g) / (one - g + two * g * u);
+        ((one + g * g - s * s) / (two * g)).max(-one).min(one)
+    }
+}
+
+impl<T> Material<T> for HenyeyGreenstein<T>
+where
+    T: SVecElem + Float,
+{
+    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, sampler: &mut Sampler) -> Option<ScatterRecord<T>> {
+        let forward = ray_in.direction.to_unit();
+        let cos_theta = self.sample_cos_theta(T::from_f64(sampler.gen()).unwrap());
+        let sin_theta = (T::one() - cos_theta * cos_theta).max(T::zero()).sqrt();
+        let phi = T::from_f64(sampler.gen_range(0.0..2. * std::f64::consts::PI)).unwrap();
+        let (t, b) = orthonormal_basis(&forward);
+        let direction = forward * cos_theta + (t * phi.cos() + b * phi.sin()) * sin_theta;
+        let scattered = Ray::<T> {
+            origin: rec.p,
+            direction,
+            time: ray_in.time,
+            wavelengths: ray_in.wavelengths,
+        };
+        let pdf = self.pdf(ray_in, rec, &scattered.direction);
+        Some(ScatterRecord { attenuation: self.albedo.value(rec.u, rec.v, &rec.p), ray: scattered, pdf: Some(pdf) })
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> Color3<T> {
+        self.albedo.value(rec.u, rec.v, &rec.p) * self.pdf(ray_in, rec, direction)
+    }
+
+    fn pdf(&self, ray_in: &Ray<T>, _rec: &HitRecord<T>, direction: &Vec3<T>) -> T {
+        henyey_greenstein(dot(&ray_in.direction.to_unit(), &direction.to_unit()), self.g)
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::spectrum::Wavelengths;
+
+    pub fn surface_hit(material: Arc<dyn Material<f64>>) -> HitRecord<'static, f64> {
+        HitRecord { p: Point3(0., 0., 0.), normal: Vec3(0., 0., 1.), material, t: 1., u: 0., v: 0., front_face: true }
+    }
+
+    /// Every scattered ray is weighted by `eval / pdf` for its direction.
+    pub fn check_scatter_weights(material: &dyn Material<f64>, ray: &Ray<f64>, rec: &HitRecord<f64>, sampler: &mut Sampler) {
+        for _ in 0..1000 {
+            let Some(scatter) = material.scatter(ray, rec, sampler) else { continue };
+            let direction = scatter.ray.direction;
+            let pdf = material.pdf(ray, rec, &direction);
+            assert!((scatter.pdf.unwrap() - pdf).abs() < 1e-9 * pdf);
+            assert!((scatter.attenuation * pdf - material.eval(ray, rec, &direction)).length() < 1e-9 * pdf);
+        }
+    }
+
+    #[test]
+    fn eval_and_pdf_match_scatter() {
+        let ray = Ray { origin: Point3(-1., 0.5, 1.), direction: Vec3(1., -0.5, -1.), time: 0., wavelengths: None };
+        let materials: Vec<Arc<dyn Material<f64>>> = vec![
+            Arc::new(Lambertian::new(Color3::new(0.8, 0.5, 0.2))),
+            Arc::new(Metal::new(Color3::new(0.9, 0.8, 0.7), 0.4)),
+            Arc::new(Metal::new(Color3::new(0.9, 0.8, 0.7), 1.5)),
+            Arc::new(Isotropic::new(Color3::new(0.5, 0.5, 0.5))),
+            Arc::new(HenyeyGreenstein::new(Color3::new(0.5, 0.5, 0.5), 0.7)),
+            Arc::new(Conductor::preset(MetalPreset::Gold, 0.5)),
+        ];
+        let mut sampler = Sampler::new(8, 9);
+        for material in materials {
+            let rec = surface_hit(material.clone());
+            check_scatter_weights(material.as_ref(), &ray, &rec, &mut sampler);
+            // The densities cover the whole sphere once.
+            let n = 400000;
+            let integral = (0..n).map(|_| material.pdf(&ray, &rec, &random_in_unit_sphere(&mut sampler))).sum::<f64>()
+                * 4. * std::f64::consts::PI / n as f64;
+            assert!((integral - 1.).abs() < 0.03, "{}", integral);
+        }
+
+        // Rough glass, entering and leaving; some samples leave below the
+        // surface they were reflected off, so the densities need not sum to 1.
+        let glass = RoughDielectric { ir: 1.5, roughness: 0.5 };
+        for front_face in [true, false] {
+            let rec = HitRecord { front_face, ..surface_hit(Arc::new(Isotropic::new(Color3::new(1., 1., 1.)))) };
+            check_scatter_weights(&glass, &ray, &rec, &mut sampler);
+        }
+
+        // Mirrors and glass are specular, with nothing to evaluate.
+        let rec = surface_hit(Arc::new(Dielectric::new(1.5)));
+        let smooth: [Arc<dyn Material<f64>>; 4] = [
+            Arc::new(Metal::new(Color3::new(1., 1., 1.), 0.)),
+            Arc::new(Dielectric::new(1.5)),
+            Arc::new(Conductor::preset(MetalPreset::Copper, 0.)),
+            Arc::new(RoughDielectric { ir: 1.5, roughness: 0. }),
+        ];
+        for material in smooth {
+            assert!(material.scatter(&ray, &rec, &mut sampler).unwrap().is_specular());
+            assert_eq!(material.pdf(&ray, &rec, &Vec3(1., 0., 1.)), 0.);
+        }
+    }
+
+    #[test]
+    fn dispersion() {
+        // Light entering BK7 at 60° from the normal: blue bends more than red,
+        // and only the hero wavelength carries on.
+        let glass = Dielectric::<f64>::dispersive(Dispersion::BK7);
+        assert!((glass.ir - 1.5168).abs() < 1e-4);
+        let rec = surface_hit(Arc::new(Isotropic::new(Color3::new(1., 1., 1.))));
+        let direction = Vec3(60f64.to_radians().sin(), 0., -60f64.to_radians().cos());
+        let refracted = |lambda: Option<f64>| {
+            let wavelengths = lambda.map(|l| Wavelengths { lambda: [l, l + 100., l - 100.], hero_only: false });
+            let ray = Ray { origin: Point3(0., 0., 1.), direction, time: 0., wavelengths };
+            let mut sampler = Sampler::new(1, 1);
+            // Refraction wins at this angle far more often than not.
+            loop {
+                let scattered = glass.scatter(&ray, &rec, &mut sampler).unwrap().ray;
+                if scattered.direction.z() < 0. {
+                    return scattered;
+                }
+            }
+        };
+        let (blue, red, rgb) = (refracted(Some(450.)), refracted(Some(650.)), refracted(None));
+        assert!(blue.direction.to_unit().x() < red.direction.to_unit().x());
+        assert!(blue.wavelengths.unwrap().hero_only && rgb.wavelengths.is_none());
+        // Snell's law at the nominal index without wavelengths.
+        assert!((rgb.direction.to_unit().x() - 60f64.to_radians().sin() / glass.ir).abs() < 1e-9);
+    }
+
+    /// Mean weight of the rays `material` scatters for light arriving at
+    /// `theta_degrees`: the fraction of energy kept in a white furnace.
+    pub fn albedo(material: &dyn Material<f64>, theta_degrees: f64, front_face: bool) -> f64 {
+        let theta = theta_degrees.to_radians();
+        let ray = Ray { origin: Point3(0., 0., 0.), direction: Vec3(theta.sin(), 0., -theta.cos()), time: 0., wavelengths: None };
+        let rec = HitRecord { front_face, ..surface_hit(Arc::new(Isotropic::new(Color3::new(1., 1., 1.)))) };
+        let mut sampler = Sampler::new(7, 7);
+        let n = 40000;
+        (0..n).filter_map(|_| material.scatter(&ray, &rec, &mut sampler)).map(|s| s.attenuation.x()).sum::<f64>() / n as f64
+    }
+
+    #[test]
+    fn white_furnace() {
+        // A perfectly reflecting conductor and glass lose energy only to
+        // the multiple bounces between microfacets that single scattering
+        // leaves out, which grows with roughness and at grazing angles.
+        let mirror = |roughness| Conductor::new(Color3::new(1., 1., 1.), Color3::new(1e4, 1e4, 1e4), roughness);
+        for (roughness, floor) in [(0., 0.999), (0.2, 0.98), (0.5, 0.75), (0.8, 0.5)] {
+            for theta in [0., 40., 70.] {
+                let kept = albedo(&mirror(roughness), theta, true);
+                assert!(kept <= 1. + 1e-9 && kept >= floor, "roughness {} theta {}: {}", roughness, theta, kept);
+                for front_face in [true, false] {
+                    let kept = albedo(&RoughDielectric { ir: 1.5, roughness }, theta, front_face);
+                    assert!(kept <= 1. + 1e-9 && kept >= floor, "glass roughness {} theta {} front {}: {}", roughness, theta, front_face, kept);
+                }
+            }
+        }
+        // Real gold keeps less, most of it in red.
+        let gold = Conductor::preset(MetalPreset::Gold, 0.);
+        let rec = surface_hit(Arc::new(Isotropic::new(Color3::new(1., 1., 1.))));
+        let ray = Ray { origin: Point3(0., 0., 1.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let color = gold.scatter(&ray, &rec, &mut Sampler::new(0, 0)).unwrap().attenuation;
+        assert!(color.x() > 0.9 && color.z() < 0.4 && color.y() < color.x(), "{:?}", color);
+    }
+
+    #[test]
+    fn henyey_greenstein_sampling() {
+        let rec = HitRecord {
+            p: Point3(0., 0., 0.),
+            normal: Vec3(1., 0., 0.),
+            material: Arc::new(Isotropic::new(Color3::new(1., 1., 1.))) as _,
+            t: 1.,
+            u: 0.,
+            v: 0.,
+            front_face: true,
+        };
+        let ray = Ray { origin: Point3(0., 0., -1.), direction: Vec3(0.3, -0.2, 2.), time: 0., wavelengths: None };
+        let forward = ray.direction.to_unit();
+        let mut sampler = Sampler::new(5, 6);
+        for g in [-0.6, 0., 0.3, 0.9] {
+            let phase = HenyeyGreenstein::new(Color3::new(1., 1., 1.), g);
+            let n = 40000;
+            let mean = (0..n)
+                .map(|_| dot(&phase.scatter(&ray, &rec, &mut sampler).unwrap().ray.direction.to_unit(), &forward))
+                .sum::<f64>() / n as f64;
+            assert!((mean - g).abs() < 0.01, "g = {}: mean cosine {}", g, mean);
+
+            // Normalised over the sphere: 2π ∫ p(cos) dcos = 1.
+            let steps = 100000;
+            let integral: f64 = (0..steps)
+                .map(|i| henyey_greenstein(-1. + 2. * (i as f64 + 0.5) / steps as f64, g) * 2. / steps as f64)
+                .sum::<f64>() * 2. * std::f64::consts::PI;
+            assert!((integral - 1.).abs() < 1e-3, "g = {}: {}", g, integral);
+        }
+    }
+}
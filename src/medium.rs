@@ -0,0 +1,289 @@
+use num::Float;
+use rand::Rng;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::common::SVecElem;
+use crate::hittable::*;
+use crate::materials::*;
+use crate::ray::*;
+use crate::sampler::Sampler;
+use crate::vec3::*;
+use crate::volume::*;
+
+/// Gap left after a boundary crossing before looking for the next one.
+const BOUNDARY_EPSILON: f64 = 1e-4;
+
+/// Deterministic random source for a ray, so media scatter the same way
+/// whichever thread traces them and however often the BVH asks.
+fn ray_sampler<T: SVecElem + Float>(ray: &Ray<T>) -> Sampler {
+    let bits = |v: T| v.to_f64().unwrap().to_bits();
+    Sampler::for_key([
+        bits(ray.origin.x()), bits(ray.origin.y()), bits(ray.origin.z()),
+        bits(ray.direction.x()), bits(ray.direction.y()), bits(ray.direction.z()),
+        bits(ray.time),
+    ])
+}
+
+/// Homogeneous participating medium filling a closed `boundary`, such as
+/// fog or smoke. Rays travel an exponentially distributed distance before
+/// scattering off the `phase_function` material.
+///
+/// The boundary may be any closed shape, concave ones included: the ray is
+/// followed through every stretch it spends inside. `density` is per unit
+/// length in the boundary's own space.
+pub struct ConstantMedium<'a, T: SVecElem + Float> {
+    pub boundary: Box<dyn Hittable<T> + 'a + Send + Sync>,
+    pub density: T,
+    pub phase_function: Arc<dyn Material<T> + 'a>,
+}
+
+impl<T> Hittable<T> for ConstantMedium<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        let epsilon = T::from_f64(BOUNDARY_EPSILON).unwrap();
+        let speed = ray.direction.length();
+        let mut sampler = ray_sampler(ray);
+
+        // Starting from -inf keeps entries and exits paired up even when the
+        // ray begins inside the medium.
+        let mut from = T::neg_infinity();
+        loop {
+            let enter = self.boundary.hit(ray, from, T::infinity())?.t;
+            let exit = self.boundary.hit(ray, enter + epsilon, T::infinity())?.t;
+            let (start, end) = (enter.max(t_min), exit.min(t_max));
+            if start < end {
+                // Memoryless, so each stretch can draw afresh.
+                let u = T::one() - T::from_f64(sampler.gen()).unwrap();
+                let distance = -u.ln() / self.density;
+                if distance < (end - start) * speed {
+                    let t = start + distance / speed;
+                    return Some(HitRecord {
+                        p: ray.at(t),
+                        // Arbitrary: the phase function ignores it.
+                        normal: Vec3(T::one(), T::zero(), T::zero()),
+                        material: Arc::clone(&self.phase_function),
+                        t,
+                        u: T::zero(),
+                        v: T::zero(),
+                        front_face: true,
+                    });
+                }
+            }
+            if exit >= t_max {
+                return None;
+            }
+            from = exit + epsilon;
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        self.boundary.bounding_box()
+    }
+}
+
+/// Distance to the next collision with a medium of extinction `majorant`,
+/// in units of the ray parameter.
+fn free_flight<T: SVecElem + Float>(majorant: T, speed: T, sampler: &mut Sampler) -> T {
+    let u = T::one() - T::from_f64(sampler.gen()).unwrap();
+    -u.ln() / (majorant * speed)
+}
+
+/// Medium whose density varies over a grid, e.g. a cloud, scaled by
+/// `density`. Collisions are sampled by delta tracking against the grid's
+/// largest value, so no ray marching bias creeps in. Shadow rays are dimmed
+/// by ratio tracking rather than stopped.
+pub struct GridMedium<'a, T: SVecElem + Float> {
+    pub grid: DensityGrid<T>,
+    pub density: T,
+    pub phase_function: Arc<dyn Material<T> + 'a>,
+}
+
+impl<T: SVecElem + Float> GridMedium<'_, T> {
+    fn majorant(&self) -> T {
+        self.grid.max() * self.density
+    }
+}
+
+impl<T> Hittable<T> for GridMedium<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        let majorant = self.majorant();
+        let (start, end) = self.grid.bbox.clip(ray, t_min, t_max)?;
+        if majorant <= T::zero() {
+            return None;
+        }
+        let speed = ray.direction.length();
+        let mut sampler = ray_sampler(ray);
+        let mut t = start;
+        loop {
+            t = t + free_flight(majorant, speed, &mut sampler);
+            if t >= end {
+                return None;
+            }
+            // Real collision with probability density / majorant, else a
+            // null collision and the ray carries on.
+            let p = ray.at(t);
+            if T::from_f64(sampler.gen()).unwrap() * majorant < self.grid.density(&p) * self.density {
+                return Some(HitRecord {
+                    p,
+                    normal: Vec3(T::one(), T::zero(), T::zero()),
+                    material: Arc::clone(&self.phase_function),
+                    t,
+                    u: T::zero(),
+                    v: T::zero(),
+                    front_face: true,
+                });
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        Some(self.grid.bbox)
+    }
+
+    /// Unbiased estimate of the fraction of light getting through between
+    /// `t_min` and `t_max`, by ratio tracking.
+    fn transmittance(&self, ray: &Ray<T>, t_min: T, t_max: T, sampler: &mut Sampler) -> T {
+        let majorant = self.majorant();
+        let Some((start, end)) = self.grid.bbox.clip(ray, t_min, t_max) else {
+            return T::one();
+        };
+        if majorant <= T::zero() {
+            return T::one();
+        }
+        let speed = ray.direction.length();
+        let mut transmittance = T::one();
+        let mut t = start;
+        loop {
+            t = t + free_flight(majorant, speed, sampler);
+            if t >= end {
+                return transmittance;
+            }
+            transmittance = transmittance * (T::one() - self.grid.density(&ray.at(t)) * self.density / majorant);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::*;
+    use crate::quad::*;
+
+    fn fog(boundary: Box<dyn Hittable<f64> + Send + Sync>, density: f64) -> ConstantMedium<'static, f64> {
+        ConstantMedium { boundary, density, phase_function: Arc::new(Isotropic::new(Color3::new(1., 1., 1.))) }
+    }
+
+    fn ray(i: u32) -> Ray<f64> {
+        // Distinct rays along -z through the same line, for fresh draws.
+        let jitter = i as f64 * 1e-9;
+        Ray { origin: Point3(jitter, 0., 10.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None }
+    }
+
+    #[test]
+    fn free_flight_is_exponential() {
+        let slab = fog(Box::new(Cuboid::new(Point3(-1., -1., -2.), Point3(1., 1., 2.), Arc::new(Lambertian::new(Color3::new(0., 0., 0.))))), 0.5);
+        let n = 20000;
+        let hits: Vec<f64> = (0..n).filter_map(|i| slab.hit(&ray(i), 0.001, f64::INFINITY)).map(|rec| rec.t - 8.).collect();
+
+        // Transmittance through 4 units at density 0.5 is e^-2.
+        let scattered = hits.len() as f64 / n as f64;
+        assert!((scattered - (1. - (-2f64).exp())).abs() < 0.01, "{}", scattered);
+        assert!(hits.iter().all(|d| (0. ..4.).contains(d)));
+        let half = hits.iter().filter(|&&d| d < 2.).count() as f64 / n as f64;
+        assert!((half - (1. - (-1f64).exp())).abs() < 0.01, "{}", half);
+
+        let same = slab.hit(&ray(7), 0.001, f64::INFINITY).map(|rec| rec.t);
+        assert_eq!(same, slab.hit(&ray(7), 0.001, f64::INFINITY).map(|rec| rec.t));
+    }
+
+    #[test]
+    fn closed_mesh_boundary() {
+        // A triangulated cube two units across, outward winding, as an OBJ
+        // mesh: the medium must see one entry and one exit like a Cuboid.
+        let cube = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                    f 1 3 2\nf 1 4 3\nf 5 6 7\nf 5 7 8\nf 1 2 6\nf 1 6 5\n\
+                    f 4 8 7\nf 4 7 3\nf 1 5 8\nf 1 8 4\nf 2 3 7\nf 2 7 6\n";
+        let mesh: HittableList<f64> = crate::obj::parse_obj(cube, std::path::Path::new("cube.obj"), |_| unreachable!()).unwrap().triangles;
+        assert_eq!(mesh.len(), 12);
+        let sigma = 0.7;
+        let medium = fog(Box::new(mesh), sigma);
+
+        // Off the face diagonals, so no ray grazes a shared edge.
+        let through = |i: u32| Ray { origin: Point3(0.3 + i as f64 * 1e-9, -0.2, 10.), ..ray(i) };
+        let n = 20000;
+        let ts: Vec<f64> = (0..n).filter_map(|i| medium.hit(&through(i), 0.001, f64::INFINITY)).map(|rec| rec.t).collect();
+        assert!(ts.iter().all(|t| (9. ..=11.).contains(t)));
+        let scattered = ts.len() as f64 / n as f64;
+        let expected = 1. - (-sigma * 2.).exp();
+        assert!((scattered - expected).abs() < 0.01, "{} vs {}", scattered, expected);
+
+        // From the centre only the one unit to the far face counts.
+        let inside = |i: u32| Ray { origin: Point3(0.3 + i as f64 * 1e-9, -0.2, 0.), ..ray(i) };
+        let scattered = (0..n).filter(|&i| medium.hit(&inside(i), 0.001, f64::INFINITY).is_some()).count() as f64 / n as f64;
+        let expected = 1. - (-sigma).exp();
+        assert!((scattered - expected).abs() < 0.01, "{} vs {}", scattered, expected);
+    }
+
+    /// Exact optical depth along the unit-speed `ray` by the midpoint rule.
+    fn optical_depth(grid: &GridMedium<f64>, ray: &Ray<f64>, t0: f64, t1: f64) -> f64 {
+        let steps = 20000;
+        let dt = (t1 - t0) / steps as f64;
+        (0..steps).map(|i| grid.grid.density(&ray.at(t0 + (i as f64 + 0.5) * dt)) * grid.density * dt).sum()
+    }
+
+    #[test]
+    fn delta_and_ratio_tracking_agree() {
+        // Density rising linearly along z through the box, 0 to 2.
+        let bbox = Aabb::new(Point3(-1., -1., -2.), Point3(1., 1., 2.));
+        let cloud = GridMedium {
+            grid: DensityGrid::from_fn(bbox, [4, 4, 64], |p| (p.z() + 2.) / 4.),
+            density: 2.,
+            phase_function: Arc::new(Isotropic::new(Color3::new(1., 1., 1.))) as _,
+        };
+        let expected = (-optical_depth(&cloud, &ray(0), 8., 12.)).exp();
+        assert!((expected - (-4f64).exp()).abs() < 0.01);
+
+        let n = 20000;
+        let escaped = (0..n).filter(|&i| cloud.hit(&ray(i), 0.001, f64::INFINITY).is_none()).count() as f64 / n as f64;
+        assert!((escaped - expected).abs() < 0.01, "{} vs {}", escaped, expected);
+
+        let mut sampler = Sampler::new(3, 4);
+        let ratio = (0..n).map(|_| cloud.transmittance(&ray(0), 0.001, f64::INFINITY, &mut sampler)).sum::<f64>() / n as f64;
+        assert!((ratio - expected).abs() < 0.005, "{} vs {}", ratio, expected);
+
+        // Part way in: only the thin front half counts.
+        let front = (-optical_depth(&cloud, &ray(0), 8., 10.)).exp();
+        let partial = (0..n).map(|_| cloud.transmittance(&ray(0), 0.001, 10., &mut sampler)).sum::<f64>() / n as f64;
+        assert!((partial - front).abs() < 0.005, "{} vs {}", partial, front);
+    }
+
+    #[test]
+    fn concave_boundary_and_inside_start() {
+        // Two separate slabs as one boundary: 1 unit of medium each.
+        let material: Arc<dyn Material<f64>> = Arc::new(Lambertian::new(Color3::new(0., 0., 0.)));
+        let slabs: HittableList<f64> = vec![
+            Box::new(Cuboid::new(Point3(-1., -1., 2.), Point3(1., 1., 3.), material.clone())) as _,
+            Box::new(Cuboid::new(Point3(-1., -1., -3.), Point3(1., 1., -2.), material)) as _,
+        ];
+        let medium = fog(Box::new(slabs), 1.);
+        let n = 20000;
+        let ts: Vec<f64> = (0..n).filter_map(|i| medium.hit(&ray(i), 0.001, f64::INFINITY)).map(|rec| rec.t).collect();
+        assert!(ts.iter().all(|t| (7. ..=8.).contains(t) || (12. ..=13.).contains(t)));
+        let total = ts.len() as f64 / n as f64;
+        assert!((total - (1. - (-2f64).exp())).abs() < 0.01, "{}", total);
+
+        // Starting inside the near slab, only the rest of it counts.
+        let inside = |i: u32| Ray { origin: Point3(i as f64 * 1e-9, 0., 2.5), ..ray(i) };
+        let ts: Vec<f64> = (0..n).filter_map(|i| medium.hit(&inside(i), 0.001, 1.)).map(|rec| rec.t).collect();
+        assert!(ts.iter().all(|t| *t <= 0.5));
+        let near = ts.len() as f64 / n as f64;
+        assert!((near - (1. - (-0.5f64).exp())).abs() < 0.01, "{}", near);
+    }
+}
@@ -0,0 +1,246 @@
+use num::Float;
+
+use crate::common::SVecElem;
+use crate::vec3::*;
+
+/// Orthonormal frame around a unit normal, for working in shading space
+/// where the normal is +z.
+#[derive(Debug, Clone)]
+pub struct Frame<T: SVecElem> {
+    t: Vec3<T>,
+    b: Vec3<T>,
+    n: Vec3<T>,
+}
+
+impl<T: SVecElem + Float> Frame<T> {
+    pub fn new(n: Vec3<T>) -> Self {
+        let (t, b) = orthonormal_basis(&n);
+        Self { t, b, n }
+    }
+
+    pub fn to_local(&self, v: &Vec3<T>) -> Vec3<T> {
+        Vec3(dot(v, &self.t), dot(v, &self.b), dot(v, &self.n))
+    }
+
+    pub fn to_world(&self, v: &Vec3<T>) -> Vec3<T> {
+        self.t * v.x() + self.b * v.y() + self.n * v.z()
+    }
+}
+
+/// Isotropic GGX (Trowbridge–Reitz) distribution of microfacet normals in
+/// shading space, with Smith masking-shadowing.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx<T: SVecElem> {
+    pub alpha: T,
+}
+
+impl<T: SVecElem + Float> Ggx<T> {
+    /// Roughness below this is treated as a perfect mirror.
+    pub const MIN_ALPHA: f64 = 1e-3;
+
+    /// From perceptual roughness in [0, 1], squared as in the Disney BRDF.
+    pub fn from_roughness(roughness: T) -> Self {
+        Self { alpha: roughness * roughness }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < T::from_f64(Self::MIN_ALPHA).unwrap()
+    }
+
+    /// Density of microfacet normals `m` per unit projected area.
+    pub fn d(&self, m: &Vec3<T>) -> T {
+        if m.z() <= T::zero() {
+            return T::zero();
+        }
+        let a2 = self.alpha * self.alpha;
+        let x = m.z() * m.z() * (a2 - T::one()) + T::one();
+        a2 / (T::from_f64(std::f64::consts::PI).unwrap() * x * x)
+    }
+
+    /// Smith's auxiliary function Λ for direction `w`.
+    pub fn lambda(&self, w: &Vec3<T>) -> T {
+        let cos2 = w.z() * w.z();
+        if cos2 <= T::zero() {
+            return T::infinity();
+        }
+        let tan2 = (T::one() - cos2).max(T::zero()) / cos2;
+        let two = T::from_f64(2.).unwrap();
+        ((T::one() + self.alpha * self.alpha * tan2).sqrt() - T::one()) / two
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: &Vec3<T>) -> T {
+        T::one() / (T::one() + self.lambda(w))
+    }
+
+    /// Height-correlated masking-shadowing for the pair of directions.
+    pub fn g2(&self, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        T::one() / (T::one() + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a normal from the distribution of normals visible from `wo`
+    /// (Heitz 2018), given two uniform numbers. `wo` must be above the
+    /// surface.
+    pub fn sample_visible(&self, wo: &Vec3<T>, u1: T, u2: T) -> Vec3<T> {
+        let (zero, one) = (T::zero(), T::one());
+        // Stretch to the hemisphere configuration.
+        let vh = Vec3(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()).to_unit();
+        let len2 = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if len2 > zero { Vec3(-vh.y(), vh.x(), zero) / len2.sqrt() } else { Vec3(one, zero, zero) };
+        let t2 = cross(&vh, &t1);
+        // Uniform disk, warped towards the visible half.
+        let r = u1.sqrt();
+        let phi = T::from_f64(2. * std::f64::consts::PI).unwrap() * u2;
+        let p1 = r * phi.cos();
+        let s = (one + vh.z()) / T::from_f64(2.).unwrap();
+        let p2 = (one - s) * (one - p1 * p1).max(zero).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (one - p1 * p1 - p2 * p2).max(zero).sqrt();
+        // Unstretch.
+        Vec3(self.alpha * nh.x(), self.alpha * nh.y(), nh.z().max(zero)).to_unit()
+    }
+
+    /// Density of `sample_visible` returning `m` for `wo`.
+    pub fn pdf_visible(&self, wo: &Vec3<T>, m: &Vec3<T>) -> T {
+        self.g1(wo) * dot(wo, m).max(T::zero()) * self.d(m) / wo.z()
+    }
+}
+
+/// Unpolarised Fresnel reflectance for light meeting an interface at
+/// cosine `cos_i` from the side with index 1, where `eta` is the relative
+/// index of the other side. Total internal reflection gives 1.
+pub fn fresnel_dielectric<T: SVecElem + Float>(cos_i: T, eta: T) -> T {
+    let cos_i = cos_i.abs().min(T::one());
+    let sin2_t = (T::one() - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= T::one() {
+        return T::one();
+    }
+    let cos_t = (T::one() - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (rs * rs + rp * rp) / T::from_f64(2.).unwrap()
+}
+
+/// Unpolarised Fresnel reflectance of a conductor with complex index
+/// `eta + i k`, one channel at a time.
+pub fn fresnel_conductor<T: SVecElem + Float>(cos_i: T, eta: T, k: T) -> T {
+    let (one, two) = (T::one(), T::from_f64(2.).unwrap());
+    let c2 = cos_i.abs().min(one).powi(2);
+    let s2 = one - c2;
+    let t0 = eta * eta - k * k - s2;
+    let a2b2 = (t0 * t0 + T::from_f64(4.).unwrap() * eta * eta * k * k).sqrt();
+    let a = ((a2b2 + t0) / two).max(T::zero()).sqrt();
+    let t1 = a2b2 + c2;
+    let t2 = two * a * c2.sqrt();
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = c2 * a2b2 + s2 * s2;
+    let t4 = t2 * s2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rs + rp) / two
+}
+
+/// Reflection of the outgoing direction `wo` about the normal `m`; both
+/// point away from the surface.
+pub fn reflect_about<T: SVecElem + Float>(wo: &Vec3<T>, m: &Vec3<T>) -> Vec3<T> {
+    *m * (T::from_f64(2.).unwrap() * dot(wo, m)) - *wo
+}
+
+/// Refraction of `wo` through the normal `m` into a medium of relative
+/// index `eta`, pointing away on the far side; `None` on total internal
+/// reflection.
+pub fn refract_through<T: SVecElem + Float>(wo: &Vec3<T>, m: &Vec3<T>, eta: T) -> Option<Vec3<T>> {
+    let cos_i = dot(wo, m);
+    let sin2_t = (T::one() - cos_i * cos_i).max(T::zero()) / (eta * eta);
+    if sin2_t >= T::one() {
+        return None;
+    }
+    let cos_t = (T::one() - sin2_t).sqrt();
+    Some(-*wo / eta + *m * (cos_i / eta - cos_t))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::Sampler;
+    use rand::Rng;
+
+    fn direction(theta_degrees: f64) -> Vec3<f64> {
+        let theta = theta_degrees.to_radians();
+        Vec3(theta.sin(), 0., theta.cos())
+    }
+
+    /// ∫ f(m) dm over the upper hemisphere, drawing cosine-weighted normals
+    /// so sharp lobes are not missed. Always the same normals, so ratios of
+    /// two integrals are far less noisy than either.
+    fn integrate_normals(f: impl Fn(Vec3<f64>) -> f64) -> f64 {
+        let mut sampler = Sampler::new(3, 4);
+        let n = 200000;
+        (0..n)
+            .map(|_| {
+                let (r, phi) = (sampler.gen::<f64>().sqrt(), sampler.gen_range(0.0..2. * std::f64::consts::PI));
+                let m = Vec3(r * phi.cos(), r * phi.sin(), (1. - r * r).max(1e-12).sqrt());
+                f(m) * std::f64::consts::PI / m.z()
+            })
+            .sum::<f64>() / n as f64
+    }
+
+    #[test]
+    fn weak_white_furnace() {
+        let mut sampler = Sampler::new(1, 2);
+        for alpha in [0.05, 0.3, 0.8] {
+            let ggx = Ggx { alpha };
+            for theta in [0., 45., 80.] {
+                let wo = direction(theta);
+                // Visible normals cover the view exactly once:
+                // ∫ G1(wo) D(m) max(0, wo·m) / wo.z dm = 1.
+                let integral = integrate_normals(|m| ggx.pdf_visible(&wo, &m));
+                let tolerance = if alpha < 0.1 { 0.1 } else { 0.02 };
+                assert!((integral - 1.).abs() < tolerance, "alpha {} theta {}: {}", alpha, theta, integral);
+
+                // Sampled normals face the viewer, spread as the density says.
+                let n = 20000;
+                let mean_cos = (0..n)
+                    .map(|_| {
+                        let m = ggx.sample_visible(&wo, sampler.gen(), sampler.gen());
+                        assert!(m.z() >= 0. && dot(&wo, &m) >= -1e-12);
+                        m.z()
+                    })
+                    .sum::<f64>() / n as f64;
+                let expected = integrate_normals(|m| m.z() * ggx.pdf_visible(&wo, &m)) / integral;
+                assert!((mean_cos - expected).abs() < 0.02, "alpha {} theta {}: {} vs {}", alpha, theta, mean_cos, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn fresnel() {
+        // Normal incidence on glass reflects 4%, and grazing light all of it.
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-12);
+        assert!((fresnel_dielectric(0., 1.5) - 1.).abs() < 1e-12);
+        // From inside glass, past the critical angle.
+        assert_eq!(fresnel_dielectric(0.5, 1. / 1.5), 1.);
+
+        // With no absorption a conductor is a dielectric.
+        for cos in [1., 0.7, 0.2] {
+            assert!((fresnel_conductor(cos, 1.5, 0.) - fresnel_dielectric(cos, 1.5)).abs() < 1e-9);
+        }
+        // ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2) head on.
+        assert!((fresnel_conductor(1., 0.2, 3.9) - (0.64 + 15.21) / (1.44 + 15.21)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn refraction_and_frames() {
+        let m = Vec3(0., 0., 1.);
+        let wo = direction(30.);
+        let wi = refract_through(&wo, &m, 1.5).unwrap();
+        // Snell: sin 30° = 1.5 sin θt, on the far side.
+        assert!((wi.x() + 0.5 / 1.5).abs() < 1e-12 && wi.z() < 0. && (wi.length() - 1.).abs() < 1e-12);
+        assert!(refract_through(&direction(60.), &m, 1. / 1.5).is_none());
+        assert!(reflect_about(&wo, &m).is_close(Vec3(-0.5, 0., 0.75f64.sqrt())));
+
+        let frame = Frame::new(Vec3(1., 2., 2.) / 3.);
+        let v = Vec3(0.3, -0.4, 0.5);
+        assert!(frame.to_world(&frame.to_local(&v)).is_close(v));
+        assert!((frame.to_local(&Vec3(1., 2., 2.)).z() - 3.).abs() < 1e-12);
+    }
+}
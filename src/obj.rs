@@ -0,0 +1,506 @@
+use num::Float;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+use crate::common::SVecElem;
+use crate::hittable_list::*;
+use crate::materials::*;
+use crate::principled::*;
+use crate::triangle::*;
+use crate::vec3::*;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+type MaterialMap<'a, T> = HashMap<String, Arc<dyn Material<T> + 'a>>;
+
+/// Triangles of an OBJ file. Those of emissive materials are listed again
+/// in `lights`, sharing the same geometry, for sampling as lights.
+pub struct ObjModel<'a, T: SVecElem> {
+    pub triangles: HittableList<'a, T>,
+    pub lights: HittableList<'a, T>,
+}
+
+/// Loads an OBJ file, resolving `mtllib` statements relative to its directory.
+pub fn load_obj<'a, T>(path: impl AsRef<Path>) -> Result<ObjModel<'a, T>, ObjError>
+where
+    T: 'a + SVecElem + Float,
+{
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|source| ObjError::Io { path: path.to_owned(), source })?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("")).to_owned();
+    parse_obj(&source, path, |name| {
+        let mtl_path = dir.join(name);
+        let source = fs::read_to_string(&mtl_path).map_err(|source| ObjError::Io { path: mtl_path.clone(), source })?;
+        Ok((mtl_path, source))
+    })
+}
+
+struct Parser<'s> {
+    path: &'s Path,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> ObjError {
+        ObjError::Parse { path: self.path.to_owned(), line: self.line, message: message.into() }
+    }
+
+    fn float(&self, tokens: &mut SplitWhitespace, what: &str) -> Result<f64, ObjError> {
+        let token = tokens.next().ok_or_else(|| self.error(format!("missing {}", what)))?;
+        token.parse().map_err(|_| self.error(format!("invalid number `{}` for {}", token, what)))
+    }
+
+    fn floats<const N: usize>(&self, tokens: &mut SplitWhitespace, what: &str) -> Result<[f64; N], ObjError> {
+        let mut values = [0.; N];
+        for v in values.iter_mut() {
+            *v = self.float(tokens, what)?;
+        }
+        Ok(values)
+    }
+
+    fn name<'t>(&self, tokens: &mut SplitWhitespace<'t>, what: &str) -> Result<&'t str, ObjError> {
+        tokens.next().ok_or_else(|| self.error(format!("missing {}", what)))
+    }
+
+    /// Resolves a 1-based (or negative, relative) OBJ index against `count` elements.
+    fn index(&self, token: &str, count: usize, what: &str) -> Result<usize, ObjError> {
+        let index: i64 = token.parse().map_err(|_| self.error(format!("invalid {} index `{}`", what, token)))?;
+        let resolved = match index {
+            0 => None,
+            i if i > 0 => Some(i - 1),
+            i => Some(count as i64 + i),
+        };
+        match resolved {
+            Some(i) if i >= 0 && (i as usize) < count => Ok(i as usize),
+            _ => Err(self.error(format!("{} index {} out of range ({} defined)", what, index, count))),
+        }
+    }
+}
+
+/// Position, texture and normal indices of one face corner.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct Batch {
+    material: Option<String>,
+    faces: Vec<[Corner; 3]>,
+}
+
+/// Parses OBJ `source`. `load_mtl` maps a `mtllib` name to the path and
+/// contents of the material library.
+pub fn parse_obj<'a, T, F>(source: &str, path: &Path, mut load_mtl: F) -> Result<ObjModel<'a, T>, ObjError>
+where
+    T: 'a + SVecElem + Float,
+    F: FnMut(&str) -> Result<(PathBuf, String), ObjError>,
+{
+    let mut parser = Parser { path, line: 0 };
+    let mut positions: Vec<Point3<T>> = Vec::new();
+    let mut tex_coords: Vec<TexCoord<T>> = Vec::new();
+    let mut normals: Vec<Vec3<T>> = Vec::new();
+    let mut materials: MaterialMap<T> = HashMap::new();
+    let mut batches = vec![Batch { material: None, faces: Vec::new() }];
+
+    for (number, line) in source.lines().enumerate() {
+        parser.line = number + 1;
+        let line = line.split('#').next().unwrap();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else { continue };
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = parser.floats(&mut tokens, "vertex coordinate")?;
+                positions.push(Point3::new(x, y, z));
+            }
+            "vt" => {
+                let u = parser.float(&mut tokens, "texture coordinate")?;
+                let v = match tokens.next() {
+                    Some(token) => token.parse().map_err(|_| parser.error(format!("invalid number `{}` for texture coordinate", token)))?,
+                    None => 0.,
+                };
+                tex_coords.push((T::from_f64(u).unwrap(), T::from_f64(v).unwrap()));
+            }
+            "vn" => {
+                let [x, y, z] = parser.floats(&mut tokens, "normal coordinate")?;
+                normals.push(Vec3::new(x, y, z));
+            }
+            "f" => {
+                let mut corners = Vec::new();
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let v = parser.index(parts.next().unwrap(), positions.len(), "vertex")?;
+                    let vt = match parts.next() {
+                        Some("") | None => None,
+                        Some(t) => Some(parser.index(t, tex_coords.len(), "texture coordinate")?),
+                    };
+                    let vn = match parts.next() {
+                        Some("") | None => None,
+                        Some(n) => Some(parser.index(n, normals.len(), "normal")?),
+                    };
+                    if parts.next().is_some() {
+                        return Err(parser.error(format!("malformed face vertex `{}`", token)));
+                    }
+                    corners.push((v, vt, vn));
+                }
+                if corners.len() < 3 {
+                    return Err(parser.error(format!("face needs at least 3 vertices, got {}", corners.len())));
+                }
+                let batch = batches.last_mut().unwrap();
+                for i in 1..corners.len() - 1 {
+                    batch.faces.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            "g" | "o" => {
+                let material = batches.last().unwrap().material.clone();
+                batches.push(Batch { material, faces: Vec::new() });
+            }
+            "usemtl" => {
+                let name = parser.name(&mut tokens, "material name")?;
+                if !materials.contains_key(name) {
+                    return Err(parser.error(format!("unknown material `{}`", name)));
+                }
+                batches.push(Batch { material: Some(name.to_owned()), faces: Vec::new() });
+            }
+            "mtllib" => {
+                let mut any = false;
+                for name in tokens {
+                    let (mtl_path, mtl_source) = load_mtl(name)?;
+                    materials.extend(parse_mtl(&mtl_source, &mtl_path)?);
+                    any = true;
+                }
+                if !any {
+                    return Err(parser.error("missing material library name"));
+                }
+            }
+            // Smoothing groups, free-form geometry and friends carry nothing we render.
+            _ => {}
+        }
+    }
+
+    let default_material: Arc<dyn Material<T> + 'a> = Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5)));
+    let mut model = ObjModel { triangles: HittableList::new(), lights: HittableList::new() };
+    for batch in batches.into_iter().filter(|b| !b.faces.is_empty()) {
+        let material = match &batch.material {
+            Some(name) => Arc::clone(&materials[name]),
+            None => Arc::clone(&default_material),
+        };
+        let emissive = material.is_emissive();
+        let mesh = Arc::new(build_mesh(&batch.faces, &positions, &tex_coords, &normals, material));
+        model.triangles.extend(mesh.triangles());
+        if emissive {
+            model.lights.extend(mesh.triangles());
+        }
+    }
+    Ok(model)
+}
+
+/// Re-indexes OBJ corners so that every attribute shares one index buffer.
+/// Normals and UVs are only kept if every corner of the batch has them.
+fn build_mesh<'a, T>(
+    faces: &[[Corner; 3]],
+    positions: &[Point3<T>],
+    tex_coords: &[TexCoord<T>],
+    normals: &[Vec3<T>],
+    material: Arc<dyn Material<T> + 'a>,
+) -> TriangleMesh<'a, T>
+where
+    T: 'a + SVecElem + Float,
+{
+    let has_uvs = faces.iter().flatten().all(|c| c.1.is_some());
+    let has_normals = faces.iter().flatten().all(|c| c.2.is_some());
+
+    let mut remap: HashMap<Corner, usize> = HashMap::new();
+    let mut mesh_positions = Vec::new();
+    let mut mesh_uvs = Vec::new();
+    let mut mesh_normals = Vec::new();
+    let mut indices = Vec::with_capacity(faces.len());
+
+    for face in faces {
+        indices.push(face.map(|corner| {
+            let key = (corner.0, corner.1.filter(|_| has_uvs), corner.2.filter(|_| has_normals));
+            *remap.entry(key).or_insert_with(|| {
+                mesh_positions.push(positions[key.0]);
+                if let Some(vt) = key.1 { mesh_uvs.push(tex_coords[vt]); }
+                if let Some(vn) = key.2 { mesh_normals.push(normals[vn]); }
+                mesh_positions.len() - 1
+            })
+        }));
+    }
+
+    TriangleMesh {
+        positions: Arc::new(mesh_positions),
+        normals: if has_normals { Some(Arc::new(mesh_normals)) } else { None },
+        uvs: if has_uvs { Some(Arc::new(mesh_uvs)) } else { None },
+        indices: Arc::new(indices),
+        material,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MtlEntry {
+    kd: [f64; 3],
+    ks: [f64; 3],
+    ke: [f64; 3],
+    ns: f64,
+    ni: f64,
+    d: f64,
+    illum: u32,
+}
+
+impl Default for MtlEntry {
+    fn default() -> Self {
+        Self { kd: [0.8, 0.8, 0.8], ks: [0., 0., 0.], ke: [0., 0., 0.], ns: 0., ni: 1.5, d: 1., illum: 2 }
+    }
+}
+
+impl MtlEntry {
+    /// Maps the Phong-style MTL parameters onto the closest material we have:
+    /// an emissive `Ke` makes a `DiffuseLight`, transparent or refracting
+    /// models become `Dielectric`, models with ray-traced reflection become a
+    /// metallic `Principled` GGX lobe (see `blinn_phong_roughness`), and
+    /// everything else is `Lambertian` with the diffuse colour.
+    fn to_material<'a, T>(&self) -> Arc<dyn Material<T> + 'a>
+    where
+        T: 'a + SVecElem + Float,
+    {
+        if self.ke.iter().any(|&c| c > 0.) {
+            let [r, g, b] = self.ke;
+            Arc::new(DiffuseLight { emit: Color3::new(r, g, b) })
+        } else if self.d < 1. || matches!(self.illum, 4 | 6 | 7 | 9) {
+            Arc::new(Dielectric::new(T::from_f64(self.ni).unwrap()))
+        } else if matches!(self.illum, 3 | 5 | 8) {
+            let [r, g, b] = if self.ks.iter().any(|&c| c > 0.) { self.ks } else { self.kd };
+            let roughness = T::from_f64(blinn_phong_roughness(self.ns)).unwrap();
+            Arc::new(Principled {
+                metallic: constant(T::one()),
+                roughness: constant(roughness),
+                ..Principled::new(Color3::new(r, g, b))
+            })
+        } else {
+            let [r, g, b] = self.kd;
+            Arc::new(Lambertian::new(Color3::new(r, g, b)))
+        }
+    }
+}
+
+/// Perceptual GGX roughness for a Blinn–Phong exponent `Ns`. Walter et al.
+/// (2007) match the exponent to a microfacet width α = sqrt(2 / (Ns + 2)),
+/// so `Ns` = 0 is fully rough (α = 1) and α falls towards a mirror as `Ns`
+/// grows; the result is sqrt(α), since `Ggx::from_roughness` squares it.
+fn blinn_phong_roughness(ns: f64) -> f64 {
+    (2. / (ns.max(0.) + 2.)).sqrt().sqrt()
+}
+
+/// Parses an MTL material library.
+pub fn parse_mtl<'a, T>(source: &str, path: &Path) -> Result<MaterialMap<'a, T>, ObjError>
+where
+    T: 'a + SVecElem + Float,
+{
+    let mut parser = Parser { path, line: 0 };
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        parser.line = number + 1;
+        let line = line.split('#').next().unwrap();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else { continue };
+
+        if keyword == "newmtl" {
+            let name = parser.name(&mut tokens, "material name")?;
+            entries.push((name.to_owned(), MtlEntry::default()));
+            continue;
+        }
+        let known = matches!(keyword, "Kd" | "Ks" | "Ke" | "Ns" | "Ni" | "d" | "Tr" | "illum");
+        let entry = match entries.last_mut() {
+            Some((_, entry)) => entry,
+            None if known => return Err(parser.error(format!("`{}` before any `newmtl`", keyword))),
+            None => continue,
+        };
+        match keyword {
+            "Kd" => entry.kd = parser.floats(&mut tokens, "diffuse colour")?,
+            "Ks" => entry.ks = parser.floats(&mut tokens, "specular colour")?,
+            "Ke" => entry.ke = parser.floats(&mut tokens, "emissive colour")?,
+            "Ns" => entry.ns = parser.float(&mut tokens, "specular exponent")?,
+            "Ni" => entry.ni = parser.float(&mut tokens, "index of refraction")?,
+            "d" => entry.d = parser.float(&mut tokens, "dissolve")?,
+            "Tr" => entry.d = 1. - parser.float(&mut tokens, "transparency")?,
+            "illum" => {
+                let token = parser.name(&mut tokens, "illumination model")?;
+                entry.illum = token.parse().map_err(|_| parser.error(format!("invalid illumination model `{}`", token)))?;
+            }
+            // Texture maps, ambient colour and the like are ignored.
+            _ => {}
+        }
+    }
+
+    Ok(entries.into_iter().map(|(name, entry)| (name, entry.to_material())).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::*;
+    use crate::ray::*;
+    use crate::sampler::Sampler;
+
+    fn parse(source: &str, mtl: &str) -> Result<HittableList<'static, f64>, ObjError> {
+        parse_model(source, mtl).map(|model| model.triangles)
+    }
+
+    fn parse_model(source: &str, mtl: &str) -> Result<ObjModel<'static, f64>, ObjError> {
+        let mtl = mtl.to_owned();
+        parse_obj(source, Path::new("test.obj"), |name| Ok((PathBuf::from(name), mtl.clone())))
+    }
+
+    fn parse_error_line(source: &str) -> usize {
+        match parse(source, "") {
+            Err(ObjError::Parse { line, .. }) => line,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn quad_fan_with_negative_indices() {
+        let source = "\
+# unit square
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+g square
+f -4//1 -3//1 -2//1 -1//1
+";
+        let world = parse(source, "").unwrap();
+        assert_eq!(world.len(), 2);
+
+        let ray = Ray { origin: Point3(0.9, 0.9, 1.0), direction: Vec3(0.0, 0.0, -1.0), time: 0., wavelengths: None };
+        let rec = world.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 1.0);
+        assert_eq!(rec.normal, Vec3(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn groups_and_materials() {
+        let mtl = "\
+newmtl red
+Kd 1 0 0
+newmtl mirror
+Kd 0.2 0.2 0.2
+Ks 0.9 0.8 0.7
+Ns 1000
+illum 3
+newmtl brushed
+Kd 0.5 0.6 0.7
+illum 3
+newmtl glass
+Ni 1.33
+d 0.2
+newmtl lamp
+Kd 0.1 0.1 0.1
+Ke 4 3 2
+";
+        // One triangle per group, side by side along x.
+        let mut source = String::from("mtllib scene.mtl\n");
+        for (i, name) in ["red", "mirror", "brushed", "glass", "lamp"].iter().enumerate() {
+            source += &format!("v {0} 0 0\nv {1} 0 0\nv {0} 1 0\ng {2}\nusemtl {2}\nf -3 -2 -1\n", 2 * i, 2 * i + 1, name);
+        }
+        let model = parse_model(&source, mtl).unwrap();
+        let world = model.triangles;
+        assert_eq!(world.len(), 5);
+        assert_eq!(model.lights.len(), 1);
+        let material = |i: usize| {
+            let ray = Ray { origin: Point3(2. * i as f64 + 0.25, 0.25, 1.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+            world.hit(&ray, 0.001, f64::INFINITY).unwrap().material
+        };
+        let black = Color3::new(0., 0., 0.);
+        let rec = |material| HitRecord { p: Point3(0., 0., 0.), normal: Vec3(0., 0., 1.), material, t: 1., u: 0., v: 0., front_face: true };
+        let incoming = Ray { origin: Point3(-1., 0., 1.), direction: Vec3(1., 0., -1.), time: 0., wavelengths: None };
+        let mut sampler = Sampler::new(0, 0);
+        let mut scatter = |i| {
+            let rec = rec(material(i));
+            (rec.material.emitted(&rec), rec.material.scatter(&incoming, &rec, &mut sampler))
+        };
+
+        // Lambertian with the diffuse colour.
+        let (emitted, red) = scatter(0);
+        let red = red.unwrap();
+        assert!(emitted == black && !red.is_specular() && red.attenuation == Color3::new(1., 0., 0.));
+
+        // A glossy metal tinted by Ks, tight around the mirror direction,
+        // and a rough one falling back to Kd.
+        let mut mirror_cos = |i, tint: fn(&Color3<f64>) -> bool| {
+            let samples: Vec<_> = (0..200).filter_map(|_| scatter(i).1).collect();
+            assert!(samples.iter().all(|s| !s.is_specular() && tint(&s.attenuation)));
+            let mirror = Vec3(1., 0., 1.).to_unit();
+            samples.iter().map(|s| dot(&s.ray.direction.to_unit(), &mirror)).sum::<f64>() / samples.len() as f64
+        };
+        assert!(mirror_cos(1, |a| a.x() > a.y() && a.y() > a.z()) > 0.98);
+        assert!(mirror_cos(2, |a| a.x() < a.y() && a.y() < a.z()) < 0.95);
+
+        // Glass bending light by Ni.
+        let refracted = (0..100)
+            .filter_map(|_| scatter(3).1)
+            .find(|s| s.ray.direction.z() < 0.)
+            .unwrap();
+        assert!(refracted.is_specular() && refracted.attenuation == Color3::new(1., 1., 1.));
+        let sin_t = refracted.ray.direction.to_unit().x();
+        assert!((sin_t * 1.33 - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-9, "{}", sin_t);
+
+        // A light glowing with Ke.
+        let (emitted, lamp) = scatter(4);
+        assert!(emitted == Color3::new(4., 3., 2.) && lamp.is_none());
+    }
+
+    #[test]
+    fn specular_exponent() {
+        // Ns = 0 is fully rough, Ns = 1000 (the usual maximum) is nearly a
+        // mirror, with α = sqrt(2 / 1002) in between.
+        assert_eq!(blinn_phong_roughness(0.), 1.);
+        assert!((blinn_phong_roughness(1000.).powi(2) - (2. / 1002_f64).sqrt()).abs() < 1e-12);
+        assert!(blinn_phong_roughness(1000.) < blinn_phong_roughness(10.));
+        assert_eq!(blinn_phong_roughness(-5.), 1.);
+    }
+
+    #[test]
+    fn error_lines() {
+        assert_eq!(parse_error_line("v 0 0 0\nv 1 0\n"), 2);
+        assert_eq!(parse_error_line("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n"), 5);
+        assert_eq!(parse_error_line("v 0 0 0\nf 1 0 1\n"), 2);
+        assert_eq!(parse_error_line("v 0 0 0\nv 1 0 0\nf 1 2\n"), 3);
+        assert_eq!(parse_error_line("usemtl missing\n"), 1);
+        assert_eq!(parse_error_line("v 0 x 0\n"), 1);
+    }
+
+    #[test]
+    fn mtl_error_line() {
+        let result = parse_mtl::<f64>("newmtl a\nKd 1 1\n", Path::new("test.mtl"));
+        match result {
+            Err(ObjError::Parse { path, line, .. }) => {
+                assert_eq!(path, Path::new("test.mtl"));
+                assert_eq!(line, 2);
+            }
+            _ => panic!("expected a parse error"),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+/// Octave settings shared by the fractal sums.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Octaves {
+    pub count: u32,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f64,
+    /// Amplitude multiplier between octaves.
+    pub gain: f64,
+}
+
+impl Default for Octaves {
+    fn default() -> Self {
+        Self { count: 6, lacunarity: 2., gain: 0.5 }
+    }
+}
+
+/// Ken Perlin's improved gradient noise, with the permutation table
+/// shuffled from a seed.
+#[derive(Debug, Clone)]
+pub struct Perlin {
+    perm: [u8; 512],
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Dot product with one of the 12 cube edge directions picked by `hash`.
+fn grad(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(&mut StdRng::seed_from_u64(seed));
+        let mut perm = [0; 512];
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = table[i & 255];
+        }
+        Self { perm }
+    }
+
+    /// Noise in about [-1, 1]; zero at every integer lattice point.
+    pub fn noise(&self, p: [f64; 3]) -> f64 {
+        let cell = p.map(|v| v.floor());
+        let [x, y, z] = [p[0] - cell[0], p[1] - cell[1], p[2] - cell[2]];
+        let [xi, yi, zi] = cell.map(|v| (v as i64 & 255) as usize);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let perm = &self.perm;
+        let a = perm[xi] as usize + yi;
+        let (aa, ab) = (perm[a] as usize + zi, perm[a + 1] as usize + zi);
+        let b = perm[xi + 1] as usize + yi;
+        let (ba, bb) = (perm[b] as usize + zi, perm[b + 1] as usize + zi);
+
+        lerp(w,
+            lerp(v,
+                lerp(u, grad(perm[aa], x, y, z), grad(perm[ba], x - 1., y, z)),
+                lerp(u, grad(perm[ab], x, y - 1., z), grad(perm[bb], x - 1., y - 1., z))),
+            lerp(v,
+                lerp(u, grad(perm[aa + 1], x, y, z - 1.), grad(perm[ba + 1], x - 1., y, z - 1.)),
+                lerp(u, grad(perm[ab + 1], x, y - 1., z - 1.), grad(perm[bb + 1], x - 1., y - 1., z - 1.))))
+    }
+
+    /// Sums `octaves.count` rescaled copies of `f`, normalised by the total
+    /// amplitude.
+    fn octave_sum(&self, p: [f64; 3], octaves: &Octaves, mut f: impl FnMut(f64) -> f64) -> f64 {
+        let (mut sum, mut norm) = (0., 0.);
+        let (mut amplitude, mut frequency) = (1., 1.);
+        for _ in 0..octaves.count.max(1) {
+            sum += amplitude * f(self.noise(p.map(|v| v * frequency)));
+            norm += amplitude;
+            amplitude *= octaves.gain;
+            frequency *= octaves.lacunarity;
+        }
+        sum / norm
+    }
+
+    /// Fractional Brownian motion, in about [-1, 1].
+    pub fn fbm(&self, p: [f64; 3], octaves: &Octaves) -> f64 {
+        self.octave_sum(p, octaves, |n| n)
+    }
+
+    /// Sum of absolute noise, in about [0, 1], with creases at the zero
+    /// crossings.
+    pub fn turbulence(&self, p: [f64; 3], octaves: &Octaves) -> f64 {
+        self.octave_sum(p, octaves, f64::abs)
+    }
+
+    /// Musgrave's ridged multifractal in [0, 1]: sharp crests where the noise
+    /// crosses zero, with each octave weighted by the one before so detail
+    /// gathers along the ridges.
+    pub fn ridged(&self, p: [f64; 3], octaves: &Octaves) -> f64 {
+        let mut weight = 1.;
+        self.octave_sum(p, octaves, |n| {
+            let signal = (1. - n.abs()).powi(2) * weight;
+            weight = (signal * 2.).clamp(0., 1.);
+            signal
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> impl Iterator<Item = [f64; 3]> {
+        (0..2000).map(|i| {
+            let i = i as f64;
+            [i * 0.137 - 50., (i * 0.311).sin() * 20., i * 0.0173]
+        })
+    }
+
+    #[test]
+    fn zero_on_lattice() {
+        let perlin = Perlin::new(1);
+        for p in [[0., 0., 0.], [3., -7., 12.], [255., 256., -1.]] {
+            assert_eq!(perlin.noise(p), 0.);
+        }
+    }
+
+    #[test]
+    fn seeded_tables() {
+        let p = [1.3, 2.7, -0.4];
+        assert_eq!(Perlin::new(5).noise(p), Perlin::new(5).noise(p));
+        assert_ne!(Perlin::new(5).noise(p), Perlin::new(6).noise(p));
+    }
+
+    #[test]
+    fn bounded_and_continuous() {
+        let perlin = Perlin::new(0);
+        let octaves = Octaves::default();
+        for p in points() {
+            let n = perlin.noise(p);
+            assert!(n.abs() <= 1.05, "{} at {:?}", n, p);
+            let near = perlin.noise([p[0] + 1e-4, p[1], p[2]]);
+            assert!((n - near).abs() < 1e-3);
+
+            assert!(perlin.fbm(p, &octaves).abs() <= 1.05);
+            assert!((0. ..=1.05).contains(&perlin.turbulence(p, &octaves)));
+            assert!((0. ..=1.).contains(&perlin.ridged(p, &octaves)));
+        }
+    }
+
+    #[test]
+    fn single_octave() {
+        let perlin = Perlin::new(3);
+        let one = Octaves { count: 1, ..Default::default() };
+        let p = [0.3, 4.2, -1.9];
+        assert_eq!(perlin.fbm(p, &one), perlin.noise(p));
+        assert_eq!(perlin.turbulence(p, &one), perlin.noise(p).abs());
+    }
+}
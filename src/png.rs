@@ -0,0 +1,197 @@
+use std::io::{self, Write};
+
+use crate::image::*;
+use crate::vec3::*;
+use crate::zlib::*;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// 8-bit truecolour PNG with unfiltered scanlines and stored zlib data.
+pub struct Png;
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Reverses the per-scanline filters in place; `bpp` is bytes per pixel.
+fn unfilter(data: &mut [u8], stride: usize, bpp: usize) -> Result<(), String> {
+    let mut previous = vec![0u8; stride];
+    for line in data.chunks_mut(stride + 1) {
+        let (filter, row) = line.split_first_mut().unwrap();
+        for i in 0..row.len() {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = previous[i];
+            let c = if i >= bpp { previous[i - bpp] } else { 0 };
+            row[i] = row[i].wrapping_add(match *filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                f => return Err(format!("invalid PNG filter type {}", f)),
+            });
+        }
+        previous.copy_from_slice(row);
+    }
+    Ok(())
+}
+
+/// Decodes a non-interlaced PNG with 8 or 16 bits per sample into 8-bit RGB.
+/// Grey, palette and alpha variants are supported; alpha is dropped.
+pub fn decode_png(bytes: &[u8]) -> Result<RgbImage, String> {
+    if bytes.get(..8) != Some(&SIGNATURE[..]) {
+        return Err("missing PNG signature".into());
+    }
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut idat = Vec::new();
+    loop {
+        let len = bytes.get(pos..pos + 4).ok_or("truncated PNG chunk")?;
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        let chunk = bytes.get(pos + 4..pos + 12 + len).ok_or("truncated PNG chunk")?;
+        let (kind, rest) = chunk.split_at(4);
+        let (data, crc) = rest.split_at(len);
+        if crc32_update(crc32(kind), data).to_be_bytes() != crc {
+            return Err(format!("CRC mismatch in {} chunk", String::from_utf8_lossy(kind)));
+        }
+        pos += 12 + len;
+        match kind {
+            b"IHDR" if data.len() == 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"IDAT" => idat.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or("missing IHDR chunk")?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap());
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if width == 0 || height == 0 {
+        return Err(format!("empty {}x{} PNG image", width, height));
+    }
+    if interlace != 0 {
+        return Err("interlaced PNG images are not supported".into());
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("invalid PNG colour type {}", color_type)),
+    };
+    if !(depth == 8 || depth == 16 && color_type != 3) {
+        return Err(format!("unsupported PNG bit depth {} for colour type {}", depth, color_type));
+    }
+
+    let bpp = channels * depth as usize / 8;
+    let stride = width as usize * bpp;
+    let mut data = decompress(&idat)?;
+    if data.len() != (stride + 1) * height as usize {
+        return Err("PNG image data has the wrong size".into());
+    }
+    unfilter(&mut data, stride, bpp)?;
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for line in data.chunks(stride + 1) {
+        // Keep the most significant byte of 16-bit samples.
+        let samples: Vec<u8> = line[1..].iter().step_by(depth as usize / 8).copied().collect();
+        for px in samples.chunks(channels) {
+            pixels.push(match color_type {
+                0 | 4 => Color3(px[0], px[0], px[0]),
+                3 => {
+                    let entry = palette.get(px[0] as usize * 3..px[0] as usize * 3 + 3).ok_or("palette index out of range")?;
+                    Color3(entry[0], entry[1], entry[2])
+                }
+                _ => Color3(px[0], px[1], px[2]),
+            });
+        }
+    }
+    Ok(RgbImage { width, height, pixels })
+}
+
+fn write_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32_update(crc32(kind), data).to_be_bytes())
+}
+
+impl ImageWriter for Png {
+    fn write(&self, out: &mut dyn Write, pixels: &[Color3<u8>], width: u32, height: u32) -> io::Result<()> {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        out.write_all(&SIGNATURE)?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        // Bit depth 8, colour type 2 (RGB), deflate, adaptive filtering, no interlace.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(out, b"IHDR", &header)?;
+
+        let mut scanlines = Vec::with_capacity(pixels.len() * 3 + height as usize);
+        for row in pixels.chunks(width as usize) {
+            scanlines.push(0); // filter type None
+            scanlines.extend(row.iter().flat_map(|&Color3(r, g, b)| [r, g, b]));
+        }
+        write_chunk(out, b"IDAT", &compress_stored(&scanlines))?;
+        write_chunk(out, b"IEND", &[])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiny_image() {
+        let pixels = [Color3(255, 0, 0), Color3(0, 255, 0), Color3(0, 0, 255), Color3(1, 2, 3)];
+        let mut out = Vec::new();
+        Png.write(&mut out, &pixels, 2, 2).unwrap();
+        assert_eq!(out, [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0xfd, 0xd4, 0x9a,
+            0x73, 0x00, 0x00, 0x00, 0x19, 0x49, 0x44, 0x41, 0x54, 0x78, 0x01, 0x01, 0x0e, 0x00, 0xf1, 0xff,
+            0x00, 0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0x01, 0x02, 0x03, 0x19, 0xfe,
+            0x03, 0x04, 0x8c, 0x41, 0xae, 0x93, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42,
+            0x60, 0x82,
+        ]);
+    }
+
+    #[test]
+    fn decode_roundtrip() {
+        let pixels = vec![Color3(255, 0, 0), Color3(0, 255, 0), Color3(0, 0, 255), Color3(1, 2, 3), Color3(9, 8, 7), Color3(4, 5, 6)];
+        let mut out = Vec::new();
+        Png.write(&mut out, &pixels, 3, 2).unwrap();
+        assert_eq!(decode_png(&out).unwrap(), RgbImage { width: 3, height: 2, pixels });
+    }
+
+    #[test]
+    fn decode_filtered_rgba() {
+        // 3x2 RGBA written by hand with Python's zlib: Sub on the first row,
+        // Paeth on the second, and an ancillary tEXt chunk to skip.
+        let png = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00,
+            0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x08, 0x06, 0x00, 0x00, 0x00, 0x9d, 0x74, 0x66, 0x1a, 0x00, 0x00, 0x00,
+            0x03, 0x74, 0x45, 0x58, 0x74, 0x61, 0x00, 0x62, 0xdc, 0x49, 0xa2, 0x3b, 0x00, 0x00, 0x00, 0x23, 0x49, 0x44,
+            0x41, 0x54, 0x78, 0xda, 0x63, 0xe4, 0x3a, 0x21, 0xf7, 0x5f, 0xee, 0x8d, 0x5c, 0xe3, 0xa5, 0x40, 0xb9, 0x06,
+            0x16, 0xa6, 0x6f, 0xac, 0x0c, 0x36, 0x1b, 0x34, 0xea, 0xd9, 0x7e, 0xcd, 0xed, 0x04, 0x00, 0x82, 0x74, 0x0a,
+            0x15, 0x1b, 0x09, 0x25, 0x12, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+        let image = decode_png(&png).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.pixels, [
+            Color3(10, 200, 30), Color3(40, 180, 60), Color3(250, 5, 90),
+            Color3(12, 190, 35), Color3(100, 100, 100), Color3(0, 255, 1),
+        ]);
+
+        let mut corrupt = png;
+        corrupt[70] ^= 0xff;
+        assert!(decode_png(&corrupt).is_err());
+    }
+}
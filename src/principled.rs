@@ -0,0 +1,354 @@
+use num::Float;
+use rand::Rng;
+use std::sync::Arc;
+
+use crate::common::SVecElem;
+use crate::hittable::*;
+use crate::materials::*;
+use crate::microfacet::*;
+use crate::ray::*;
+use crate::sampler::Sampler;
+use crate::texture::*;
+use crate::vec3::*;
+
+/// Disney-style principled BSDF (Burley 2012, 2015): a Burley diffuse base
+/// with sheen, a GGX specular lobe that turns into a conductor as
+/// `metallic` rises, rough-glass transmission tinted by `base_color`, and a
+/// clear GGX coat on top. Every parameter is a texture; the scalar ones
+/// read the mean of its channels, clamped to [0, 1].
+pub struct Principled<T: SVecElem + Float> {
+    pub base_color: Arc<dyn Texture<T>>,
+    pub metallic: Arc<dyn Texture<T>>,
+    pub roughness: Arc<dyn Texture<T>>,
+    /// Dielectric reflectance at normal incidence, scaled so 0.5 is 4%.
+    pub specular: Arc<dyn Texture<T>>,
+    pub sheen: Arc<dyn Texture<T>>,
+    pub clearcoat: Arc<dyn Texture<T>>,
+    pub clearcoat_roughness: Arc<dyn Texture<T>>,
+    pub transmission: Arc<dyn Texture<T>>,
+    /// Index of refraction of the transmitted lobe.
+    pub ior: T,
+}
+
+/// Grey constant texture for the scalar parameters.
+pub fn constant<T: SVecElem + Float>(value: T) -> Arc<dyn Texture<T>> {
+    Arc::new(SolidColor { color: Color3(value, value, value) })
+}
+
+impl<T: SVecElem + Float> Principled<T> {
+    /// Roughness floor, keeping every lobe glossy rather than specular so
+    /// they can all be evaluated for light sampling.
+    pub const MIN_ROUGHNESS: f64 = 0.05;
+
+    /// A plain dielectric of colour `base_color` with the usual defaults.
+    pub fn new(base_color: Color3<T>) -> Self {
+        let half = T::from_f64(0.5).unwrap();
+        Self {
+            base_color: Arc::new(SolidColor { color: base_color }),
+            metallic: constant(T::zero()),
+            roughness: constant(half),
+            specular: constant(half),
+            sheen: constant(T::zero()),
+            clearcoat: constant(T::zero()),
+            clearcoat_roughness: constant(T::from_f64(0.1).unwrap()),
+            transmission: constant(T::zero()),
+            ior: T::from_f64(1.5).unwrap(),
+        }
+    }
+
+    fn lobes(&self, rec: &HitRecord<T>) -> Lobes<T> {
+        let scalar = |texture: &Arc<dyn Texture<T>>| {
+            let c = texture.value(rec.u, rec.v, &rec.p);
+            ((c.x() + c.y() + c.z()) / T::from_f64(3.).unwrap()).max(T::zero()).min(T::one())
+        };
+        let min_roughness = T::from_f64(Self::MIN_ROUGHNESS).unwrap();
+        let base = self.base_color.value(rec.u, rec.v, &rec.p);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness).max(min_roughness);
+        let dielectric_f0 = T::from_f64(0.08).unwrap() * scalar(&self.specular);
+        let white = Color3(T::one(), T::one(), T::one());
+        let transmission = scalar(&self.transmission);
+        Lobes {
+            base,
+            roughness,
+            diffuse: (T::one() - metallic) * (T::one() - transmission),
+            specular: T::one() - (T::one() - metallic) * transmission,
+            transmission: (T::one() - metallic) * transmission,
+            dielectric_f0,
+            f0: white * (dielectric_f0 * (T::one() - metallic)) + base * metallic,
+            ggx: Ggx::from_roughness(roughness),
+            sheen: scalar(&self.sheen),
+            clearcoat: scalar(&self.clearcoat),
+            coat: Ggx::from_roughness(scalar(&self.clearcoat_roughness).max(min_roughness)),
+            glass: RoughDielectric { ir: self.ior, roughness },
+            eta: RoughDielectric { ir: self.ior, roughness }.eta(rec),
+        }
+    }
+}
+
+/// The parameters of a `Principled` material at one hit point, in the form
+/// each lobe needs them.
+struct Lobes<T: SVecElem + Float> {
+    base: Color3<T>,
+    roughness: T,
+    /// Weights of the diffuse (and sheen), specular and transmission lobes.
+    diffuse: T,
+    specular: T,
+    transmission: T,
+    dielectric_f0: T,
+    f0: Color3<T>,
+    ggx: Ggx<T>,
+    sheen: T,
+    clearcoat: T,
+    coat: Ggx<T>,
+    glass: RoughDielectric<T>,
+    eta: T,
+}
+
+/// Schlick's weight `(1 - cos)^5`.
+fn schlick_weight<T: SVecElem + Float>(cos: T) -> T {
+    (T::one() - cos).max(T::zero()).min(T::one()).powi(5)
+}
+
+fn luminance<T: SVecElem + Float>(c: &Color3<T>) -> T {
+    c.x() * T::from_f64(0.2126).unwrap() + c.y() * T::from_f64(0.7152).unwrap() + c.z() * T::from_f64(0.0722).unwrap()
+}
+
+impl<T: SVecElem + Float> Lobes<T> {
+    const COAT_F0: f64 = 0.04;
+
+    fn fresnel(&self, cos: T) -> Color3<T> {
+        let one = Color3(T::one(), T::one(), T::one());
+        self.f0 + (one - self.f0) * schlick_weight(cos)
+    }
+
+    fn coat_fresnel(&self, cos: T) -> T {
+        let f0 = T::from_f64(Self::COAT_F0).unwrap();
+        f0 + (T::one() - f0) * schlick_weight(cos)
+    }
+
+    /// Probabilities of sampling the diffuse, specular, clearcoat and
+    /// transmission lobes, roughly in proportion to the energy each
+    /// reflects towards `wo`.
+    fn selection(&self, wo: &Vec3<T>) -> [T; 4] {
+        let weights = [
+            self.diffuse,
+            self.specular * luminance(&self.fresnel(wo.z())),
+            self.clearcoat * self.coat_fresnel(wo.z()),
+            self.transmission,
+        ];
+        let total = weights.iter().fold(T::zero(), |sum, &w| sum + w);
+        if total <= T::zero() {
+            return [T::zero(); 4];
+        }
+        weights.map(|w| w / total)
+    }
+
+    /// BSDF times cosine, summed over the lobes.
+    fn eval(&self, wo: &Vec3<T>, wi: &Vec3<T>) -> Color3<T> {
+        let (zero, one, four) = (T::zero(), T::one(), T::from_f64(4.).unwrap());
+        let mut f = Color3(zero, zero, zero);
+        if wo.z() <= zero || wi.z() == zero {
+            return f;
+        }
+        if self.transmission > zero {
+            let (glass, _) = self.glass.eval_pdf(wo, wi, self.eta);
+            let tint = if wi.z() < zero { self.base } else { Color3(one, one, one) };
+            f += tint * (glass * self.transmission);
+        }
+        if wi.z() < zero {
+            return f;
+        }
+        let h = (*wo + *wi).to_unit();
+        let cos_d = dot(wi, &h);
+        if self.diffuse > zero {
+            // Burley's diffuse, brighter at grazing angles when rough, under
+            // the light the specular layer lets through each way.
+            let through = |cos: T| one - (self.dielectric_f0 + (one - self.dielectric_f0) * schlick_weight(cos));
+            let fd90 = T::from_f64(0.5).unwrap() + T::from_f64(2.).unwrap() * self.roughness * cos_d * cos_d;
+            let retro = (one + (fd90 - one) * schlick_weight(wi.z())) * (one + (fd90 - one) * schlick_weight(wo.z()));
+            let pi = T::from_f64(std::f64::consts::PI).unwrap();
+            let sheen = self.sheen * schlick_weight(cos_d);
+            let weight = self.diffuse * through(wi.z()) * through(wo.z()) * wi.z();
+            f += (self.base * (retro / pi) + Color3(sheen, sheen, sheen)) * weight;
+        }
+        if self.specular > zero {
+            let d = self.ggx.d(&h) * self.ggx.g2(wo, wi) / (four * wo.z());
+            f += self.fresnel(dot(wo, &h)) * (self.specular * d);
+        }
+        if self.clearcoat > zero {
+            let coat = self.clearcoat * self.coat_fresnel(dot(wo, &h)) * self.coat.d(&h) * self.coat.g2(wo, wi) / (four * wo.z());
+            f += Color3(coat, coat, coat);
+        }
+        f
+    }
+
+    /// Density of `sample` returning `wi`: the lobes' densities, mixed by
+    /// their selection probabilities.
+    fn pdf(&self, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let (zero, four) = (T::zero(), T::from_f64(4.).unwrap());
+        if wo.z() <= zero || wi.z() == zero {
+            return zero;
+        }
+        let [diffuse, specular, coat, transmission] = self.selection(wo);
+        let mut pdf = transmission * self.glass.eval_pdf(wo, wi, self.eta).1;
+        if wi.z() < zero {
+            return pdf;
+        }
+        let h = (*wo + *wi).to_unit();
+        pdf = pdf + diffuse * wi.z() / T::from_f64(std::f64::consts::PI).unwrap();
+        for (probability, ggx) in [(specular, &self.ggx), (coat, &self.coat)] {
+            if probability > zero {
+                pdf = pdf + probability * ggx.g1(wo) * ggx.d(&h) / (four * wo.z());
+            }
+        }
+        pdf
+    }
+
+    /// Picks a lobe and samples a direction from it, or `None` if the
+    /// sample is absorbed.
+    fn sample(&self, wo: &Vec3<T>, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        let [diffuse, specular, coat, _] = self.selection(wo);
+        let u = T::from_f64(sampler.gen()).unwrap();
+        let reflect_off = |ggx: &Ggx<T>, sampler: &mut Sampler| {
+            let m = ggx.sample_visible(wo, T::from_f64(sampler.gen()).unwrap(), T::from_f64(sampler.gen()).unwrap());
+            Some(reflect_about(wo, &m)).filter(|wi| wi.z() > T::zero())
+        };
+        if u < diffuse {
+            let (r, phi) = (sampler.gen::<f64>().sqrt(), sampler.gen_range(0.0..2. * std::f64::consts::PI));
+            let z = (1. - r * r).max(0.).sqrt();
+            Some(Vec3(T::from_f64(r * phi.cos()).unwrap(), T::from_f64(r * phi.sin()).unwrap(), T::from_f64(z).unwrap()))
+        } else if u < diffuse + specular {
+            reflect_off(&self.ggx, sampler)
+        } else if u < diffuse + specular + coat {
+            reflect_off(&self.coat, sampler)
+        } else {
+            self.glass.sample(wo, self.eta, sampler)
+        }
+    }
+}
+
+impl<T> Material<T> for Principled<T>
+where
+    T: SVecElem + Float,
+{
+    fn scatter(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, sampler: &mut Sampler) -> Option<ScatterRecord<T>> {
+        let frame = Frame::new(rec.normal);
+        let wo = frame.to_local(&-ray_in.direction.to_unit());
+        if wo.z() <= T::zero() {
+            return None;
+        }
+        let lobes = self.lobes(rec);
+        let wi = lobes.sample(&wo, sampler)?;
+        let pdf = lobes.pdf(&wo, &wi);
+        if pdf <= T::zero() {
+            return None;
+        }
+        let ray = Ray { origin: rec.p, direction: frame.to_world(&wi), time: ray_in.time, wavelengths: ray_in.wavelengths };
+        Some(ScatterRecord { attenuation: lobes.eval(&wo, &wi) / pdf, ray, pdf: Some(pdf) })
+    }
+
+    fn eval(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> Color3<T> {
+        let frame = Frame::new(rec.normal);
+        let wo = frame.to_local(&-ray_in.direction.to_unit());
+        self.lobes(rec).eval(&wo, &frame.to_local(&direction.to_unit()))
+    }
+
+    fn pdf(&self, ray_in: &Ray<T>, rec: &HitRecord<T>, direction: &Vec3<T>) -> T {
+        let frame = Frame::new(rec.normal);
+        let wo = frame.to_local(&-ray_in.direction.to_unit());
+        self.lobes(rec).pdf(&wo, &frame.to_local(&direction.to_unit()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::random_in_unit_sphere;
+    use crate::materials::tests::*;
+
+    #[test]
+    fn eval_and_pdf_match_scatter() {
+        let ray = Ray { origin: Point3(-1., 0.5, 1.), direction: Vec3(1., -0.5, -1.), time: 0., wavelengths: None };
+        let materials = [
+            Principled::new(Color3::new(0.8, 0.2, 0.1)),
+            Principled { sheen: constant(1.), clearcoat: constant(1.), ..Principled::new(Color3::new(0.8, 0.2, 0.1)) },
+            Principled { metallic: constant(0.5), roughness: constant(0.3), ..Principled::new(Color3::new(0.9, 0.6, 0.3)) },
+            Principled { roughness: constant(0.), ..Principled::new(Color3::new(0.5, 0.5, 0.5)) },
+            Principled { transmission: constant(0.7), roughness: constant(0.2), ..Principled::new(Color3::new(0.7, 0.9, 1.)) },
+        ];
+        let mut sampler = Sampler::new(10, 11);
+        for (i, material) in materials.iter().enumerate() {
+            for front_face in [true, false] {
+                let rec = HitRecord { front_face, ..surface_hit(Arc::new(Isotropic::new(Color3::new(1., 1., 1.)))) };
+                check_scatter_weights(material, &ray, &rec, &mut sampler);
+            }
+            // Opaque ones only reflect, and their densities cover the
+            // hemisphere once.
+            if i < 4 {
+                let rec = surface_hit(Arc::new(Isotropic::new(Color3::new(1., 1., 1.))));
+                let n = 400000;
+                let integral = (0..n).map(|_| material.pdf(&ray, &rec, &random_in_unit_sphere(&mut sampler))).sum::<f64>()
+                    * 4. * std::f64::consts::PI / n as f64;
+                assert!((integral - 1.).abs() < 0.05, "material {}: {}", i, integral);
+            }
+        }
+    }
+
+    #[test]
+    fn limits_match_simpler_materials() {
+        let ray = Ray { origin: Point3(-1., 0.5, 1.), direction: Vec3(1., -0.5, -1.), time: 0., wavelengths: None };
+        let rec = surface_hit(Arc::new(Isotropic::new(Color3::new(1., 1., 1.))));
+        let mut sampler = Sampler::new(12, 13);
+        let white = Color3::new(1., 1., 1.);
+        // White, fully metallic: a perfect GGX mirror.
+        let mirror = Principled { metallic: constant(1.), roughness: constant(0.4), ..Principled::new(white) };
+        let conductor = Conductor::new(white, Color3::new(1e4, 1e4, 1e4), 0.4);
+        // White, fully transmissive: rough glass.
+        let glass = Principled { transmission: constant(1.), roughness: constant(0.4), ..Principled::new(white) };
+        let rough_glass = RoughDielectric { ir: 1.5, roughness: 0.4 };
+        for _ in 0..200 {
+            let direction = random_in_unit_sphere(&mut sampler);
+            if direction.z() > 0. {
+                assert!((mirror.eval(&ray, &rec, &direction) - conductor.eval(&ray, &rec, &direction)).length() < 1e-6);
+            }
+            assert!((glass.eval(&ray, &rec, &direction) - rough_glass.eval(&ray, &rec, &direction)).length() < 1e-9);
+            assert!((glass.pdf(&ray, &rec, &direction) - rough_glass.pdf(&ray, &rec, &direction)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn energy() {
+        // Layers never reflect more than arrives, a metal keeps its colour
+        // and a clear coat adds a little on top of the base.
+        for theta in [0., 45., 75.] {
+            let white = albedo(&Principled { roughness: constant(0.2), ..Principled::new(Color3::new(1., 1., 1.)) }, theta, true);
+            assert!(white > 0.75 && white <= 1., "theta {}: {}", theta, white);
+            let grey = albedo(&Principled::new(Color3::new(0.5, 0.5, 0.5)), theta, true);
+            let coated = albedo(&Principled { clearcoat: constant(1.), ..Principled::new(Color3::new(0.5, 0.5, 0.5)) }, theta, true);
+            assert!(coated > grey, "theta {}: {} vs {}", theta, coated, grey);
+        }
+        let red = Principled { metallic: constant(1.), roughness: constant(0.3), ..Principled::new(Color3::new(0.9, 0.1, 0.1)) };
+        let rec = surface_hit(Arc::new(Isotropic::new(Color3::new(1., 1., 1.))));
+        let ray = Ray { origin: Point3(0., 0., 1.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let color = red.scatter(&ray, &rec, &mut Sampler::new(0, 0)).unwrap().attenuation;
+        assert!(color.x() > 0.5 && color.y() < 0.2, "{:?}", color);
+    }
+
+    #[test]
+    fn textured_parameters() {
+        // Metallic in every other unit cube along x, dielectric between.
+        let split = Arc::new(Checker { scale: 1., even: constant(1.), odd: constant(0.) });
+        let material = Principled { metallic: split, roughness: constant(0.1), ..Principled::new(Color3::new(0.9, 0.1, 0.1)) };
+        let ray = Ray { origin: Point3(0., 0., 1.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let at = |x: f64| HitRecord { p: Point3(x, 0.5, 0.5), ..surface_hit(Arc::new(Isotropic::new(Color3::new(1., 1., 1.)))) };
+        let mirror_direction = Vec3(0., 0., 1.);
+        // The metal has a bright highlight in its own colour, the plastic a
+        // faint white one.
+        let metal = material.eval(&ray, &at(0.5), &mirror_direction);
+        let plastic = material.eval(&ray, &at(1.5), &mirror_direction);
+        assert!(metal.x() > 10. * plastic.x(), "{:?} vs {:?}", metal, plastic);
+        assert!((metal.x() / metal.y() - 9.).abs() < 1e-6 && plastic.x() / plastic.y() < 1.1, "{:?} vs {:?}", metal, plastic);
+    }
+}
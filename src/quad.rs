@@ -0,0 +1,345 @@
+use num::Float;
+use rand::Rng;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::common::SVecElem;
+use crate::hittable::*;
+use crate::hittable_list::*;
+use crate::materials::*;
+use crate::ray::*;
+use crate::sampler::Sampler;
+use crate::vec3::*;
+
+/// Intersects the plane through `point` with unit `normal`, returning `t`.
+fn hit_plane<T>(point: &Point3<T>, normal: &Vec3<T>, ray: &Ray<T>, t_min: T, t_max: T) -> Option<T>
+where
+    T: SVecElem + Float,
+{
+    let denom = dot(normal, &ray.direction);
+    if denom.abs() < T::from_f64(1e-8).unwrap() {
+        return None;
+    }
+    let t = dot(normal, &(*point - ray.origin)) / denom;
+    if t < t_min || t_max < t {
+        return None;
+    }
+    Some(t)
+}
+
+fn planar_record<'a, T>(ray: &Ray<T>, t: T, normal: Vec3<T>, (u, v): (T, T), material: &Arc<dyn Material<T> + 'a>) -> HitRecord<'a, T>
+where
+    T: SVecElem + Float,
+{
+    let front_face = dot(&ray.direction, &normal) < T::zero();
+    HitRecord {
+        t,
+        u,
+        v,
+        p: ray.at(t),
+        material: Arc::clone(material),
+        normal: if front_face { normal } else { -normal },
+        front_face,
+    }
+}
+
+/// Parallelogram with corner `origin` and edges `u` and `v`. The front side
+/// faces `cross(u, v)`; texture coordinates run from 0 to 1 along each edge.
+pub struct Quad<'a, T: SVecElem + Float> {
+    pub origin: Point3<T>,
+    pub u: Vec3<T>,
+    pub v: Vec3<T>,
+    pub material: Arc<dyn Material<T> + 'a>,
+    normal: Vec3<T>,
+    /// `cross(u, v) / |cross(u, v)|²`, for projecting hits onto the edges.
+    w: Vec3<T>,
+}
+
+impl<'a, T: SVecElem + Float> Quad<'a, T> {
+    pub fn new(origin: Point3<T>, u: Vec3<T>, v: Vec3<T>, material: Arc<dyn Material<T> + 'a>) -> Self {
+        let n = cross(&u, &v);
+        Self { origin, u, v, material, normal: n.to_unit(), w: n / dot(&n, &n) }
+    }
+}
+
+impl<T> Hittable<T> for Quad<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        let t = hit_plane(&self.origin, &self.normal, ray, t_min, t_max)?;
+        let planar = ray.at(t) - self.origin;
+        let alpha = dot(&self.w, &cross(&planar, &self.v));
+        let beta = dot(&self.w, &cross(&self.u, &planar));
+        let unit = T::zero()..=T::one();
+        if !unit.contains(&alpha) || !unit.contains(&beta) {
+            return None;
+        }
+        Some(planar_record(ray, t, self.normal, (alpha, beta), &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        // Pad so quads lying in an axis plane still have a volume.
+        let pad = T::from_f64(1e-4).unwrap();
+        let bbox = Aabb::new(self.origin, self.origin + self.u + self.v)
+            .grow(self.origin + self.u)
+            .grow(self.origin + self.v);
+        Some(Aabb::new(bbox.minimum - pad, bbox.maximum + pad))
+    }
+
+    /// Uniform over the area.
+    fn sample_towards(&self, origin: &Point3<T>, _time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        let (a, b) = (T::from_f64(sampler.gen()).unwrap(), T::from_f64(sampler.gen()).unwrap());
+        Some(self.origin + self.u * a + self.v * b - *origin)
+    }
+
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        match self.hit(ray, T::from_f64(RAY_EPSILON).unwrap(), T::infinity()) {
+            Some(rec) => area_to_solid_angle(ray, rec.t, &self.normal, cross(&self.u, &self.v).length()),
+            None => T::zero(),
+        }
+    }
+}
+
+/// Flat disk facing `normal`. `u` is the angle around the centre and `v`
+/// the distance from it, both in [0, 1].
+pub struct Disk<'a, T: SVecElem + Float> {
+    pub center: Point3<T>,
+    pub radius: T,
+    pub material: Arc<dyn Material<T> + 'a>,
+    normal: Vec3<T>,
+    tangent: Vec3<T>,
+    bitangent: Vec3<T>,
+}
+
+impl<'a, T: SVecElem + Float> Disk<'a, T> {
+    pub fn new(center: Point3<T>, normal: Vec3<T>, radius: T, material: Arc<dyn Material<T> + 'a>) -> Self {
+        let normal = normal.to_unit();
+        let (tangent, bitangent) = orthonormal_basis(&normal);
+        Self { center, radius, material, normal, tangent, bitangent }
+    }
+}
+
+impl<T> Hittable<T> for Disk<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        let t = hit_plane(&self.center, &self.normal, ray, t_min, t_max)?;
+        let offset = ray.at(t) - self.center;
+        let r = offset.length();
+        if r > self.radius {
+            return None;
+        }
+        let pi = T::from_f64(std::f64::consts::PI).unwrap();
+        let phi = dot(&offset, &self.bitangent).atan2(dot(&offset, &self.tangent));
+        let u = (phi + pi) / (pi + pi);
+        Some(planar_record(ray, t, self.normal, (u, r / self.radius), &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        // Per axis, the rim reaches radius * sqrt(1 - n_a^2) from the centre.
+        let pad = T::from_f64(1e-4).unwrap();
+        let n = self.normal;
+        let reach = |a: T| self.radius * (T::one() - a * a).max(T::zero()).sqrt() + pad;
+        let half = Vec3(reach(n.x()), reach(n.y()), reach(n.z()));
+        Some(Aabb::new(self.center - half, self.center + half))
+    }
+
+    /// Uniform over the area.
+    fn sample_towards(&self, origin: &Point3<T>, _time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        let r = self.radius * T::from_f64(sampler.gen::<f64>().sqrt()).unwrap();
+        let phi = T::from_f64(sampler.gen_range(0.0..2. * std::f64::consts::PI)).unwrap();
+        Some(self.center + (self.tangent * phi.cos() + self.bitangent * phi.sin()) * r - *origin)
+    }
+
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        let area = T::from_f64(std::f64::consts::PI).unwrap() * self.radius * self.radius;
+        match self.hit(ray, T::from_f64(RAY_EPSILON).unwrap(), T::infinity()) {
+            Some(rec) => area_to_solid_angle(ray, rec.t, &self.normal, area),
+            None => T::zero(),
+        }
+    }
+}
+
+/// Infinite plane through `point`. Texture coordinates are distances along
+/// a fixed tangent frame, so they repeat with any wrapping texture.
+pub struct Plane<'a, T: SVecElem + Float> {
+    pub point: Point3<T>,
+    pub material: Arc<dyn Material<T> + 'a>,
+    normal: Vec3<T>,
+    tangent: Vec3<T>,
+    bitangent: Vec3<T>,
+}
+
+impl<'a, T: SVecElem + Float> Plane<'a, T> {
+    pub fn new(point: Point3<T>, normal: Vec3<T>, material: Arc<dyn Material<T> + 'a>) -> Self {
+        let normal = normal.to_unit();
+        let (tangent, bitangent) = orthonormal_basis(&normal);
+        Self { point, material, normal, tangent, bitangent }
+    }
+}
+
+impl<T> Hittable<T> for Plane<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        let t = hit_plane(&self.point, &self.normal, ray, t_min, t_max)?;
+        let offset = ray.at(t) - self.point;
+        let uv = (dot(&offset, &self.tangent), dot(&offset, &self.bitangent));
+        Some(planar_record(ray, t, self.normal, uv, &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        None
+    }
+}
+
+/// Axis-aligned box between two corners, made of six outward-facing quads.
+/// Named to stay clear of `std::boxed::Box`.
+pub struct Cuboid<'a, T: SVecElem + Float> {
+    sides: HittableList<'a, T>,
+}
+
+impl<'a, T: 'a + SVecElem + Float> Cuboid<'a, T> {
+    pub fn new(a: Point3<T>, b: Point3<T>, material: Arc<dyn Material<T> + 'a>) -> Self {
+        let bbox = Aabb::new(a, b);
+        let (min, max) = (bbox.minimum, bbox.maximum);
+        let d = max - min;
+        let dx = Vec3(d.x(), T::zero(), T::zero());
+        let dy = Vec3(T::zero(), d.y(), T::zero());
+        let dz = Vec3(T::zero(), T::zero(), d.z());
+
+        // Edge order keeps every cross(u, v) pointing out of the box.
+        let faces = [
+            (Point3(min.x(), min.y(), max.z()), dx, dy), // front
+            (Point3(max.x(), min.y(), max.z()), -dz, dy), // right
+            (Point3(max.x(), min.y(), min.z()), -dx, dy), // back
+            (Point3(min.x(), min.y(), min.z()), dz, dy), // left
+            (Point3(min.x(), max.y(), max.z()), dx, -dz), // top
+            (Point3(min.x(), min.y(), min.z()), dx, dz), // bottom
+        ];
+        let sides = faces.into_iter()
+            .map(|(origin, u, v)| Box::new(Quad::new(origin, u, v, Arc::clone(&material))) as _)
+            .collect();
+        Self { sides }
+    }
+}
+
+impl<T> Hittable<T> for Cuboid<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        self.sides.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        self.sides.bounding_box()
+    }
+
+    fn sample_towards(&self, origin: &Point3<T>, time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        self.sides.sample_towards(origin, time, sampler)
+    }
+
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        self.sides.pdf_towards(ray)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::tests::sampled_solid_angle;
+
+    fn grey() -> Arc<dyn Material<f64>> {
+        Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5)))
+    }
+
+    fn ray(origin: (f64, f64, f64), direction: (f64, f64, f64)) -> Ray<f64> {
+        Ray { origin: Point3::new(origin.0, origin.1, origin.2), direction: Vec3::new(direction.0, direction.1, direction.2), time: 0., wavelengths: None }
+    }
+
+    #[test]
+    fn quad_hit_and_uv() {
+        let quad = Quad::new(Point3::new(0., 0., 0.), Vec3::new(2., 0., 0.), Vec3::new(0., 4., 0.), grey());
+        let hit = quad.hit(&ray((0.5, 3., 5.), (0., 0., -1.)), 0.001, f64::INFINITY).unwrap();
+        assert_eq!(hit.t, 5.);
+        assert!(hit.front_face);
+        assert_eq!(hit.normal, Vec3::new(0., 0., 1.));
+        assert!((hit.u - 0.25).abs() < 1e-12 && (hit.v - 0.75).abs() < 1e-12);
+
+        let back = quad.hit(&ray((0.5, 3., -5.), (0., 0., 1.)), 0.001, f64::INFINITY).unwrap();
+        assert!(!back.front_face);
+        assert_eq!(back.normal, Vec3::new(0., 0., -1.));
+
+        assert!(quad.hit(&ray((2.5, 3., 5.), (0., 0., -1.)), 0.001, f64::INFINITY).is_none());
+        assert!(quad.hit(&ray((0.5, 3., 5.), (1., 0., 0.)), 0.001, f64::INFINITY).is_none());
+        assert!(quad.hit(&ray((0.5, 3., 5.), (0., 0., -1.)), 0.001, 4.).is_none());
+    }
+
+    #[test]
+    fn disk_hit() {
+        let disk = Disk::new(Point3::new(1., 1., 1.), Vec3::new(0., 2., 0.), 0.5, grey());
+        let hit = disk.hit(&ray((1.3, 3., 1.), (0., -1., 0.)), 0.001, f64::INFINITY).unwrap();
+        assert_eq!(hit.t, 2.);
+        assert!(hit.front_face);
+        assert!((hit.v - 0.6).abs() < 1e-12);
+        assert!(disk.hit(&ray((1.3, 3., 1.5), (0., -1., 0.)), 0.001, f64::INFINITY).is_none());
+
+        let bbox = disk.bounding_box().unwrap();
+        assert!((bbox.minimum.x() - 0.5).abs() < 1e-3 && (bbox.maximum.y() - 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn light_sampling() {
+        // A unit square seen face on from 1 unit away subtends 4 asin(1/5).
+        let square = Quad::new(Point3::new(-0.5, -0.5, -1.), Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.), grey());
+        let expected = 4. * (1f64 / 5.).asin();
+        let estimate = sampled_solid_angle(&square, Point3::new(0., 0., 0.));
+        assert!((estimate - expected).abs() < 0.01, "{} vs {}", estimate, expected);
+        assert_eq!(square.pdf_towards(&ray((0., 0., 0.), (0., 0., 1.))), 0.);
+
+        // A unit disk from 1 unit along its axis subtends 2π (1 - 1/√2).
+        let disk = Disk::new(Point3::new(0., 2., 0.), Vec3::new(0., -1., 0.), 1., grey());
+        let expected = 2. * std::f64::consts::PI * (1. - 0.5f64.sqrt());
+        let estimate = sampled_solid_angle(&disk, Point3::new(0., 1., 0.));
+        assert!((estimate - expected).abs() < 0.02, "{} vs {}", estimate, expected);
+
+        // A box picks one of its sides, the far ones too: straight through
+        // the front face at distance 1 and the back face at distance 2.
+        let cube = Cuboid::new(Point3::new(-0.5, -0.5, -2.), Point3::new(0.5, 0.5, -1.), grey());
+        let cube_pdf = cube.pdf_towards(&ray((0.1, 0.2, 0.), (0., 0., -1.)));
+        assert!((cube_pdf - (1. + 4.) / 6.).abs() < 1e-12, "{}", cube_pdf);
+    }
+
+    #[test]
+    fn plane_is_unbounded() {
+        let plane = Plane::new(Point3::new(0., -1., 0.), Vec3::new(0., 1., 0.), grey());
+        assert!(plane.bounding_box().is_none());
+        let hit = plane.hit(&ray((1e6, 0., -3e5), (0., -1., 0.)), 0.001, f64::INFINITY).unwrap();
+        assert_eq!(hit.t, 1.);
+        assert!(plane.hit(&ray((0., 0., 0.), (0., 1., 0.)), 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn cuboid_faces_outward() {
+        let cuboid = Cuboid::new(Point3::new(1., 2., 3.), Point3::new(-1., 0., 0.), grey());
+        let directions = [(1., 0., 0.), (-1., 0., 0.), (0., 1., 0.), (0., -1., 0.), (0., 0., 1.), (0., 0., -1.)];
+        for (x, y, z) in directions {
+            // From outside along each axis: hits the facing side from the front.
+            let hit = cuboid.hit(&ray((-10. * x, 1. - 10. * y, 1.5 - 10. * z), (x, y, z)), 0.001, f64::INFINITY).unwrap();
+            assert!(hit.front_face);
+            assert_eq!(hit.normal, Vec3::new(-x, -y, -z));
+
+            // From the centre: every wall is seen from behind.
+            let hit = cuboid.hit(&ray((0., 1., 1.5), (x, y, z)), 0.001, f64::INFINITY).unwrap();
+            assert!(!hit.front_face);
+            assert_eq!(hit.normal, Vec3::new(-x, -y, -z));
+        }
+        let bbox = cuboid.bounding_box().unwrap();
+        assert!(bbox.minimum.is_close(Point3::new(-1., 0., 0.) - 1e-4));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::vec3::*;
 use crate::common::SVecElem;
+use crate::spectrum::Wavelengths;
 use num::{Float, abs};
 
 
@@ -9,6 +10,10 @@ where
 {
     pub origin: Point3<T>,
     pub direction: Vec3<T>,
+    /// Instant within the camera shutter interval at which the ray is cast.
+    pub time: T,
+    /// Wavelengths carried by a spectral path; `None` when rendering in RGB.
+    pub wavelengths: Option<Wavelengths>,
 }
 
 impl<T: SVecElem> Ray<T> {
@@ -39,6 +44,8 @@ mod tests {
         let ray = Ray {
             origin: Point3(1.0, 2.0, 3.0),
             direction: Vec3(1.0, 2.0, 3.0),
+            time: 0.0,
+            wavelengths: None,
         };
 
         assert_eq!(ray.at(0.0), Point3(1.0, 2.0, 3.0));
@@ -0,0 +1,549 @@
+use num::Float;
+use rand::Rng;
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+
+use crate::background::*;
+use crate::camera::*;
+use crate::common::*;
+use crate::hittable::*;
+use crate::image::*;
+use crate::ray::*;
+use crate::sampler::*;
+use crate::spectrum::*;
+use crate::vec3::*;
+
+/// Edge length in pixels of the square tiles handed to workers.
+pub const TILE_SIZE: u32 = 32;
+
+/// Radiance arriving along `ray`. At every non-specular bounce both one of
+/// the `lights` and the material are sampled, and the two are combined by
+/// multiple importance sampling; an empty list leaves lights to be found by
+/// random bounces alone. An environment map background is sampled the same
+/// way, as a light of its own.
+///
+/// If the ray carries wavelengths the result holds radiance at each of them,
+/// with the RGB colours of materials, lights and background uplifted to
+/// spectra along the way.
+pub fn ray_color<T>(ray: Ray<T>, world: &dyn Hittable<T>, lights: &dyn Hittable<T>, background: &Background<T>, depth: u32, sampler: &mut Sampler) -> Color3<T>
+where
+    T: SVecElem + Float,
+{
+    trace(ray, world, lights, background, depth, sampler, None)
+}
+
+/// Power heuristic weight (β = 2) for a sample drawn with density `pdf`
+/// when another strategy could have drawn it with density `other`.
+pub fn power_heuristic<T: SVecElem + Float>(pdf: T, other: T) -> T {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b <= T::zero() {
+        return T::zero();
+    }
+    a / (a + b)
+}
+
+/// `bsdf_pdf` is the density the previous bounce drew `ray` with, if it
+/// also sampled the lights, so emission found here shares the credit.
+fn trace<T>(ray: Ray<T>, world: &dyn Hittable<T>, lights: &dyn Hittable<T>, background: &Background<T>, depth: u32, sampler: &mut Sampler, bsdf_pdf: Option<T>) -> Color3<T>
+where
+    T: SVecElem + Float,
+{
+    let black = Color3::new(0.0, 0.0, 0.0);
+    if depth == 0 {
+        return black;
+    }
+    let Some(hit) = world.hit(&ray, T::from_f64(RAY_EPSILON).unwrap(), T::infinity()) else {
+        let mut radiance = spectral(&ray, background.color(&ray));
+        if let Some(pdf) = bsdf_pdf {
+            radiance = radiance * power_heuristic(pdf, background.pdf_towards(&ray));
+        }
+        return radiance;
+    };
+    let mut emitted = spectral(&ray, hit.material.emitted(&hit));
+    if let Some(pdf) = bsdf_pdf {
+        if emitted != black {
+            emitted = emitted * power_heuristic(pdf, lights.pdf_towards(&ray));
+        }
+    }
+    let Some(scatter) = hit.material.scatter(&ray, &hit, sampler) else {
+        return emitted;
+    };
+    let direct = match scatter.is_specular() {
+        true => black,
+        false => sample_lights(&ray, &hit, world, lights, sampler) + sample_background(&ray, &hit, world, background, sampler),
+    };
+    let termination = match (ray.wavelengths, scatter.ray.wavelengths) {
+        (Some(before), Some(after)) => Wavelengths::termination_weight(&before, &after),
+        _ => Color3::new(1., 1., 1.),
+    };
+    let attenuation = spectral(&ray, scatter.attenuation);
+    let indirect = trace(scatter.ray, world, lights, background, depth - 1, sampler, scatter.pdf);
+    emitted + direct + attenuation * termination * indirect
+}
+
+/// `color` at the wavelengths `ray` carries, if any.
+fn spectral<T: SVecElem + Float>(ray: &Ray<T>, color: Color3<T>) -> Color3<T> {
+    match ray.wavelengths {
+        Some(wavelengths) => wavelengths.uplift(&color),
+        None => color,
+    }
+}
+
+/// Light reflected at `hit` from one sampled point on the `lights`, dimmed
+/// by the transmittance of everything in between, weighted against the
+/// material's own sampling. Media along the way let part of the light
+/// through rather than all or nothing.
+fn sample_lights<T>(ray_in: &Ray<T>, hit: &HitRecord<T>, world: &dyn Hittable<T>, lights: &dyn Hittable<T>, sampler: &mut Sampler) -> Color3<T>
+where
+    T: SVecElem + Float,
+{
+    let black = Color3::new(0.0, 0.0, 0.0);
+    let Some(direction) = lights.sample_towards(&hit.p, ray_in.time, sampler) else {
+        return black;
+    };
+    let shadow = Ray { origin: hit.p, direction, time: ray_in.time, wavelengths: ray_in.wavelengths };
+    let light_pdf = lights.pdf_towards(&shadow);
+    let f = hit.material.eval(ray_in, hit, &direction);
+    if light_pdf <= T::zero() || f == black {
+        return black;
+    }
+    let epsilon = T::from_f64(RAY_EPSILON).unwrap();
+    let Some(light) = lights.hit(&shadow, epsilon, T::infinity()) else {
+        return black;
+    };
+    // Stop short of the light, which is part of the world too.
+    let transmittance = world.transmittance(&shadow, epsilon, light.t - epsilon / direction.length(), sampler);
+    if transmittance <= T::zero() {
+        return black;
+    }
+    let weight = power_heuristic(light_pdf, hit.material.pdf(ray_in, hit, &direction));
+    spectral(ray_in, light.material.emitted(&light)) * spectral(ray_in, f) * (transmittance * weight / light_pdf)
+}
+
+/// Light reaching `hit` from one direction drawn from the background,
+/// dimmed by the transmittance of the world along the way, weighted against
+/// the material's own sampling.
+fn sample_background<T>(ray_in: &Ray<T>, hit: &HitRecord<T>, world: &dyn Hittable<T>, background: &Background<T>, sampler: &mut Sampler) -> Color3<T>
+where
+    T: SVecElem + Float,
+{
+    let black = Color3::new(0.0, 0.0, 0.0);
+    let Some(direction) = background.sample_towards(sampler) else {
+        return black;
+    };
+    let escape = Ray { origin: hit.p, direction, time: ray_in.time, wavelengths: ray_in.wavelengths };
+    let background_pdf = background.pdf_towards(&escape);
+    let f = hit.material.eval(ray_in, hit, &direction);
+    if background_pdf <= T::zero() || f == black {
+        return black;
+    }
+    let transmittance = world.transmittance(&escape, T::from_f64(RAY_EPSILON).unwrap(), T::infinity(), sampler);
+    if transmittance <= T::zero() {
+        return black;
+    }
+    let weight = power_heuristic(background_pdf, hit.material.pdf(ray_in, hit, &direction));
+    spectral(ray_in, background.color(&escape)) * spectral(ray_in, f) * (transmittance * weight / background_pdf)
+}
+
+/// A rectangle of pixels; `y` counts rows from the top of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Splits a `width` x `height` image into tiles of at most `size` pixels a
+/// side, in reading order.
+pub fn tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(size as usize) {
+        for x in (0..width).step_by(size as usize) {
+            tiles.push(Tile { x, y, width: size.min(width - x), height: size.min(height - y) });
+        }
+    }
+    tiles
+}
+
+/// Sums `settings.samples` paths for every pixel of `tile`, row by row from
+/// the top. Spectral paths are converted to RGB one by one.
+pub fn render_tile<T>(world: &dyn Hittable<T>, lights: &dyn Hittable<T>, background: &Background<T>, cam: &Camera<T>, settings: &RenderSettings, tile: &Tile) -> Vec<Color3<T>>
+where
+    T: SVecElem + Float,
+{
+    let (width, height) = (settings.width, settings.height());
+    let mut pixels = Vec::with_capacity(tile.width as usize * tile.height as usize);
+
+    for row in tile.y..tile.y + tile.height {
+        let j = height - 1 - row;
+        for i in tile.x..tile.x + tile.width {
+            let pixel = row as u64 * width as u64 + i as u64;
+            let mut pixel_color = Color3::new(0.0, 0.0, 0.0);
+            for sample in 0..settings.samples {
+                let mut sampler = Sampler::for_sample(settings.seed, pixel, sample as u64);
+                let u = (i as f64 + sampler.gen_range(-0.5..0.5)) / (width - 1) as f64;
+                let v = (j as f64 + sampler.gen_range(-0.5..0.5)) / (height - 1) as f64;
+
+                let mut ray = cam.get_ray(u, v, &mut sampler);
+                ray.wavelengths = settings.spectral.then(|| Wavelengths::sample(sampler.gen()));
+                let wavelengths = ray.wavelengths;
+                let radiance = ray_color(ray, world, lights, background, settings.max_depth, &mut sampler);
+                pixel_color += match wavelengths {
+                    Some(wavelengths) => wavelengths.to_rgb(&radiance),
+                    None => radiance,
+                };
+            }
+            pixels.push(pixel_color);
+        }
+    }
+    pixels
+}
+
+/// Renders the image on `settings.threads` workers that pull tiles from a
+/// shared queue and add them into a single framebuffer.
+///
+/// The result holds the sum of all samples per pixel, top row first.
+pub fn render<T, W, L>(world: Arc<W>, lights: Arc<L>, background: Background<T>, cam: Arc<Camera<T>>, settings: &RenderSettings) -> Image<T>
+where
+    T: 'static + SVecElem + Float,
+    W: 'static + Hittable<T> + Send + Sync,
+    L: 'static + Hittable<T> + Send + Sync,
+{
+    let (width, height) = (settings.width, settings.height());
+    let mut queue = tiles(width, height, TILE_SIZE);
+    let total = queue.len();
+    // Workers pop from the back, so reverse to render in reading order.
+    queue.reverse();
+    let queue = Arc::new(Mutex::new(queue));
+
+    let framebuffer = Arc::new(Mutex::new(vec![Color3::<T>::new(0.0, 0.0, 0.0); width as usize * height as usize]));
+    let tiles_done = Arc::new(Mutex::new(0));
+
+    let pool = ThreadPool::new(settings.threads);
+    for _ in 0..settings.threads {
+        let thread_world = Arc::clone(&world);
+        let thread_lights = Arc::clone(&lights);
+        let thread_cam = Arc::clone(&cam);
+        let background = background.clone();
+        let thread_queue = Arc::clone(&queue);
+        let thread_framebuffer = Arc::clone(&framebuffer);
+        let thread_tiles_done = Arc::clone(&tiles_done);
+        let settings = *settings;
+
+        pool.execute(move || loop {
+            let tile = match thread_queue.lock().unwrap().pop() {
+                Some(tile) => tile,
+                None => break,
+            };
+            let pixels = render_tile(thread_world.as_ref(), thread_lights.as_ref(), &background, &thread_cam, &settings, &tile);
+
+            let mut framebuffer = thread_framebuffer.lock().unwrap();
+            for (row, line) in pixels.chunks(tile.width as usize).enumerate() {
+                let start = (tile.y as usize + row) * width as usize + tile.x as usize;
+                for (dst, &src) in framebuffer[start..start + line.len()].iter_mut().zip(line) {
+                    *dst += src;
+                }
+            }
+            drop(framebuffer);
+
+            let mut tiles_done = thread_tiles_done.lock().unwrap();
+            *tiles_done += 1;
+            eprint!("{} tiles left\r", total - *tiles_done);
+        });
+    }
+    pool.join();
+    assert_eq!(pool.panic_count(), 0, "a render worker panicked");
+
+    let framebuffer = std::mem::take(&mut *framebuffer.lock().unwrap());
+    Box::new(framebuffer)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aabb::*;
+    use crate::bvh::*;
+    use crate::envmap::*;
+    use crate::sky::*;
+    use crate::materials::*;
+    use crate::hittable_list::*;
+    use crate::medium::*;
+    use crate::quad::*;
+    use crate::volume::*;
+
+    fn no_lights() -> Arc<HittableList<'static, f64>> {
+        Arc::new(HittableList::new())
+    }
+
+    #[test]
+    fn tiles_cover_image() {
+        let tiles = tiles(70, 33, 32);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[2], Tile { x: 64, y: 0, width: 6, height: 32 });
+        assert_eq!(tiles[5], Tile { x: 64, y: 32, width: 6, height: 1 });
+        let area: u32 = tiles.iter().map(|t| t.width * t.height).sum();
+        assert_eq!(area, 70 * 33);
+    }
+
+    #[test]
+    fn matches_per_pixel_sky() {
+        // With nothing to hit every pixel sees the sky gradient, so the tiled
+        // framebuffer must match a plain scanline render up to jitter.
+        let settings = RenderSettings { width: 40, aspect_ratio: 40. / 35., samples: 4, max_depth: 5, threads: 3, seed: 0, spectral: false };
+        let camera = || Camera::<f64>::new((0., 0., 0.), (0., 0., -1.), (0., 1., 0.), 90., settings.aspect_ratio, 0., 1.);
+        let world: Arc<HittableList<'static, f64>> = Arc::new(HittableList::new());
+        let image = render(world.clone(), no_lights(), Background::sky(), Arc::new(camera()), &settings);
+        let cam = camera();
+
+        let (width, height) = (settings.width, settings.height());
+        assert_eq!(image.len(), (width * height) as usize);
+        for (index, pixel) in image.iter().enumerate() {
+            let (i, j) = (index as u32 % width, height - 1 - index as u32 / width);
+            let mut sampler = Sampler::new(0, 0);
+            let ray = cam.get_ray(i as f64 / (width - 1) as f64, j as f64 / (height - 1) as f64, &mut sampler);
+            let expected = ray_color(ray, world.as_ref(), no_lights().as_ref(), &Background::sky(), settings.max_depth, &mut sampler) * settings.samples as f64;
+            assert!((*pixel - expected).length() < 0.05, "pixel {} {}: {:?} vs {:?}", i, j, pixel, expected);
+        }
+    }
+
+    #[test]
+    fn deterministic_across_threads() {
+        let render_with = |threads, seed| {
+            let settings = RenderSettings { width: 48, aspect_ratio: 1.5, samples: 3, max_depth: 8, threads, seed, spectral: false };
+            let cam = Camera::<f64>::new((13., 2., 3.), (0., 0., 0.), (0., 1., 0.), 20., settings.aspect_ratio, 0.1, 10.);
+            render(Arc::new(Bvh::new(crate::random_scene(3))), no_lights(), Background::sky(), Arc::new(cam), &settings)
+        };
+        let reference = render_with(1, 7);
+        assert_eq!(render_with(4, 7), reference);
+        assert_eq!(render_with(2, 7), reference);
+        assert_ne!(render_with(2, 8), reference);
+    }
+
+    #[test]
+    fn motion_blur_averages_over_shutter() {
+        // A lamp sweeping past the centre pixel is in view for a tenth of
+        // the shutter interval.
+        let lamp: HittableList<'static, f64> = vec![Box::new(MovingSphere {
+            center0: Point3::new(-10., 0., -5.),
+            center1: Point3::new(10., 0., -5.),
+            time0: 0.,
+            time1: 1.,
+            radius: 1.,
+            material: Arc::new(DiffuseLight { emit: Color3::new(1., 1., 1.) }),
+        }) as _];
+        let settings = RenderSettings { width: 3, aspect_ratio: 1., samples: 4000, max_depth: 2, threads: 1, seed: 0, spectral: false };
+        let mut cam = Camera::<f64>::new((0., 0., 0.), (0., 0., -1.), (0., 1., 0.), 0.1, 1., 0., 1.);
+        cam.set_shutter(0., 1.);
+        let image = render(Arc::new(lamp), no_lights(), Background::None, Arc::new(cam), &settings);
+        let center = image[4].x() / settings.samples as f64;
+        assert!((center - 0.1).abs() < 0.02, "{}", center);
+    }
+
+    #[test]
+    fn spectral_matches_rgb() {
+        // A red ball under a white sky sends back its albedo along every
+        // path; uplifted to spectra and back it must average the same.
+        let ball: HittableList<'static, f64> = vec![Box::new(Sphere {
+            center: Point3::new(0., 0., -3.),
+            radius: 1.,
+            material: Arc::new(Lambertian::new(Color3::new(0.8, 0.3, 0.1))),
+        }) as _];
+        let ball = Arc::new(ball);
+        let render_with = |spectral| {
+            let settings = RenderSettings { width: 3, aspect_ratio: 1., samples: 4000, max_depth: 3, threads: 1, seed: 0, spectral };
+            let cam = Camera::<f64>::new((0., 0., 0.), (0., 0., -1.), (0., 1., 0.), 1., 1., 0., 1.);
+            let background = Background::Solid(Color3::new(1., 1., 1.));
+            render(ball.clone(), no_lights(), background, Arc::new(cam), &settings)[4] / settings.samples as f64
+        };
+        let rgb = render_with(false);
+        assert!(rgb.is_close(Color3::new(0.8, 0.3, 0.1)), "{:?}", rgb);
+        let spectral = render_with(true);
+        assert!((spectral - rgb).length() < 0.03, "{:?} vs {:?}", spectral, rgb);
+    }
+
+    #[test]
+    fn light_sampling_reduces_variance() {
+        // Paths through one spot on the Cornell box floor, with the lamp
+        // sampled at each bounce and without: the same mean, far less noise.
+        let scene = crate::cornell_box::<f64>();
+        let estimate = |lights: &dyn Hittable<f64>| mean_and_error(4000, |i| {
+            let mut sampler = Sampler::for_sample(1, 0, i);
+            let ray = scene.camera.get_ray(0.3, 0.2, &mut sampler);
+            let c = ray_color(ray, &scene.world, lights, &scene.background, 10, &mut sampler);
+            (c.x() + c.y() + c.z()) / 3.
+        });
+        let (plain, plain_error) = estimate(no_lights().as_ref());
+        let (sampled, sampled_error) = estimate(&scene.lights);
+        assert!(sampled_error < plain_error / 10., "{} vs {}", sampled_error, plain_error);
+        assert!((sampled - plain).abs() < 4. * (plain_error + sampled_error).sqrt(), "{} vs {}", sampled, plain);
+    }
+
+    /// Mean and squared standard error of `n` samples of `f`.
+    fn mean_and_error(n: u64, mut f: impl FnMut(u64) -> f64) -> (f64, f64) {
+        let samples: Vec<f64> = (0..n).map(&mut f).collect();
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let variance = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / (n - 1) as f64;
+        (mean, variance / n as f64)
+    }
+
+    #[test]
+    fn mis_tames_glossy_highlights() {
+        // A lamp whose blurry reflection in a slightly rough metal floor
+        // straddles its edge.
+        let glossy: Arc<dyn Material<f64>> = Arc::new(Metal::new(Color3::new(0.8, 0.8, 0.8), 0.1));
+        let lamp = Arc::new(Quad::new(Point3(-1.5, 2., -4.5), Vec3(3., 0., 0.), Vec3(0., 0., 3.), Arc::new(DiffuseLight { emit: Color3::new(4., 4., 4.) }) as _));
+        let world: HittableList<'static, f64> = vec![
+            Box::new(Quad::new(Point3(-10., 0., 10.), Vec3(20., 0., 0.), Vec3(0., 0., -20.), glossy.clone())) as _,
+            Box::new(Arc::clone(&lamp)) as _,
+        ];
+        let lights: HittableList<'static, f64> = vec![Box::new(lamp) as _];
+        let ray = || Ray { origin: Point3(0., 1., 3.), direction: Vec3(0., -1., -2.5), time: 0., wavelengths: None };
+        let radiance = |lights: &dyn Hittable<f64>| mean_and_error(20000, |i| {
+            ray_color(ray(), &world, lights, &Background::None, 2, &mut Sampler::for_sample(2, 0, i)).x()
+        });
+        let (bsdf, bsdf_error) = radiance(no_lights().as_ref());
+        let (mis, mis_error) = radiance(&lights);
+
+        // Light sampling on its own, as the reflection's only estimate.
+        let floor = world[0].hit(&ray(), 0.001, f64::INFINITY).unwrap();
+        let (light, light_error) = mean_and_error(20000, |i| {
+            let mut sampler = Sampler::for_sample(3, 0, i);
+            let direction = lights.sample_towards(&floor.p, 0., &mut sampler).unwrap();
+            let pdf = lights.pdf_towards(&Ray { origin: floor.p, direction, time: 0., wavelengths: None });
+            4. * glossy.eval(&ray(), &floor, &direction).x() / pdf
+        });
+
+        let close = |a: f64, b: f64, error: f64| (a - b).abs() < 4. * error.sqrt();
+        assert!(close(mis, bsdf, mis_error + bsdf_error) && close(mis, light, mis_error + light_error), "{} {} {}", mis, bsdf, light);
+        assert!(mis_error < 2. * bsdf_error, "{} vs {}", mis_error, bsdf_error);
+        assert!(mis_error < light_error / 10., "{} vs {}", mis_error, light_error);
+    }
+
+    /// Hides an object's transmittance, so shadow rays see it all or nothing.
+    struct Opaque<H>(H);
+
+    impl<H: Hittable<f64>> Hittable<f64> for Opaque<H> {
+        fn hit(&self, ray: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord<'_, f64>> {
+            self.0.hit(ray, t_min, t_max)
+        }
+
+        fn bounding_box(&self) -> Option<Aabb<f64>> {
+            self.0.bounding_box()
+        }
+    }
+
+    #[test]
+    fn shadow_rays_fade_through_clouds() {
+        // A floor lit by a lamp through a thin cloud with one dense corner
+        // off to the side, so the majorant is well above the density the
+        // shadow rays cross: ratio tracking along them must give the same
+        // light as all-or-nothing visibility, with far less noise.
+        let cloud = || GridMedium {
+            grid: DensityGrid::from_fn(Aabb::new(Point3(-2., 1., -2.), Point3(2., 3., 2.)), [4, 4, 4], |p| match p.x() > 1. && p.z() > 1. {
+                true => 1.,
+                false => 0.25,
+            }),
+            density: 2.,
+            phase_function: Arc::new(Isotropic::new(Color3::new(1., 1., 1.))) as _,
+        };
+        let lamp = Arc::new(Quad::new(Point3(-0.5, 4., -0.5), Vec3(1., 0., 0.), Vec3(0., 0., 1.), Arc::new(DiffuseLight { emit: Color3::new(10., 10., 10.) }) as _));
+        let world = |cloud: Box<dyn Hittable<f64> + Send + Sync>| -> HittableList<'static, f64> {
+            let grey = Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5)));
+            vec![Box::new(Quad::new(Point3(-10., 0., 10.), Vec3(20., 0., 0.), Vec3(0., 0., -20.), grey)) as _, Box::new(Arc::clone(&lamp)) as _, cloud]
+        };
+        let lights: HittableList<'static, f64> = vec![Box::new(Arc::clone(&lamp)) as _];
+        // From under the cloud, so only the shadow rays pass through it.
+        let estimate = |world: &HittableList<'static, f64>| mean_and_error(4000, |i| {
+            let ray = Ray { origin: Point3(0., 0.5, 3.), direction: Vec3(0., -0.5, -3.), time: 0., wavelengths: None };
+            ray_color(ray, world, &lights, &Background::None, 1, &mut Sampler::for_sample(6, 0, i)).x()
+        });
+        let (binary, binary_error) = estimate(&world(Box::new(Opaque(cloud()))));
+        let (ratio, ratio_error) = estimate(&world(Box::new(cloud())));
+        assert!((ratio - binary).abs() < 4. * (binary_error + ratio_error).sqrt(), "{} vs {}", ratio, binary);
+        assert!(ratio_error < binary_error / 3., "{} vs {}", ratio_error, binary_error);
+    }
+
+    #[test]
+    fn environment_map_is_sampled() {
+        // The top of a diffuse ball under a dim sky with a sun one texel
+        // wide: the reflection must match the integral over the map, with
+        // little noise.
+        let mut pixels = vec![Color3(0.5, 0.5, 0.5); 32 * 16];
+        pixels[3 * 32 + 20] = Color3(2000., 2000., 2000.);
+        let map = EnvironmentMap::new(&HdrImage { width: 32, height: 16, pixels }, 40., 1.);
+        let background = Background::Environment(Arc::new(map.clone()));
+        let (mean, error) = mean_and_error(4000, |i| top_of_ball(&background, 4, i).x());
+
+        // Midpoint rule over the upper hemisphere for (albedo / π) ∫ L cos θ dω.
+        let (n_theta, n_phi) = (400, 1600);
+        let (d_theta, d_phi) = (std::f64::consts::FRAC_PI_2 / n_theta as f64, 2. * std::f64::consts::PI / n_phi as f64);
+        let mut expected = 0.;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let direction = Vec3(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                expected += map.radiance(&direction).x() * theta.cos() * theta.sin() * d_theta * d_phi;
+            }
+        }
+        expected *= 0.5 / std::f64::consts::PI;
+        assert!((mean - expected).abs() < 0.02 * expected, "{} vs {}", mean, expected);
+        assert!(error.sqrt() < 0.01 * expected, "{} vs {}", error.sqrt(), expected);
+    }
+
+    #[test]
+    fn daylight_sun_is_sampled() {
+        // The top of a diffuse ball sees the whole sky and the sun, a disk
+        // half a degree across that only light sampling finds.
+        let sky = Daylight::new(50., 120., 4., Color3::new(0.2, 0.2, 0.2));
+        let expected = sky.irradiance() * 0.5 / std::f64::consts::PI;
+        let background = Background::Daylight(sky);
+        let ball: HittableList<'static, f64> = vec![Box::new(Sphere {
+            center: Point3::new(0., 0., 0.),
+            radius: 1.,
+            material: Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5))),
+        }) as _];
+        let (mean, error) = mean_and_error(4000, |i| {
+            let ray = Ray { origin: Point3(0., 5., 0.), direction: Vec3(0., -1., 0.), time: 0., wavelengths: None };
+            ray_color(ray, &ball, no_lights().as_ref(), &background, 5, &mut Sampler::for_sample(5, 0, i)).y()
+        });
+        assert!((mean - expected.y()).abs() < 0.02 * expected.y(), "{} vs {}", mean, expected.y());
+        assert!(error.sqrt() < 0.01 * expected.y(), "{} vs {}", error.sqrt(), expected.y());
+    }
+
+    fn diffuse_ball(center: Point3<f64>, albedo: Color3<f64>) -> HittableList<'static, f64> {
+        vec![Box::new(Sphere { center, radius: 1., material: Arc::new(Lambertian::new(albedo)) }) as _]
+    }
+
+    /// Radiance sent straight up by the top of a grey unit ball at the
+    /// origin, lit only by `background`, along path `i` of stream `seed`.
+    fn top_of_ball(background: &Background<f64>, seed: u64, i: u64) -> Color3<f64> {
+        let ball = diffuse_ball(Point3::new(0., 0., 0.), Color3::new(0.5, 0.5, 0.5));
+        let ray = Ray { origin: Point3(0., 5., 0.), direction: Vec3(0., -1., 0.), time: 0., wavelengths: None };
+        ray_color(ray, &ball, no_lights().as_ref(), background, 5, &mut Sampler::for_sample(seed, 0, i))
+    }
+
+    fn enclosing_sphere(material: Arc<dyn Material<f64>>) -> HittableList<'static, f64> {
+        vec![Box::new(Sphere { center: Point3::new(0., 0., 0.), radius: 10., material }) as _]
+    }
+
+    #[test]
+    fn emission_and_background() {
+        let ray = || Ray { origin: Point3::new(0., 0., 0.), direction: Vec3::new(0.3, 0.2, -1.), time: 0., wavelengths: None };
+        let mut sampler = Sampler::new(1, 2);
+        let no_lights = no_lights();
+
+        let lamp = enclosing_sphere(Arc::new(DiffuseLight { emit: Color3::new(2., 3., 4.) }));
+        assert_eq!(ray_color(ray(), &lamp, no_lights.as_ref(), &Background::sky(), 10, &mut sampler), Color3::new(2., 3., 4.));
+
+        // A closed room without lights stays dark whatever the background.
+        let room = enclosing_sphere(Arc::new(Lambertian::new(Color3::new(0.9, 0.9, 0.9))));
+        assert_eq!(ray_color(ray(), &room, no_lights.as_ref(), &Background::Solid(Color3::new(1., 1., 1.)), 10, &mut sampler), Color3::new(0., 0., 0.));
+
+        let empty: HittableList<f64> = HittableList::new();
+        assert_eq!(ray_color(ray(), &empty, no_lights.as_ref(), &Background::None, 10, &mut sampler), Color3::new(0., 0., 0.));
+        assert_eq!(ray_color(ray(), &empty, no_lights.as_ref(), &Background::Solid(Color3::new(0.2, 0.3, 0.4)), 10, &mut sampler), Color3::new(0.2, 0.3, 0.4));
+    }
+}
@@ -0,0 +1,1531 @@
+use num::Float;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::background::*;
+use crate::envmap::*;
+use crate::sky::*;
+use crate::camera::*;
+use crate::common::*;
+use crate::aabb::*;
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::image::ImageError;
+use crate::hittable_list::*;
+use crate::instance::*;
+use crate::materials::*;
+use crate::medium::*;
+use crate::obj::*;
+use crate::perlin::*;
+use crate::principled::*;
+use crate::quad::*;
+use crate::spectrum::*;
+use crate::texture::*;
+use crate::transform::*;
+use crate::triangle::*;
+use crate::vec3::*;
+use crate::volume::*;
+
+/// Everything needed to render a frame, as described by a scene file.
+pub struct Scene<'a, T: SVecElem + Float> {
+    pub world: HittableList<'a, T>,
+    /// Emitters in `world` that can be sampled directly, shared with it.
+    pub lights: HittableList<'a, T>,
+    pub camera: Camera<T>,
+    pub settings: RenderSettings,
+    pub background: Background<T>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io { path: PathBuf, source: io::Error },
+    Syntax(toml::de::Error),
+    /// A well-formed entry with values we cannot render, e.g. `objects[3]`.
+    Invalid { entry: String, message: String },
+    /// An OBJ file that failed to load, with the entry that named it.
+    Obj { entry: String, source: ObjError },
+    Image(ImageError),
+    /// A `.vol` grid that failed to load, with the entry that named it.
+    Volume { entry: String, source: VolumeError },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Syntax(e) => write!(f, "{}", e),
+            SceneError::Invalid { entry, message } => write!(f, "{}: {}", entry, message),
+            SceneError::Obj { entry, source } => write!(f, "{}: {}", entry, source),
+            SceneError::Image(e) => write!(f, "{}", e),
+            SceneError::Volume { entry, source } => write!(f, "{}: {}", entry, source),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    render: RenderSection,
+    camera: CameraSection,
+    #[serde(default)]
+    background: BackgroundDesc,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectEntry>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RenderSection {
+    width: Option<u32>,
+    aspect_ratio: Option<f64>,
+    samples: Option<u32>,
+    max_depth: Option<u32>,
+    threads: Option<usize>,
+    seed: Option<u64>,
+    spectral: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraSection {
+    lookfrom: [f64; 3],
+    lookat: [f64; 3],
+    #[serde(default = "default_vup")]
+    vup: [f64; 3],
+    vfov: f64,
+    #[serde(default)]
+    aperture: f64,
+    focus_dist: Option<f64>,
+    /// Open and close times; an instant at 0 by default.
+    #[serde(default)]
+    shutter: [f64; 2],
+}
+
+fn default_vup() -> [f64; 3] {
+    [0., 1., 0.]
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum BackgroundDesc {
+    None,
+    Solid { color: [f64; 3] },
+    Gradient { bottom: [f64; 3], top: [f64; 3] },
+    /// The default white to blue gradient.
+    #[default]
+    Sky,
+    /// Equirectangular `.hdr` or `.pfm` file, relative to the scene file,
+    /// turned `rotation` degrees about +y and scaled by `intensity`.
+    Environment {
+        path: PathBuf,
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    /// Preetham clear sky with the sun `elevation` degrees up and `azimuth`
+    /// degrees from -z towards +x.
+    Daylight {
+        elevation: f64,
+        #[serde(default)]
+        azimuth: f64,
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        #[serde(default = "default_ground_albedo")]
+        ground_albedo: [f64; 3],
+    },
+}
+
+fn default_intensity() -> f64 {
+    1.
+}
+
+fn default_turbidity() -> f64 {
+    3.
+}
+
+fn default_ground_albedo() -> [f64; 3] {
+    [0.3, 0.3, 0.3]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum TextureDesc {
+    Solid { color: [f64; 3] },
+    /// 3D checker of cubes with edge `scale`.
+    Checker { scale: f64, even: [f64; 3], odd: [f64; 3] },
+    /// PPM or PNG file, relative to the scene file.
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        wrap: WrapMode,
+        #[serde(default)]
+        filter: Filter,
+    },
+    Gradient {
+        start: [f64; 3],
+        end: [f64; 3],
+        #[serde(default)]
+        axis: GradientAxis,
+    },
+    /// Perlin noise blended between two colours.
+    Noise {
+        #[serde(default)]
+        kind: NoiseKind,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+        octaves: Option<u32>,
+        lacunarity: Option<f64>,
+        gain: Option<f64>,
+        low: [f64; 3],
+        high: [f64; 3],
+    },
+    Marble {
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+        base: [f64; 3],
+        vein: [f64; 3],
+    },
+    Wood {
+        #[serde(default = "default_scale")]
+        scale: f64,
+        rings: f64,
+        #[serde(default)]
+        seed: u64,
+        light: [f64; 3],
+        dark: [f64; 3],
+    },
+}
+
+fn default_scale() -> f64 {
+    1.
+}
+
+/// A constant colour, or the name of an entry in `[textures]`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AlbedoDesc {
+    Color([f64; 3]),
+    Texture(String),
+}
+
+/// A number in [0, 1], or the name of an entry in `[textures]` whose mean
+/// channel gives it.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScalarDesc {
+    Value(f64),
+    Texture(String),
+}
+
+impl Default for ScalarDesc {
+    fn default() -> Self {
+        ScalarDesc::Value(0.)
+    }
+}
+
+fn default_half() -> ScalarDesc {
+    ScalarDesc::Value(0.5)
+}
+
+fn default_clearcoat_roughness() -> ScalarDesc {
+    ScalarDesc::Value(0.1)
+}
+
+fn default_ior() -> f64 {
+    1.5
+}
+
+/// Index of refraction as a function of wavelength in micrometres.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum DispersionDesc {
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+    /// Schott N-BK7 crown glass.
+    Bk7,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian { albedo: AlbedoDesc },
+    Metal { albedo: AlbedoDesc, fuzz: f64 },
+    /// Glass, smooth unless given a GGX `roughness`. Smooth glass may give
+    /// a `dispersion` instead of `ir`, for spectral renders.
+    Dielectric {
+        ir: Option<f64>,
+        dispersion: Option<DispersionDesc>,
+        #[serde(default)]
+        roughness: f64,
+    },
+    /// GGX metal with a complex index of refraction, either a named `preset`
+    /// or explicit per-channel `eta` and `k`.
+    Conductor {
+        preset: Option<MetalPreset>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        #[serde(default)]
+        roughness: f64,
+    },
+    DiffuseLight { emit: [f64; 3] },
+    /// Disney-style uber material; every parameter but `ior` may name a
+    /// texture.
+    Principled {
+        base_color: AlbedoDesc,
+        #[serde(default)]
+        metallic: ScalarDesc,
+        #[serde(default = "default_half")]
+        roughness: ScalarDesc,
+        #[serde(default = "default_half")]
+        specular: ScalarDesc,
+        #[serde(default)]
+        sheen: ScalarDesc,
+        #[serde(default)]
+        clearcoat: ScalarDesc,
+        #[serde(default = "default_clearcoat_roughness")]
+        clearcoat_roughness: ScalarDesc,
+        #[serde(default)]
+        transmission: ScalarDesc,
+        #[serde(default = "default_ior")]
+        ior: f64,
+    },
+    /// Phase function for media, scattering evenly in all directions.
+    Isotropic { albedo: AlbedoDesc },
+    /// Anisotropic phase function with mean scattering cosine `g`.
+    HenyeyGreenstein { albedo: AlbedoDesc, g: f64 },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere { center: [f64; 3], radius: f64, material: String },
+    /// Sphere travelling from `center0` at `time0` to `center1` at `time1`.
+    #[serde(rename = "moving_sphere")]
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "default_time1")]
+        time1: f64,
+        radius: f64,
+        material: String,
+    },
+    /// Parallelogram from the corner `origin` along edges `u` and `v`.
+    Quad { origin: [f64; 3], u: [f64; 3], v: [f64; 3], material: String },
+    Disk { center: [f64; 3], normal: [f64; 3], radius: f64, material: String },
+    Plane { point: [f64; 3], normal: [f64; 3], material: String },
+    /// Single triangle, smooth shaded if given one normal per vertex.
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        normals: Option<[[f64; 3]; 3]>,
+        material: String,
+    },
+    /// Axis-aligned box between two opposite corners.
+    Box { min: [f64; 3], max: [f64; 3], material: String },
+    /// Wavefront OBJ file, relative to the scene file, using its own MTL materials.
+    Mesh { path: PathBuf },
+    /// Constant-density volume filling a closed boundary, scattering by
+    /// `material` (usually isotropic).
+    Medium { boundary: BoundaryDesc, density: f64, material: String },
+    /// Medium whose density, scaled by `density`, varies over a grid.
+    Volume {
+        grid: GridDesc,
+        #[serde(default = "default_density")]
+        density: f64,
+        material: String,
+    },
+}
+
+fn default_density() -> f64 {
+    1.
+}
+
+/// Source of a volume's density grid.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum GridDesc {
+    /// Mitsuba `.vol` grid, bounds included, relative to the scene file.
+    File { path: PathBuf },
+    /// Procedural cloud filling the box from `min` to `max`.
+    Cloud {
+        min: [f64; 3],
+        max: [f64; 3],
+        #[serde(default = "default_resolution")]
+        resolution: [usize; 3],
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+        octaves: Option<u32>,
+    },
+}
+
+fn default_resolution() -> [usize; 3] {
+    [64, 64, 64]
+}
+
+/// Closed shape enclosing a medium.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum BoundaryDesc {
+    Sphere { center: [f64; 3], radius: f64 },
+    Box { min: [f64; 3], max: [f64; 3] },
+    Mesh { path: PathBuf },
+}
+
+impl ObjectDesc {
+    fn kind(&self) -> &'static str {
+        match self {
+            ObjectDesc::Sphere { .. } => "sphere",
+            ObjectDesc::MovingSphere { .. } => "moving_sphere",
+            ObjectDesc::Quad { .. } => "quad",
+            ObjectDesc::Disk { .. } => "disk",
+            ObjectDesc::Plane { .. } => "plane",
+            ObjectDesc::Triangle { .. } => "triangle",
+            ObjectDesc::Box { .. } => "box",
+            ObjectDesc::Mesh { .. } => "mesh",
+            ObjectDesc::Medium { .. } => "medium",
+            ObjectDesc::Volume { .. } => "volume",
+        }
+    }
+
+    fn material(&self) -> Option<&str> {
+        match self {
+            ObjectDesc::Sphere { material, .. }
+            | ObjectDesc::MovingSphere { material, .. }
+            | ObjectDesc::Quad { material, .. }
+            | ObjectDesc::Disk { material, .. }
+            | ObjectDesc::Plane { material, .. }
+            | ObjectDesc::Triangle { material, .. }
+            | ObjectDesc::Box { material, .. }
+            | ObjectDesc::Medium { material, .. }
+            | ObjectDesc::Volume { material, .. } => Some(material),
+            ObjectDesc::Mesh { .. } => None,
+        }
+    }
+}
+
+/// One step of an object's placement; rotations are in degrees.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    Translate([f64; 3]),
+    Scale([f64; 3]),
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Rotate { axis: [f64; 3], angle: f64 },
+    /// Top three rows of an affine matrix, applied to column vectors.
+    Matrix([[f64; 4]; 3]),
+}
+
+fn default_time1() -> f64 {
+    1.
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RotationDesc {
+    axis: [f64; 3],
+    angle: f64,
+}
+
+/// Placement at `time`: scale, then rotate, then translate.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f64,
+    #[serde(default)]
+    translate: [f64; 3],
+    rotate: Option<RotationDesc>,
+    #[serde(default = "unit_scale")]
+    scale: [f64; 3],
+}
+
+fn unit_scale() -> [f64; 3] {
+    [1., 1., 1.]
+}
+
+#[derive(Deserialize)]
+struct ObjectEntry {
+    #[serde(flatten)]
+    shape: ObjectDesc,
+    /// Applied in order, the first step innermost.
+    #[serde(default)]
+    transform: Vec<TransformDesc>,
+    /// Keyframes moving the object after `transform`.
+    #[serde(default)]
+    motion: Vec<KeyframeDesc>,
+}
+
+fn invalid(entry: impl Into<String>, message: impl Into<String>) -> SceneError {
+    SceneError::Invalid { entry: entry.into(), message: message.into() }
+}
+
+fn load_mesh<'a, T>(path: &Path, entry: &str) -> Result<ObjModel<'a, T>, SceneError>
+where
+    T: 'a + SVecElem + Float,
+{
+    load_obj(path).map_err(|source| SceneError::Obj { entry: entry.into(), source })
+}
+
+fn check(ok: bool, entry: &str, message: impl FnOnce() -> String) -> Result<(), SceneError> {
+    if ok { Ok(()) } else { Err(invalid(entry, message())) }
+}
+
+fn check_color(color: &[f64; 3], entry: &str, name: &str) -> Result<(), SceneError> {
+    check(color.iter().all(|c| (0. ..=1.).contains(c)), entry, || format!("{} components must be in [0, 1], got {:?}", name, color))
+}
+
+fn check_radiance(color: &[f64; 3], entry: &str, name: &str) -> Result<(), SceneError> {
+    check(color.iter().all(|c| c.is_finite() && *c >= 0.), entry, || format!("{} components must be finite and non-negative, got {:?}", name, color))
+}
+
+fn check_roughness(roughness: f64, entry: &str) -> Result<(), SceneError> {
+    check((0. ..=1.).contains(&roughness), entry, || format!("roughness must be in [0, 1], got {}", roughness))
+}
+
+fn check_scale(scale: f64, entry: &str) -> Result<(), SceneError> {
+    check(scale.is_finite() && scale > 0., entry, || format!("scale must be positive, got {}", scale))
+}
+
+fn check_finite(values: &[f64], entry: &str, name: &str) -> Result<(), SceneError> {
+    check(values.iter().all(|v| v.is_finite()), entry, || format!("{} must be finite, got {:?}", name, values))
+}
+
+fn to_motion<T: SVecElem + Float>(keys: &[KeyframeDesc], entry: &str) -> Result<Option<Motion<T>>, SceneError> {
+    let vector = |v: &[f64; 3]| Vec3::<T>::new(v[0], v[1], v[2]);
+    let mut keyframes = Vec::with_capacity(keys.len());
+    for key in keys {
+        check_finite(&[key.time], entry, "keyframe time")?;
+        check(keys.iter().filter(|other| other.time == key.time).count() == 1, entry, || format!("more than one keyframe at time {}", key.time))?;
+        check_finite(&key.translate, entry, "keyframe translation")?;
+        check(key.scale.iter().all(|f| f.is_finite() && *f != 0.), entry, || format!("scale factors must be non-zero, got {:?}", key.scale))?;
+        let rotation = match &key.rotate {
+            Some(RotationDesc { axis, angle }) => {
+                check_finite(&[axis[0], axis[1], axis[2], *angle], entry, "keyframe rotation")?;
+                check(axis.iter().any(|a| *a != 0.), entry, || "rotation axis must be non-zero".into())?;
+                Quat::from_axis_angle(vector(axis), T::from_f64(*angle).unwrap())
+            }
+            None => Quat::identity(),
+        };
+        keyframes.push(Keyframe {
+            time: T::from_f64(key.time).unwrap(),
+            translation: vector(&key.translate),
+            rotation,
+            scale: vector(&key.scale),
+        });
+    }
+    Ok(Motion::new(keyframes))
+}
+
+impl BoundaryDesc {
+    /// The shape never shades anything, so it just carries the medium's material.
+    fn to_hittable<'a, T>(&self, entry: &str, base_dir: &Path, material: Arc<dyn Material<T> + 'a>) -> Result<Box<dyn Hittable<T> + 'a + Send + Sync>, SceneError>
+    where
+        T: 'a + SVecElem + Float,
+    {
+        let point = |p: &[f64; 3]| Point3::<T>::new(p[0], p[1], p[2]);
+        Ok(match self {
+            BoundaryDesc::Sphere { center, radius } => {
+                check_finite(center, entry, "boundary center")?;
+                check(radius.is_finite() && *radius > 0., entry, || format!("boundary radius must be positive, got {}", radius))?;
+                Box::new(Sphere { center: point(center), radius: T::from_f64(*radius).unwrap(), material })
+            }
+            BoundaryDesc::Box { min, max } => {
+                check_finite(&[*min, *max].concat(), entry, "boundary min and max")?;
+                check((0..3).all(|a| min[a] < max[a]), entry, || format!("boundary min {:?} must be below max {:?} on every axis", min, max))?;
+                Box::new(Cuboid::new(point(min), point(max), material))
+            }
+            BoundaryDesc::Mesh { path } => {
+                Box::new(Bvh::new(load_mesh(&base_dir.join(path), entry)?.triangles))
+            }
+        })
+    }
+}
+
+impl GridDesc {
+    fn to_grid<T: SVecElem + Float>(&self, entry: &str, base_dir: &Path) -> Result<DensityGrid<T>, SceneError> {
+        Ok(match self {
+            GridDesc::File { path } => read_vol(base_dir.join(path)).map_err(|source| SceneError::Volume { entry: entry.into(), source })?,
+            GridDesc::Cloud { min, max, resolution, scale, seed, octaves } => {
+                check_finite(&[*min, *max].concat(), entry, "grid min and max")?;
+                check((0..3).all(|a| min[a] < max[a]), entry, || format!("grid min {:?} must be below max {:?} on every axis", min, max))?;
+                check(resolution.iter().all(|n| (1..=1024).contains(n)), entry, || format!("grid resolution must be in 1..=1024, got {:?}", resolution))?;
+                check_scale(*scale, entry)?;
+                let octaves = Octaves { count: octaves.unwrap_or(Octaves::default().count), ..Default::default() };
+                check((1..=16).contains(&octaves.count), entry, || format!("octaves must be in 1..=16, got {}", octaves.count))?;
+                let bbox = Aabb::new(Point3::new(min[0], min[1], min[2]), Point3::new(max[0], max[1], max[2]));
+                noise_cloud(bbox, *resolution, &Perlin::new(*seed), *scale, &octaves)
+            }
+        })
+    }
+}
+
+/// Composes the steps, or `None` for an empty list.
+fn to_transform<T: SVecElem + Float>(steps: &[TransformDesc], entry: &str) -> Result<Option<Transform<T>>, SceneError> {
+    let vector = |v: &[f64; 3]| Vec3::<T>::new(v[0], v[1], v[2]);
+    let angle = |degrees: f64| {
+        check_finite(&[degrees], entry, "rotation angle")?;
+        Ok(T::from_f64(degrees).unwrap())
+    };
+    let mut transform: Option<Transform<T>> = None;
+    for step in steps {
+        let next = match step {
+            TransformDesc::Translate(offset) => {
+                check_finite(offset, entry, "translation")?;
+                Transform::translate(vector(offset))
+            }
+            TransformDesc::Scale(factors) => {
+                check(factors.iter().all(|f| f.is_finite() && *f != 0.), entry, || format!("scale factors must be non-zero, got {:?}", factors))?;
+                Transform::scale(vector(factors))
+            }
+            TransformDesc::RotateX(degrees) => Transform::rotate_x(angle(*degrees)?),
+            TransformDesc::RotateY(degrees) => Transform::rotate_y(angle(*degrees)?),
+            TransformDesc::RotateZ(degrees) => Transform::rotate_z(angle(*degrees)?),
+            TransformDesc::Rotate { axis, angle: degrees } => {
+                check_finite(axis, entry, "rotation axis")?;
+                check(axis.iter().any(|a| *a != 0.), entry, || "rotation axis must be non-zero".into())?;
+                Transform::rotate(vector(axis), angle(*degrees)?)
+            }
+            TransformDesc::Matrix(rows) => {
+                check_finite(&rows.concat(), entry, "matrix")?;
+                let mut m = Mat4::identity();
+                for (row, values) in m.0.iter_mut().zip(rows) {
+                    *row = values.map(|v| T::from_f64(v).unwrap());
+                }
+                Transform::from_matrix(m).ok_or_else(|| invalid(entry, format!("matrix must be invertible, got {:?}", rows)))?
+            }
+        };
+        transform = Some(transform.map_or(next, |t| t.then(&next)));
+    }
+    Ok(transform)
+}
+
+impl RenderSection {
+    fn to_settings(&self) -> Result<RenderSettings, SceneError> {
+        let defaults = RenderSettings::default();
+        let settings = RenderSettings {
+            width: self.width.unwrap_or(defaults.width),
+            aspect_ratio: self.aspect_ratio.unwrap_or(defaults.aspect_ratio),
+            samples: self.samples.unwrap_or(defaults.samples),
+            max_depth: self.max_depth.unwrap_or(defaults.max_depth),
+            threads: self.threads.unwrap_or(defaults.threads),
+            seed: self.seed.unwrap_or(defaults.seed),
+            spectral: self.spectral.unwrap_or(defaults.spectral),
+        };
+        let entry = "render";
+        check(settings.width > 0, entry, || "width must be positive".into())?;
+        check(settings.aspect_ratio.is_finite() && settings.aspect_ratio > 0., entry, || {
+            format!("aspect_ratio must be positive, got {}", settings.aspect_ratio)
+        })?;
+        check(settings.height() > 0, entry, || format!("width {} and aspect_ratio {} give an empty image", settings.width, settings.aspect_ratio))?;
+        check(settings.samples > 0, entry, || "samples must be positive".into())?;
+        check(settings.max_depth > 0, entry, || "max_depth must be positive".into())?;
+        check(settings.threads > 0, entry, || "threads must be positive".into())?;
+        Ok(settings)
+    }
+}
+
+impl CameraSection {
+    fn to_camera<T: SVecElem + Float>(&self, aspect_ratio: f64) -> Result<Camera<T>, SceneError> {
+        let entry = "camera";
+        check_finite(&self.lookfrom, entry, "lookfrom")?;
+        check_finite(&self.lookat, entry, "lookat")?;
+        check_finite(&self.vup, entry, "vup")?;
+        check(self.lookfrom != self.lookat, entry, || "lookfrom and lookat must differ".into())?;
+        check(self.vfov > 0. && self.vfov < 180., entry, || format!("vfov must be in (0, 180), got {}", self.vfov))?;
+        check(self.aperture >= 0., entry, || format!("aperture must not be negative, got {}", self.aperture))?;
+
+        let [fx, fy, fz] = self.lookfrom;
+        let [ax, ay, az] = self.lookat;
+        let focus_dist = self.focus_dist.unwrap_or_else(|| ((fx - ax).powi(2) + (fy - ay).powi(2) + (fz - az).powi(2)).sqrt());
+        check(focus_dist > 0., entry, || format!("focus_dist must be positive, got {}", focus_dist))?;
+
+        let forward = Vec3::<f64>::new(ax - fx, ay - fy, az - fz);
+        let up = Vec3::<f64>::new(self.vup[0], self.vup[1], self.vup[2]);
+        check(cross(&forward, &up).length() > 0., entry, || "vup must not be parallel to the view direction".into())?;
+
+        let [open, close] = self.shutter;
+        check_finite(&self.shutter, entry, "shutter")?;
+        check(open <= close, entry, || format!("shutter must open before it closes, got {:?}", self.shutter))?;
+
+        let mut camera = Camera::new(
+            (fx, fy, fz),
+            (ax, ay, az),
+            (self.vup[0], self.vup[1], self.vup[2]),
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            focus_dist,
+        );
+        camera.set_shutter(T::from_f64(open).unwrap(), T::from_f64(close).unwrap());
+        Ok(camera)
+    }
+}
+
+impl BackgroundDesc {
+    fn to_background<T: SVecElem + Float>(&self, base_dir: &Path) -> Result<Background<T>, SceneError> {
+        let entry = "background";
+        let color = |c: &[f64; 3]| Color3::new(c[0], c[1], c[2]);
+        Ok(match self {
+            BackgroundDesc::None => Background::None,
+            BackgroundDesc::Solid { color: c } => {
+                check_radiance(c, entry, "color")?;
+                Background::Solid(color(c))
+            }
+            BackgroundDesc::Gradient { bottom, top } => {
+                check_radiance(bottom, entry, "bottom")?;
+                check_radiance(top, entry, "top")?;
+                Background::Gradient { bottom: color(bottom), top: color(top) }
+            }
+            BackgroundDesc::Sky => Background::sky(),
+            BackgroundDesc::Environment { path, rotation, intensity } => {
+                check_finite(&[*rotation], entry, "rotation")?;
+                check(intensity.is_finite() && *intensity >= 0., entry, || format!("intensity must be finite and non-negative, got {}", intensity))?;
+                let map = EnvironmentMap::load(base_dir.join(path), *rotation, *intensity).map_err(SceneError::Image)?;
+                Background::Environment(Arc::new(map))
+            }
+            BackgroundDesc::Daylight { elevation, azimuth, turbidity, ground_albedo } => {
+                check((0. ..=90.).contains(elevation), entry, || format!("sun elevation must be in [0, 90] degrees, got {}", elevation))?;
+                check_finite(&[*azimuth], entry, "azimuth")?;
+                check((2. ..=10.).contains(turbidity), entry, || format!("turbidity must be in [2, 10], got {}", turbidity))?;
+                check_color(ground_albedo, entry, "ground_albedo")?;
+                Background::Daylight(Daylight::new(*elevation, *azimuth, *turbidity, color(ground_albedo)))
+            }
+        })
+    }
+}
+
+type TextureMap<'s, T> = BTreeMap<&'s str, Arc<dyn Texture<T>>>;
+
+fn color<T: SVecElem + Float>(c: &[f64; 3]) -> Color3<T> {
+    Color3::new(c[0], c[1], c[2])
+}
+
+impl TextureDesc {
+    fn to_texture<T: SVecElem + Float>(&self, entry: &str, base_dir: &Path) -> Result<Arc<dyn Texture<T>>, SceneError> {
+        Ok(match self {
+            TextureDesc::Solid { color: c } => {
+                check_color(c, entry, "color")?;
+                Arc::new(SolidColor { color: color(c) })
+            }
+            TextureDesc::Checker { scale, even, odd } => {
+                check_scale(*scale, entry)?;
+                check_color(even, entry, "even")?;
+                check_color(odd, entry, "odd")?;
+                Arc::new(Checker {
+                    scale: T::from_f64(*scale).unwrap(),
+                    even: Arc::new(SolidColor { color: color(even) }),
+                    odd: Arc::new(SolidColor { color: color(odd) }),
+                })
+            }
+            TextureDesc::Image { path, wrap, filter } => {
+                Arc::new(ImageTexture::load(base_dir.join(path), *wrap, *filter).map_err(SceneError::Image)?)
+            }
+            TextureDesc::Gradient { start, end, axis } => {
+                check_color(start, entry, "start")?;
+                check_color(end, entry, "end")?;
+                Arc::new(Gradient { start: color(start), end: color(end), axis: *axis })
+            }
+            TextureDesc::Noise { kind, scale, seed, octaves, lacunarity, gain, low, high } => {
+                check_scale(*scale, entry)?;
+                let defaults = Octaves::default();
+                let octaves = Octaves {
+                    count: octaves.unwrap_or(defaults.count),
+                    lacunarity: lacunarity.unwrap_or(defaults.lacunarity),
+                    gain: gain.unwrap_or(defaults.gain),
+                };
+                check((1..=16).contains(&octaves.count), entry, || format!("octaves must be in 1..=16, got {}", octaves.count))?;
+                check(octaves.lacunarity.is_finite() && octaves.lacunarity > 0., entry, || format!("lacunarity must be positive, got {}", octaves.lacunarity))?;
+                check(octaves.gain.is_finite() && octaves.gain > 0., entry, || format!("gain must be positive, got {}", octaves.gain))?;
+                check_color(low, entry, "low")?;
+                check_color(high, entry, "high")?;
+                Arc::new(NoiseTexture { perlin: Perlin::new(*seed), kind: *kind, scale: *scale, octaves, low: color(low), high: color(high) })
+            }
+            TextureDesc::Marble { scale, seed, base, vein } => {
+                check_scale(*scale, entry)?;
+                check_color(base, entry, "base")?;
+                check_color(vein, entry, "vein")?;
+                Arc::new(Marble { perlin: Perlin::new(*seed), scale: *scale, base: color(base), vein: color(vein) })
+            }
+            TextureDesc::Wood { scale, rings, seed, light, dark } => {
+                check_scale(*scale, entry)?;
+                check(rings.is_finite() && *rings > 0., entry, || format!("rings must be positive, got {}", rings))?;
+                check_color(light, entry, "light")?;
+                check_color(dark, entry, "dark")?;
+                Arc::new(Wood { perlin: Perlin::new(*seed), scale: *scale, rings: *rings, light: color(light), dark: color(dark) })
+            }
+        })
+    }
+}
+
+impl AlbedoDesc {
+    fn to_texture<T: SVecElem + Float>(&self, entry: &str, textures: &TextureMap<T>) -> Result<Arc<dyn Texture<T>>, SceneError> {
+        match self {
+            AlbedoDesc::Color(c) => {
+                check_color(c, entry, "albedo")?;
+                Ok(Arc::new(SolidColor { color: color(c) }))
+            }
+            AlbedoDesc::Texture(name) => textures.get(name.as_str())
+                .cloned()
+                .ok_or_else(|| invalid(entry, format!("unknown texture `{}`", name))),
+        }
+    }
+}
+
+impl DispersionDesc {
+    fn to_dispersion(&self, entry: &str) -> Result<Dispersion, SceneError> {
+        let dispersion = match *self {
+            DispersionDesc::Cauchy { a, b } => Dispersion::Cauchy { a, b },
+            DispersionDesc::Sellmeier { b, c } => Dispersion::Sellmeier { b, c },
+            DispersionDesc::Bk7 => Dispersion::BK7,
+        };
+        // A resonance inside the sampled range sends n² through ±∞ however
+        // finely it is scanned, so rule it out first.
+        if let Dispersion::Sellmeier { b, c } = dispersion {
+            for i in (0..3).filter(|&i| b[i] != 0. && c[i] > 0.) {
+                let resonance = c[i].sqrt() * 1e3;
+                check(!(LAMBDA_MIN..=LAMBDA_MAX).contains(&resonance), entry, || format!("Sellmeier term {} resonates at {} nm, inside the sampled range", i, resonance))?;
+            }
+        }
+        for step in 0..=(LAMBDA_MAX - LAMBDA_MIN) as usize {
+            let lambda = LAMBDA_MIN + step as f64;
+            let ior = dispersion.ior(lambda);
+            check(ior.is_finite() && ior > 0., entry, || format!("dispersion gives index {} at {} nm", ior, lambda))?;
+        }
+        Ok(dispersion)
+    }
+}
+
+impl ScalarDesc {
+    fn to_texture<T: SVecElem + Float>(&self, entry: &str, name: &str, textures: &TextureMap<T>) -> Result<Arc<dyn Texture<T>>, SceneError> {
+        match self {
+            ScalarDesc::Value(value) => {
+                check((0. ..=1.).contains(value), entry, || format!("{} must be in [0, 1], got {}", name, value))?;
+                Ok(constant(T::from_f64(*value).unwrap()))
+            }
+            ScalarDesc::Texture(texture) => textures.get(texture.as_str())
+                .cloned()
+                .ok_or_else(|| invalid(entry, format!("unknown texture `{}`", texture))),
+        }
+    }
+}
+
+impl MaterialDesc {
+    fn to_material<'a, T>(&self, entry: &str, textures: &TextureMap<T>) -> Result<Arc<dyn Material<T> + 'a>, SceneError>
+    where
+        T: 'a + SVecElem + Float,
+    {
+        Ok(match *self {
+            MaterialDesc::Lambertian { ref albedo } => {
+                Arc::new(Lambertian::<T> { albedo: albedo.to_texture(entry, textures)? })
+            }
+            MaterialDesc::Metal { ref albedo, fuzz } => {
+                let albedo = albedo.to_texture(entry, textures)?;
+                check((0. ..=1.).contains(&fuzz), entry, || format!("fuzz must be in [0, 1], got {}", fuzz))?;
+                Arc::new(Metal::<T> { albedo, fuzz: T::from_f64(fuzz).unwrap() })
+            }
+            MaterialDesc::Dielectric { ir, ref dispersion, roughness } => {
+                check_roughness(roughness, entry)?;
+                match (ir, dispersion) {
+                    (Some(ir), None) => {
+                        check(ir.is_finite() && ir > 0., entry, || format!("ir must be positive, got {}", ir))?;
+                        let ir = T::from_f64(ir).unwrap();
+                        if roughness > 0. {
+                            Arc::new(RoughDielectric::<T> { ir, roughness: T::from_f64(roughness).unwrap() })
+                        } else {
+                            Arc::new(Dielectric::<T>::new(ir))
+                        }
+                    }
+                    (None, Some(dispersion)) => {
+                        check(roughness == 0., entry, || "only smooth glass can be dispersive".into())?;
+                        Arc::new(Dielectric::<T>::dispersive(dispersion.to_dispersion(entry)?))
+                    }
+                    _ => return Err(invalid(entry, "dielectric needs either ir or dispersion")),
+                }
+            }
+            MaterialDesc::Conductor { preset, eta, k, roughness } => {
+                check_roughness(roughness, entry)?;
+                let roughness = T::from_f64(roughness).unwrap();
+                match (preset, eta, k) {
+                    (Some(preset), None, None) => Arc::new(Conductor::<T>::preset(preset, roughness)),
+                    (None, Some(eta), Some(k)) => {
+                        check_radiance(&eta, entry, "eta")?;
+                        check_radiance(&k, entry, "k")?;
+                        Arc::new(Conductor::<T>::new(color(&eta), color(&k), roughness))
+                    }
+                    _ => return Err(invalid(entry, "conductor needs either a preset or both eta and k")),
+                }
+            }
+            MaterialDesc::DiffuseLight { emit } => {
+                check_radiance(&emit, entry, "emit")?;
+                Arc::new(DiffuseLight::<T> { emit: Color3::new(emit[0], emit[1], emit[2]) })
+            }
+            MaterialDesc::Principled {
+                ref base_color,
+                ref metallic,
+                ref roughness,
+                ref specular,
+                ref sheen,
+                ref clearcoat,
+                ref clearcoat_roughness,
+                ref transmission,
+                ior,
+            } => {
+                check(ior.is_finite() && ior > 0., entry, || format!("ior must be positive, got {}", ior))?;
+                Arc::new(Principled::<T> {
+                    base_color: base_color.to_texture(entry, textures)?,
+                    metallic: metallic.to_texture(entry, "metallic", textures)?,
+                    roughness: roughness.to_texture(entry, "roughness", textures)?,
+                    specular: specular.to_texture(entry, "specular", textures)?,
+                    sheen: sheen.to_texture(entry, "sheen", textures)?,
+                    clearcoat: clearcoat.to_texture(entry, "clearcoat", textures)?,
+                    clearcoat_roughness: clearcoat_roughness.to_texture(entry, "clearcoat_roughness", textures)?,
+                    transmission: transmission.to_texture(entry, "transmission", textures)?,
+                    ior: T::from_f64(ior).unwrap(),
+                })
+            }
+            MaterialDesc::Isotropic { ref albedo } => {
+                Arc::new(Isotropic::<T> { albedo: albedo.to_texture(entry, textures)? })
+            }
+            MaterialDesc::HenyeyGreenstein { ref albedo, g } => {
+                let albedo = albedo.to_texture(entry, textures)?;
+                check(g > -1. && g < 1., entry, || format!("g must be in (-1, 1), got {}", g))?;
+                Arc::new(HenyeyGreenstein::<T> { albedo, g: T::from_f64(g).unwrap() })
+            }
+        })
+    }
+}
+
+/// Reads a TOML scene file. Mesh and image paths are resolved relative to it.
+pub fn load_scene<'a, T>(path: impl AsRef<Path>) -> Result<Scene<'a, T>, SceneError>
+where
+    T: 'a + SVecElem + Float,
+{
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|source| SceneError::Io { path: path.to_owned(), source })?;
+    parse_scene(&source, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+pub fn parse_scene<'a, T>(source: &str, base_dir: &Path) -> Result<Scene<'a, T>, SceneError>
+where
+    T: 'a + SVecElem + Float,
+{
+    let file: SceneFile = toml::from_str(source).map_err(SceneError::Syntax)?;
+
+    let settings = file.render.to_settings()?;
+    let camera = file.camera.to_camera(settings.aspect_ratio)?;
+    let background = file.background.to_background(base_dir)?;
+
+    let mut textures = BTreeMap::new();
+    for (name, desc) in file.textures.iter() {
+        textures.insert(name.as_str(), desc.to_texture::<T>(&format!("textures.{}", name), base_dir)?);
+    }
+
+    let mut materials = BTreeMap::new();
+    for (name, desc) in file.materials.iter() {
+        materials.insert(name.as_str(), desc.to_material::<T>(&format!("materials.{}", name), &textures)?);
+    }
+
+    let material = |entry: &str, name: &str| materials.get(name)
+        .cloned()
+        .ok_or_else(|| invalid(entry, format!("unknown material `{}`", name)));
+    let point = |p: &[f64; 3]| Point3::<T>::new(p[0], p[1], p[2]);
+
+    let mut world = HittableList::new();
+    let mut lights = HittableList::new();
+    // Transformed meshes, and their emissive triangles, are loaded once per
+    // file and shared by their instances.
+    type Shared<'a, T> = Arc<dyn Hittable<T> + 'a + Send + Sync>;
+    let mut meshes: BTreeMap<&PathBuf, (Shared<'a, T>, Option<Shared<'a, T>>)> = BTreeMap::new();
+    for (i, ObjectEntry { shape, transform, motion }) in file.objects.iter().enumerate() {
+        let entry = format!("objects[{}] ({})", i, shape.kind());
+        let motion = to_motion::<T>(motion, &entry)?;
+        // A motion alone still needs a transform to place the object under it.
+        let transform = to_transform::<T>(transform, &entry)?
+            .or_else(|| motion.as_ref().map(|_| Transform::identity()));
+        let place = |object: Arc<dyn Hittable<T> + 'a + Send + Sync>, transform: Transform<T>| -> Box<dyn Hittable<T> + 'a + Send + Sync> {
+            match &motion {
+                Some(motion) => Box::new(Instance::keyframed(object, transform, motion.clone())),
+                None => Box::new(Instance::new(object, transform)),
+            }
+        };
+        let object: Box<dyn Hittable<T> + 'a + Send + Sync> = match shape {
+            ObjectDesc::Sphere { center, radius, material: name } => {
+                check_finite(center, &entry, "center")?;
+                check(radius.is_finite() && *radius != 0., &entry, || format!("radius must be non-zero, got {}", radius))?;
+                Box::new(Sphere::<T> {
+                    center: point(center),
+                    radius: T::from_f64(*radius).unwrap(),
+                    material: material(&entry, name)?,
+                })
+            }
+            ObjectDesc::MovingSphere { center0, center1, time0, time1, radius, material: name } => {
+                check_finite(&[*center0, *center1].concat(), &entry, "center0 and center1")?;
+                check(radius.is_finite() && *radius != 0., &entry, || format!("radius must be non-zero, got {}", radius))?;
+                check(time0.is_finite() && time1.is_finite() && time0 < time1, &entry, || format!("time0 must be before time1, got {} and {}", time0, time1))?;
+                Box::new(MovingSphere::<T> {
+                    center0: point(center0),
+                    center1: point(center1),
+                    time0: T::from_f64(*time0).unwrap(),
+                    time1: T::from_f64(*time1).unwrap(),
+                    radius: T::from_f64(*radius).unwrap(),
+                    material: material(&entry, name)?,
+                })
+            }
+            ObjectDesc::Quad { origin, u, v, material: name } => {
+                check_finite(&[*origin, *u, *v].concat(), &entry, "origin, u and v")?;
+                check(cross(&point(u), &point(v)).length() > T::zero(), &entry, || "u and v must not be parallel".into())?;
+                Box::new(Quad::new(point(origin), point(u), point(v), material(&entry, name)?))
+            }
+            ObjectDesc::Disk { center, normal, radius, material: name } => {
+                check_finite(&[*center, *normal].concat(), &entry, "center and normal")?;
+                check(point(normal).length() > T::zero(), &entry, || "normal must be non-zero".into())?;
+                check(radius.is_finite() && *radius > 0., &entry, || format!("radius must be positive, got {}", radius))?;
+                Box::new(Disk::new(point(center), point(normal), T::from_f64(*radius).unwrap(), material(&entry, name)?))
+            }
+            ObjectDesc::Plane { point: p, normal, material: name } => {
+                check_finite(&[*p, *normal].concat(), &entry, "point and normal")?;
+                check(point(normal).length() > T::zero(), &entry, || "normal must be non-zero".into())?;
+                Box::new(Plane::new(point(p), point(normal), material(&entry, name)?))
+            }
+            ObjectDesc::Triangle { vertices, normals, material: name } => {
+                check_finite(&vertices.concat(), &entry, "vertices")?;
+                let vertices = vertices.map(|p| point(&p));
+                let area = cross(&(vertices[1] - vertices[0]), &(vertices[2] - vertices[0])).length();
+                check(area > T::zero(), &entry, || "vertices must not be collinear".into())?;
+                if let Some(normals) = normals {
+                    check_finite(&normals.concat(), &entry, "normals")?;
+                    check(normals.iter().all(|n| point(n).length() > T::zero()), &entry, || "normals must be non-zero".into())?;
+                }
+                Box::new(Triangle {
+                    vertices,
+                    normals: normals.map(|normals| normals.map(|n| point(&n).to_unit())),
+                    uvs: None,
+                    material: material(&entry, name)?,
+                })
+            }
+            ObjectDesc::Box { min, max, material: name } => {
+                check_finite(&[*min, *max].concat(), &entry, "min and max")?;
+                check((0..3).all(|a| min[a] < max[a]), &entry, || format!("min {:?} must be below max {:?} on every axis", min, max))?;
+                Box::new(Cuboid::new(point(min), point(max), material(&entry, name)?))
+            }
+            ObjectDesc::Medium { boundary, density, material: name } => {
+                check(density.is_finite() && *density > 0., &entry, || format!("density must be positive, got {}", density))?;
+                let phase_function = material(&entry, name)?;
+                Box::new(ConstantMedium {
+                    boundary: boundary.to_hittable(&entry, base_dir, Arc::clone(&phase_function))?,
+                    density: T::from_f64(*density).unwrap(),
+                    phase_function,
+                })
+            }
+            ObjectDesc::Volume { grid, density, material: name } => {
+                check(density.is_finite() && *density > 0., &entry, || format!("density must be positive, got {}", density))?;
+                Box::new(GridMedium {
+                    grid: grid.to_grid(&entry, base_dir)?,
+                    density: T::from_f64(*density).unwrap(),
+                    phase_function: material(&entry, name)?,
+                })
+            }
+            ObjectDesc::Mesh { path } => {
+                // Emissive triangles of a mesh are sampled together, as one light.
+                let Some(transform) = transform else {
+                    let model = load_mesh(&base_dir.join(path), &entry)?;
+                    world.extend(model.triangles);
+                    if !model.lights.is_empty() {
+                        lights.push(Box::new(model.lights));
+                    }
+                    continue;
+                };
+                let (mesh, mesh_lights) = match meshes.get(path) {
+                    Some((mesh, mesh_lights)) => (Arc::clone(mesh), mesh_lights.clone()),
+                    None => {
+                        let model = load_mesh(&base_dir.join(path), &entry)?;
+                        let mesh: Shared<'a, T> = Arc::new(Bvh::new(model.triangles));
+                        let mesh_lights = (!model.lights.is_empty()).then(|| Arc::new(model.lights) as Shared<'a, T>);
+                        meshes.insert(path, (Arc::clone(&mesh), mesh_lights.clone()));
+                        (mesh, mesh_lights)
+                    }
+                };
+                if let Some(mesh_lights) = mesh_lights {
+                    lights.push(place(mesh_lights, transform));
+                }
+                world.push(place(mesh, transform));
+                continue;
+            }
+        };
+        let object = match transform {
+            Some(transform) => place(Arc::from(object), transform),
+            None => object,
+        };
+        // Planes are unbounded and media scatter inside their volume, so
+        // neither can be sampled as a light even when it glows.
+        let sampleable = !matches!(shape, ObjectDesc::Plane { .. } | ObjectDesc::Medium { .. } | ObjectDesc::Volume { .. });
+        let emissive = sampleable && shape.material().and_then(|name| file.materials.get(name))
+            .is_some_and(|desc| matches!(desc, MaterialDesc::DiffuseLight { .. }));
+        if emissive {
+            let object: Arc<dyn Hittable<T> + 'a + Send + Sync> = Arc::from(object);
+            lights.push(Box::new(Arc::clone(&object)));
+            world.push(Box::new(object));
+        } else {
+            world.push(object);
+        }
+    }
+
+    Ok(Scene { world, lights, camera, settings, background })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::*;
+    use crate::sampler::Sampler;
+
+    const MINIMAL: &str = r#"
+[camera]
+lookfrom = [0, 0, 5]
+lookat = [0, 0, 0]
+vfov = 40
+
+[materials.red]
+type = "lambertian"
+albedo = [0.8, 0.1, 0.1]
+"#;
+
+    /// `MINIMAL` followed by a `header` such as `[background]` or
+    /// `[[objects]]` and the `body` of that section.
+    fn with_section(header: &str, body: &str) -> String {
+        format!("{}\n{}\n{}", MINIMAL, header, body)
+    }
+
+    fn invalid_entry(source: &str) -> String {
+        match parse_scene::<f64>(source, Path::new("")) {
+            Err(SceneError::Invalid { entry, .. }) => entry,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn minimal_scene() {
+        let source = format!("{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"red\"\n", MINIMAL);
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        assert_eq!(scene.world.len(), 1);
+        assert_eq!(scene.settings, RenderSettings::default());
+        assert_eq!(scene.background, Background::sky());
+    }
+
+    #[test]
+    fn lights_and_background() {
+        let source = with_section("[background]", "type = \"none\"\n\n[materials.lamp]\ntype = \"diffuse_light\"\nemit = [4, 4, 4]\n");
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        assert_eq!(scene.background, Background::None);
+
+        let gradient = with_section("[background]", "type = \"gradient\"\nbottom = [0, 0, 0]\ntop = [-1, 0, 0]\n");
+        assert_eq!(invalid_entry(&gradient), "background");
+        let lamp = with_section("[materials.lamp]", "type = \"diffuse_light\"\nemit = [1, -1, 1]\n");
+        assert_eq!(invalid_entry(&lamp), "materials.lamp");
+
+        let environment = |keys: &str| with_section("[background]", &format!("type = \"environment\"\npath = \"textures/sky.hdr\"\n{}\n", keys));
+        let scenes = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        let scene = parse_scene::<f64>(&environment("rotation = 90\nintensity = 2"), &scenes).unwrap();
+        assert!(matches!(scene.background, Background::Environment(_)));
+        assert_eq!(invalid_entry(&environment("intensity = -1")), "background");
+        assert!(matches!(parse_scene::<f64>(&environment(""), Path::new("")), Err(SceneError::Image(_))));
+
+        let daylight = |keys: &str| with_section("[background]", &format!("type = \"daylight\"\n{}\n", keys));
+        let scene = parse_scene::<f64>(&daylight("elevation = 45"), Path::new("")).unwrap();
+        assert_eq!(scene.background, Background::daylight());
+        assert!(parse_scene::<f64>(&daylight("elevation = 10\nazimuth = -90\nturbidity = 6\nground_albedo = [0.1, 0.2, 0.1]"), Path::new("")).is_ok());
+        assert_eq!(invalid_entry(&daylight("elevation = -5")), "background");
+        assert_eq!(invalid_entry(&daylight("elevation = 30\nturbidity = 1")), "background");
+        assert_eq!(invalid_entry(&daylight("elevation = 30\nground_albedo = [2, 0, 0]")), "background");
+    }
+
+    #[test]
+    fn example_scene() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/random.toml");
+        let scene = load_scene::<f32>(path).unwrap();
+        assert!(scene.world.len() > 4);
+        assert_eq!(scene.settings.width, 1200);
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/lamps.toml");
+        assert_eq!(load_scene::<f64>(path).unwrap().background, Background::None);
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/textures.toml");
+        assert_eq!(load_scene::<f64>(path).unwrap().world.len(), 6);
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/cornell.toml");
+        let cornell = load_scene::<f64>(path).unwrap();
+        assert_eq!((cornell.world.len(), cornell.lights.len()), (8, 1));
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/cornell-smoke.toml");
+        assert_eq!(load_scene::<f64>(path).unwrap().world.len(), 8);
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/cloud.toml");
+        assert_eq!(load_scene::<f64>(path).unwrap().world.len(), 2);
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/metals.toml");
+        let metals = load_scene::<f64>(path).unwrap();
+        assert_eq!((metals.world.len(), metals.lights.len()), (6, 1));
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/principled.toml");
+        assert_eq!(load_scene::<f64>(path).unwrap().world.len(), 7);
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/environment.toml");
+        assert!(matches!(load_scene::<f64>(path).unwrap().background, Background::Environment(_)));
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/daylight.toml");
+        assert!(matches!(load_scene::<f64>(path).unwrap().background, Background::Daylight(_)));
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/prism.toml");
+        assert!(load_scene::<f64>(path).unwrap().settings.spectral);
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/motion.toml");
+        assert_eq!(load_scene::<f64>(path).unwrap().camera.shutter_close, 1.);
+    }
+
+    #[test]
+    fn transforms() {
+        let source = with_section("[[objects]]", "type = \"box\"\nmin = [0, 0, 0]\nmax = [1, 1, 1]\nmaterial = \"red\"\ntransform = [{ rotate_y = 90 }, { translate = [0, 0, -3] }]\n");
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        let bbox = scene.world.bounding_box().unwrap();
+        assert!(bbox.minimum.is_close(Point3(-1e-4, -1e-4, -4. - 1e-4)) && bbox.maximum.is_close(Point3(1. + 1e-4, 1. + 1e-4, -3. + 1e-4)));
+
+        // A shear along x by y, then a lift by 2.
+        let source = source.replace("{ rotate_y = 90 }, { translate = [0, 0, -3] }", "{ matrix = [[1, 1, 0, 0], [0, 1, 0, 2], [0, 0, 1, 0]] }");
+        let bbox = parse_scene::<f64>(&source, Path::new("")).unwrap().world.bounding_box().unwrap();
+        assert!(bbox.minimum.is_close(Point3(-2e-4, 2. - 1e-4, -1e-4)) && bbox.maximum.is_close(Point3(2. + 2e-4, 3. + 1e-4, 1. + 1e-4)), "{:?}", bbox);
+
+        let object = |transform: &str| with_section("[[objects]]", &format!("type = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"red\"\ntransform = [{}]\n", transform));
+        assert_eq!(invalid_entry(&object("{ scale = [1, 0, 1] }")), "objects[0] (sphere)");
+        assert_eq!(invalid_entry(&object("{ rotate = { axis = [0, 0, 0], angle = 10 } }")), "objects[0] (sphere)");
+        assert_eq!(invalid_entry(&object("{ matrix = [[1, 2, 0, 0], [2, 4, 0, 0], [0, 0, 1, 0]] }")), "objects[0] (sphere)");
+        assert!(matches!(parse_scene::<f64>(&object("{ shear = 2 }"), Path::new("")), Err(SceneError::Syntax(_))));
+        let typo = object("").replace("radius", "radius = 1\nradiu");
+        assert!(matches!(parse_scene::<f64>(&typo, Path::new("")), Err(SceneError::Syntax(_))));
+    }
+
+    #[test]
+    fn media() {
+        let source = format!("{}{}", MINIMAL, r#"
+[materials.smoke]
+type = "isotropic"
+albedo = [0.5, 0.5, 0.5]
+
+[[objects]]
+type = "medium"
+boundary = { type = "sphere", center = [0, 0, 0], radius = 1 }
+density = 1e6
+material = "smoke"
+"#);
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        let ray = Ray { origin: Point3(0., 0., 5.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let hit = scene.world[0].hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.t - 4.).abs() < 1e-4);
+
+        let thin = source.replace("density = 1e6", "density = 0");
+        assert_eq!(invalid_entry(&thin), "objects[0] (medium)");
+        let open = source.replace("type = \"sphere\", center = [0, 0, 0], radius = 1", "type = \"quad\"");
+        assert!(matches!(parse_scene::<f64>(&open, Path::new("")), Err(SceneError::Syntax(_))));
+        let missing = source.replace("type = \"sphere\", center = [0, 0, 0], radius = 1", "type = \"mesh\", path = \"missing.obj\"");
+        assert_eq!(obj_error_entry(&missing), "objects[0] (medium)");
+    }
+
+    fn obj_error_entry(source: &str) -> String {
+        match parse_scene::<f64>(source, Path::new("")) {
+            Err(SceneError::Obj { entry, .. }) => entry,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    fn volume_error_entry(source: &str) -> String {
+        match parse_scene::<f64>(source, Path::new("")) {
+            Err(SceneError::Volume { entry, source: VolumeError::Io { .. } }) => entry,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn volumes() {
+        let source = format!("{}{}", MINIMAL, r#"
+[materials.cloud]
+type = "henyey_greenstein"
+albedo = [0.9, 0.9, 0.9]
+g = 0.6
+
+[[objects]]
+type = "volume"
+grid = { type = "cloud", min = [-1, -1, -1], max = [1, 1, 1], resolution = [16, 16, 16] }
+density = 1e6
+material = "cloud"
+"#);
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        let ray = Ray { origin: Point3(0., 0., 5.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let hit = scene.world[0].hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(hit.t > 4. && hit.t < 5.);
+
+        assert_eq!(invalid_entry(&source.replace("g = 0.6", "g = 1")), "materials.cloud");
+        assert_eq!(invalid_entry(&source.replace("density = 1e6", "density = -1")), "objects[0] (volume)");
+        assert_eq!(invalid_entry(&source.replace("max = [1, 1, 1]", "max = [1, -1, 1]")), "objects[0] (volume)");
+        let missing = source.replace(r#"{ type = "cloud", min = [-1, -1, -1], max = [1, 1, 1], resolution = [16, 16, 16] }"#, r#"{ type = "file", path = "missing.vol" }"#);
+        assert_eq!(volume_error_entry(&missing), "objects[0] (volume)");
+    }
+
+    #[test]
+    fn microfacet_materials() {
+        let source = with_section("[materials.gold]", r#"type = "conductor"
+preset = "gold"
+roughness = 0.3
+
+[materials.frosted]
+type = "dielectric"
+ir = 1.5
+roughness = 0.2
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "gold"
+
+[[objects]]
+type = "sphere"
+center = [0, 0, -4]
+radius = 1
+material = "frosted"
+"#);
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        let ray = Ray { origin: Point3(0., 0., 5.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        for object in &scene.world {
+            let hit = object.hit(&ray, 0.001, f64::INFINITY).unwrap();
+            let scattered = hit.material.scatter(&ray, &hit, &mut Sampler::new(0, 0)).unwrap();
+            assert!(!scattered.is_specular());
+        }
+
+        let custom = source.replace("preset = \"gold\"", "eta = [0.2, 0.9, 1.1]\nk = [3.9, 2.5, 2.1]");
+        assert!(parse_scene::<f64>(&custom, Path::new("")).is_ok());
+        assert_eq!(invalid_entry(&source.replace("preset = \"gold\"", "eta = [0.2, 0.9, 1.1]")), "materials.gold");
+        assert_eq!(invalid_entry(&source.replace("preset = \"gold\"", "preset = \"gold\"\nk = [1, 1, 1]")), "materials.gold");
+        assert_eq!(invalid_entry(&source.replace("roughness = 0.2", "roughness = 1.5")), "materials.frosted");
+        assert!(matches!(parse_scene::<f64>(&source.replace("\"gold\"\nroughness", "\"tin\"\nroughness"), Path::new("")), Err(SceneError::Syntax(_))));
+    }
+
+    #[test]
+    fn principled_material() {
+        let source = with_section("[textures.scratches]", r#"type = "noise"
+scale = 4
+low = [0.2, 0.2, 0.2]
+high = [0.6, 0.6, 0.6]
+
+[materials.paint]
+type = "principled"
+base_color = [0.8, 0.1, 0.1]
+metallic = 0.2
+roughness = "scratches"
+clearcoat = 1
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "paint"
+"#);
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        let ray = Ray { origin: Point3(0., 0., 5.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let hit = scene.world[0].hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(!hit.material.scatter(&ray, &hit, &mut Sampler::new(0, 0)).unwrap().is_specular());
+
+        assert_eq!(invalid_entry(&source.replace("metallic = 0.2", "metallic = 2")), "materials.paint");
+        assert_eq!(invalid_entry(&source.replace("\"scratches\"\nclearcoat", "\"rust\"\nclearcoat")), "materials.paint");
+        assert_eq!(invalid_entry(&source.replace("clearcoat = 1", "clearcoat = 1\nior = 0")), "materials.paint");
+        assert!(matches!(parse_scene::<f64>(&source.replace("clearcoat = 1", "clearcoat = 1\nsheen_tint = 1"), Path::new("")), Err(SceneError::Syntax(_))));
+    }
+
+    #[test]
+    fn dispersion() {
+        let source = MINIMAL.replace("[camera]", "[render]\nspectral = true\n\n[camera]") + r#"
+[materials.prism]
+type = "dielectric"
+dispersion = { type = "cauchy", a = 1.5, b = 0.01 }
+"#;
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        assert!(scene.settings.spectral);
+
+        let glass = |body: &str| with_section("[materials.glass]", &format!("type = \"dielectric\"\n{}\n", body));
+        assert!(parse_scene::<f64>(&glass("dispersion = { type = \"bk7\" }"), Path::new("")).is_ok());
+        assert_eq!(invalid_entry(&glass("")), "materials.glass");
+        assert_eq!(invalid_entry(&glass("ir = 1.5\ndispersion = { type = \"bk7\" }")), "materials.glass");
+        assert_eq!(invalid_entry(&glass("roughness = 0.2\ndispersion = { type = \"bk7\" }")), "materials.glass");
+        // A resonance inside the visible range.
+        assert_eq!(invalid_entry(&glass("dispersion = { type = \"sellmeier\", b = [1, 0, 0], c = [0.25, 0, 0] }")), "materials.glass");
+        // Positive at 380, 589.3 and 720 nm, but negative n² around 500 nm.
+        assert_eq!(invalid_entry(&glass("dispersion = { type = \"sellmeier\", b = [1, 0, 0], c = [0.3, 0, 0] }")), "materials.glass");
+    }
+
+    #[test]
+    fn motion() {
+        let source = MINIMAL.replace("vfov = 40", "vfov = 40\nshutter = [0, 0.5]") + r#"
+[[objects]]
+type = "moving_sphere"
+center0 = [0, 0, 0]
+center1 = [0, 2, 0]
+radius = 0.5
+material = "red"
+
+[[objects]]
+type = "box"
+min = [-0.5, -0.5, -0.5]
+max = [0.5, 0.5, 0.5]
+material = "red"
+motion = [{ time = 0, translate = [3, 0, 0] }, { time = 1, translate = [5, 0, 0], rotate = { axis = [0, 1, 0], angle = 90 } }]
+"#;
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        assert_eq!((scene.camera.shutter_open, scene.camera.shutter_close), (0., 0.5));
+
+        let ray = |x, time| Ray { origin: Point3(x, 1., 5.), direction: Vec3(0., 0., -1.), time, wavelengths: None };
+        assert!(scene.world[0].hit(&ray(0., 0.), 0.001, f64::INFINITY).is_none());
+        assert!(scene.world[0].hit(&ray(0., 0.5), 0.001, f64::INFINITY).is_some());
+        let bbox = scene.world[1].bounding_box().unwrap();
+        assert!(bbox.minimum.x() < 2.5 && bbox.maximum.x() > 5.5);
+        let ray = |x, time| Ray { origin: Point3(x, 0., 5.), direction: Vec3(0., 0., -1.), time, wavelengths: None };
+        assert!(scene.world[1].hit(&ray(3., 0.), 0.001, f64::INFINITY).is_some());
+        assert!(scene.world[1].hit(&ray(3., 1.), 0.001, f64::INFINITY).is_none());
+        assert!(scene.world[1].hit(&ray(4.2, 0.5), 0.001, f64::INFINITY).is_some());
+
+        let shutter = MINIMAL.replace("vfov = 40", "vfov = 40\nshutter = [1, 0]");
+        assert_eq!(invalid_entry(&shutter), "camera");
+        let keys = with_section("[[objects]]", "type = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"red\"\nmotion = [{ time = 1 }, { time = 1, scale = [2, 2, 2] }]\n");
+        assert_eq!(invalid_entry(&keys), "objects[0] (sphere)");
+    }
+
+    #[test]
+    fn mesh_instances_share_geometry() {
+        let dir = std::env::temp_dir().join(format!("scene-instances-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let copies: String = (0..3).map(|i| format!("\n[[objects]]\ntype = \"mesh\"\npath = \"tri.obj\"\ntransform = [{{ translate = [{}, 0, 0] }}]\n", 2 * i)).collect();
+        let scene = parse_scene::<f64>(&format!("{}{}", MINIMAL, copies), &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(scene.world.len(), 3);
+        let ray = Ray { origin: Point3(4.2, 0.2, 5.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let hit = scene.world.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(hit.p.is_close(Point3(4.2, 0.2, 0.)));
+
+        // A missing file names the entry, placed or not.
+        let missing = format!("{}{}", MINIMAL, copies.replacen("tri.obj", "missing.obj", 2));
+        assert_eq!(obj_error_entry(&missing), "objects[0] (mesh)");
+        let sphere = "\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"red\"\n";
+        let missing = format!("{}{}\n[[objects]]\ntype = \"mesh\"\npath = \"missing.obj\"\n", MINIMAL, sphere);
+        assert_eq!(obj_error_entry(&missing), "objects[1] (mesh)");
+    }
+
+    #[test]
+    fn emissive_meshes_are_lights() {
+        let dir = std::env::temp_dir().join(format!("scene-lamps-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lamp.mtl"), "newmtl lamp\nKe 4 4 4\nnewmtl matte\nKd 0.5 0.5 0.5\n").unwrap();
+        fs::write(dir.join("lamp.obj"), "mtllib lamp.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl lamp\nf 1 2 3\nusemtl matte\nf 3 2 1\n").unwrap();
+        let mesh = |transform: &str| format!("\n[[objects]]\ntype = \"mesh\"\npath = \"lamp.obj\"\n{}\n", transform);
+        let plane = "\n[[objects]]\ntype = \"plane\"\npoint = [0, 0, -1]\nnormal = [0, 0, 1]\nmaterial = \"lamp\"\n";
+        let source = format!("{}\n[materials.lamp]\ntype = \"diffuse_light\"\nemit = [1, 1, 1]\n{}{}{}{}", MINIMAL, mesh(""),
+            mesh("transform = [{ translate = [2, 0, 0] }]"), mesh("transform = [{ translate = [4, 0, 0] }]"), plane);
+        let scene = parse_scene::<f64>(&source, &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Only the glowing triangle of each mesh is a light; the plane is not.
+        assert_eq!((scene.world.len(), scene.lights.len()), (5, 3));
+        let origin = Point3(2.25, 0.25, 1.);
+        let mut sampler = Sampler::new(0, 0);
+        let direction = scene.lights[1].sample_towards(&origin, 0., &mut sampler).unwrap();
+        let ray = Ray { origin, direction, time: 0., wavelengths: None };
+        let hit = scene.world.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((2. ..3.).contains(&hit.p.x()) && hit.p.z().abs() < 1e-9);
+        assert!(scene.lights[1].pdf_towards(&ray) > 0.);
+        assert_eq!(scene.lights[0].pdf_towards(&ray), 0.);
+    }
+
+    #[test]
+    fn planar_objects() {
+        let object = |body: &str| with_section("[[objects]]", &format!("{}material = \"red\"\n", body));
+        let source = object("type = \"quad\"\norigin = [0, 0, 0]\nu = [1, 0, 0]\nv = [0, 1, 0]\n")
+            + "\n[[objects]]\ntype = \"disk\"\ncenter = [0, 0, 0]\nnormal = [0, 0, 1]\nradius = 1\nmaterial = \"red\"\n"
+            + "\n[[objects]]\ntype = \"box\"\nmin = [0, 0, 0]\nmax = [1, 1, 1]\nmaterial = \"red\"\n";
+        assert_eq!(parse_scene::<f64>(&source, Path::new("")).unwrap().world.len(), 3);
+
+        assert_eq!(invalid_entry(&object("type = \"quad\"\norigin = [0, 0, 0]\nu = [1, 0, 0]\nv = [2, 0, 0]\n")), "objects[0] (quad)");
+        assert_eq!(invalid_entry(&object("type = \"plane\"\npoint = [0, 0, 0]\nnormal = [0, 0, 0]\n")), "objects[0] (plane)");
+        assert_eq!(invalid_entry(&object("type = \"box\"\nmin = [0, 0, 0]\nmax = [1, 0, 1]\n")), "objects[0] (box)");
+    }
+
+    #[test]
+    fn triangle() {
+        let object = |body: &str| format!("{}\n[[objects]]\ntype = \"triangle\"\n{}material = \"red\"\n", MINIMAL, body);
+        let scene = parse_scene::<f64>(&object("vertices = [[-1, -1, 0], [1, -1, 0], [0, 1, 0]]\nnormals = [[0, 0, 2], [0, 0, 1], [0, 0, 1]]\n"), Path::new("")).unwrap();
+        let ray = Ray { origin: Point3(0., 0., 5.), direction: Vec3(0., 0., -1.), time: 0., wavelengths: None };
+        let hit = scene.world.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.t - 5.).abs() < 1e-12 && hit.normal.is_close(Vec3(0., 0., 1.)));
+
+        assert_eq!(invalid_entry(&object("vertices = [[0, 0, 0], [1, 1, 1], [2, 2, 2]]\n")), "objects[0] (triangle)");
+        assert_eq!(invalid_entry(&object("vertices = [[-1, -1, 0], [1, -1, 0], [0, 1, 0]]\nnormals = [[0, 0, 1], [0, 0, 0], [0, 0, 1]]\n")), "objects[0] (triangle)");
+    }
+
+    #[test]
+    fn validation_names_entry() {
+        let unknown = format!("{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"red\"\n\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"blue\"\n", MINIMAL);
+        assert_eq!(invalid_entry(&unknown), "objects[1] (sphere)");
+
+        let fuzz = format!("{}\n[materials.steel]\ntype = \"metal\"\nalbedo = [0.5, 0.5, 0.5]\nfuzz = 2\n", MINIMAL);
+        assert_eq!(invalid_entry(&fuzz), "materials.steel");
+
+        let samples = format!("[render]\nsamples = 0\n{}", MINIMAL);
+        assert_eq!(invalid_entry(&samples), "render");
+    }
+
+    #[test]
+    fn syntax_error() {
+        let source = format!("{}\n[materials.odd]\ntype = \"plastic\"\n", MINIMAL);
+        assert!(matches!(parse_scene::<f64>(&source, Path::new("")), Err(SceneError::Syntax(_))));
+    }
+
+    #[test]
+    fn textures() {
+        let source = format!("{}\n[textures.floor]\ntype = \"checker\"\nscale = 0.5\neven = [0, 0, 0]\nodd = [1, 1, 1]\n\n[materials.ground]\ntype = \"lambertian\"\nalbedo = \"floor\"\n\n[[objects]]\ntype = \"sphere\"\ncenter = [0, -1, 0]\nradius = 1\nmaterial = \"ground\"\n", MINIMAL);
+        let scene = parse_scene::<f64>(&source, Path::new("")).unwrap();
+        let ray = Ray { origin: Point3::new(0.1, 5., 0.1), direction: Vec3::new(0., -1., 0.), time: 0., wavelengths: None };
+        let hit = scene.world[0].hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(hit.material.scatter(&ray, &hit, &mut Sampler::new(0, 0)).is_some());
+
+        let unknown = format!("{}\n[materials.ground]\ntype = \"metal\"\nalbedo = \"marble\"\nfuzz = 0\n", MINIMAL);
+        assert_eq!(invalid_entry(&unknown), "materials.ground");
+
+        let marble = format!("{}\n[textures.stone]\ntype = \"marble\"\nscale = 4\nbase = [0.9, 0.9, 0.9]\nvein = [0.2, 0.2, 0.3]\n\n[textures.clouds]\ntype = \"noise\"\nkind = \"turbulence\"\noctaves = 20\nlow = [0, 0, 0]\nhigh = [1, 1, 1]\n", MINIMAL);
+        assert_eq!(invalid_entry(&marble), "textures.clouds");
+
+        let missing = format!("{}\n[textures.earth]\ntype = \"image\"\npath = \"no/such/earth.png\"\n", MINIMAL);
+        assert!(matches!(parse_scene::<f64>(&missing, Path::new("")), Err(SceneError::Image(ImageError::Io { .. }))));
+    }
+}
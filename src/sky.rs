@@ -0,0 +1,230 @@
+use num::Float;
+use rand::Rng;
+use std::f64::consts::PI;
+
+use crate::common::SVecElem;
+use crate::sampler::*;
+use crate::spectrum::*;
+use crate::tonemap::xyz_to_srgb;
+use crate::vec3::*;
+
+/// Half the angle the sun's disk spans, in degrees.
+pub const SUN_ANGULAR_RADIUS: f64 = 0.2665;
+
+/// Luminance in cd/m² of one unit of radiance, chosen so that sun and sky
+/// at mid elevation light a white floor to about one, like the gradient sky.
+const CANDELA_PER_UNIT: f64 = 30_000.;
+
+/// Clear sky after Preetham, Shirley and Smits, "A practical analytic model
+/// for daylight" (1999), with a sun disk whose colour comes from the same
+/// paper's Rayleigh and aerosol extinction of a 5778 K black body.
+///
+/// Below the horizon lies a Lambertian ground of the given albedo, lit by
+/// sun and sky.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Daylight<T: SVecElem> {
+    /// Unit direction towards the sun.
+    sun: Vec3<T>,
+    sun_zenith: f64,
+    /// Perez coefficients A to E for Y, x and y.
+    perez: [[f64; 5]; 3],
+    /// Y, x and y at the zenith.
+    zenith: [f64; 3],
+    sun_radiance: Color3<T>,
+    ground: Color3<T>,
+}
+
+impl<T: SVecElem + Float> Daylight<T> {
+    /// The sun stands `elevation` degrees above the horizon, `azimuth`
+    /// degrees from -z towards +x. The model holds for turbidity in 2..=10
+    /// and a sun above the horizon.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, ground_albedo: Color3<T>) -> Self {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let sun = Vec3::new(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos());
+        let theta = PI / 2. - elevation;
+        let t = turbidity;
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta);
+        let cubic = |c: [f64; 4]| c[0] * theta.powi(3) + c[1] * theta.powi(2) + c[2] * theta + c[3];
+        let zenith = [
+            // Preetham's zenith luminance is in kcd/m².
+            1000. * ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192),
+            t * t * cubic([0.00166, -0.00375, 0.00209, 0.])
+                + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+                + cubic([0.11693, -0.21196, 0.06052, 0.25886]),
+            t * t * cubic([0.00275, -0.00610, 0.00317, 0.])
+                + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+                + cubic([0.15346, -0.26756, 0.06670, 0.26688]),
+        ];
+
+        let mut daylight = Self {
+            sun,
+            sun_zenith: theta,
+            perez,
+            zenith,
+            sun_radiance: Self::rgb(sun_xyz(theta, turbidity)),
+            ground: Color3::new(0., 0., 0.),
+        };
+        daylight.ground = ground_albedo * daylight.irradiance() / T::from_f64(PI).unwrap();
+        daylight
+    }
+
+    fn rgb(xyz: [f64; 3]) -> Color3<T> {
+        let [r, g, b] = xyz_to_srgb(xyz).map(|c| c.max(0.) / CANDELA_PER_UNIT);
+        Color3::new(r, g, b)
+    }
+
+    /// Radiance of the sky alone, or of the ground below the horizon.
+    fn sky(&self, direction: &Vec3<T>) -> Color3<T> {
+        let d = direction.to_unit();
+        if d.y() < T::zero() {
+            return self.ground;
+        }
+        let cos_theta = d.y().to_f64().unwrap().max(1e-3);
+        let cos_gamma = dot(&d, &self.sun).to_f64().unwrap().clamp(-1., 1.);
+        let perez = |c: &[f64; 5], cos_theta: f64, cos_gamma: f64| {
+            let gamma = cos_gamma.acos();
+            (1. + c[0] * (c[1] / cos_theta).exp()) * (1. + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+        };
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            let c = &self.perez[i];
+            self.zenith[i] * perez(c, cos_theta, cos_gamma) / perez(c, 1., self.sun_zenith.cos())
+        });
+        Self::rgb([x / y * luminance, luminance, (1. - x - y) / y * luminance])
+    }
+
+    /// Radiance arriving from `direction`: sky or ground, plus the sun
+    /// inside its disk.
+    pub fn radiance(&self, direction: &Vec3<T>) -> Color3<T> {
+        match self.in_sun(direction) {
+            true => self.sky(direction) + self.sun_radiance,
+            false => self.sky(direction),
+        }
+    }
+
+    /// Sun and sky irradiance on an upward facing surface.
+    pub fn irradiance(&self) -> Color3<T> {
+        // Midpoint rule over the upper hemisphere; the sky is smooth.
+        let (n_theta, n_phi) = (64, 256);
+        let (d_theta, d_phi) = (PI / 2. / n_theta as f64, 2. * PI / n_phi as f64);
+        let mut sky = Color3::new(0., 0., 0.);
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            let weight = T::from_f64(theta.cos() * theta.sin() * d_theta * d_phi).unwrap();
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                sky += self.sky(&Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())) * weight;
+            }
+        }
+        let cos_max = SUN_ANGULAR_RADIUS.to_radians().cos();
+        let sun = 2. * PI * (1. - cos_max) * self.sun_zenith.cos().max(0.);
+        sky + self.sun_radiance * T::from_f64(sun).unwrap()
+    }
+
+    fn in_sun(&self, direction: &Vec3<T>) -> bool {
+        dot(&direction.to_unit(), &self.sun).to_f64().unwrap() >= SUN_ANGULAR_RADIUS.to_radians().cos()
+    }
+
+    /// A direction uniformly within the sun's disk.
+    pub fn sample_sun(&self, sampler: &mut Sampler) -> Vec3<T> {
+        // In f64: the disk is too small for the cone to be resolved in f32.
+        let cos_max = SUN_ANGULAR_RADIUS.to_radians().cos();
+        let cos_theta = 1. - sampler.gen::<f64>() * (1. - cos_max);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = sampler.gen_range(0.0..2. * PI);
+        let (t, b) = orthonormal_basis(&self.sun);
+        let f = |v: f64| T::from_f64(v).unwrap();
+        self.sun * f(cos_theta) + (t * f(phi.cos()) + b * f(phi.sin())) * f(sin_theta)
+    }
+
+    /// Solid angle density of [`Self::sample_sun`] drawing `direction`.
+    pub fn sun_pdf(&self, direction: &Vec3<T>) -> T {
+        match self.in_sun(direction) {
+            true => T::from_f64(1. / (2. * PI * (1. - SUN_ANGULAR_RADIUS.to_radians().cos()))).unwrap(),
+            false => T::zero(),
+        }
+    }
+}
+
+/// CIE XYZ of sunlight, in cd/m², after Rayleigh and aerosol extinction
+/// along the air mass for a sun `theta` radians from the zenith. Ozone and
+/// water vapour absorption are left out.
+fn sun_xyz(theta: f64, turbidity: f64) -> [f64; 3] {
+    let (h, c, k) = (6.62607015e-34, 299_792_458., 1.380649e-23);
+    let air_mass = 1. / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253));
+    let beta = 0.04608365822050 * turbidity - 0.04586025928522;
+    let mut xyz = [0.; 3];
+    for nm in LAMBDA_MIN as u32..=LAMBDA_MAX as u32 {
+        let (metres, microns) = (nm as f64 * 1e-9, nm as f64 * 1e-3);
+        let planck = 2. * h * c * c / metres.powi(5) / ((h * c / (metres * k * 5778.)).exp() - 1.);
+        let rayleigh = (-0.008735 * microns.powf(-4.08) * air_mass).exp();
+        let aerosol = (-beta * microns.powf(-1.3) * air_mass).exp();
+        // 683 lm/W, and spectral radiance per metre integrated in 1 nm steps.
+        let value = 683. * planck * rayleigh * aerosol * 1e-9;
+        for (sum, cmf) in xyz.iter_mut().zip(cie_xyz(nm as f64)) {
+            *sum += value * cmf;
+        }
+    }
+    xyz
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tonemap::luminance;
+
+    fn luma(c: Color3<f64>) -> f64 {
+        luminance([c.x(), c.y(), c.z()])
+    }
+
+    #[test]
+    fn preetham_sky() {
+        let sky = Daylight::<f64>::new(45., 30., 3., Color3::new(0.3, 0.3, 0.3));
+        // The zenith shows Preetham's zenith luminance, 7.32 kcd/m² here.
+        let zenith = luma(sky.radiance(&Vec3::new(0., 1., 0.))) * CANDELA_PER_UNIT;
+        assert!((zenith - 7320.).abs() < 10., "{}", zenith);
+
+        // Symmetric about the sun's vertical plane, brightest around the
+        // sun and blue away from it.
+        let (s, c) = (30f64.to_radians().sin(), 30f64.to_radians().cos());
+        let (left, right) = (Vec3::new(s + 0.3 * c, 0.4, -c + 0.3 * s), Vec3::new(s - 0.3 * c, 0.4, -c - 0.3 * s));
+        assert!((sky.radiance(&left) - sky.radiance(&right)).length() < 1e-9);
+        let (near, away) = (sky.radiance(&Vec3::new(s, 1.2, -c)), sky.radiance(&Vec3::new(-s, 1.2, c)));
+        assert!(luma(near) > 2. * luma(away));
+        assert!(away.z() > away.x());
+
+        // The ground reflects what falls on it.
+        let ground = sky.radiance(&Vec3::new(0., -1., 0.));
+        assert!((ground - sky.irradiance() * 0.3 / PI).length() < 1e-12);
+        assert!((luma(sky.irradiance()) - 2.9).abs() < 0.2, "{:?}", sky.irradiance());
+    }
+
+    #[test]
+    fn sun() {
+        let noon = Daylight::<f64>::new(80., 0., 3., Color3::new(0., 0., 0.));
+        let evening = Daylight::<f64>::new(5., 0., 3., Color3::new(0., 0., 0.));
+        let hazy = Daylight::<f64>::new(80., 0., 8., Color3::new(0., 0., 0.));
+        let up = |sky: &Daylight<f64>| sky.sun_radiance;
+        assert!(luma(up(&evening)) < luma(up(&noon)) / 5.);
+        assert!(luma(up(&hazy)) < luma(up(&noon)));
+        let warmth = |c: Color3<f64>| c.x() / c.z();
+        assert!(warmth(up(&evening)) > 2. * warmth(up(&noon)));
+        assert!(warmth(up(&noon)) > 1.);
+
+        let mut sampler = Sampler::new(3, 0);
+        let pdf = noon.sun_pdf(&noon.sun);
+        for _ in 0..100 {
+            let direction = noon.sample_sun(&mut sampler);
+            assert!((direction.length() - 1.).abs() < 1e-9);
+            assert_eq!(noon.sun_pdf(&direction), pdf);
+            assert_eq!(noon.radiance(&direction), noon.sky(&direction) + noon.sun_radiance);
+        }
+        assert!((1. / pdf - 6.8e-5).abs() < 1e-6);
+        assert_eq!(noon.sun_pdf(&Vec3::new(0., 1., 0.)), 0.);
+    }
+}
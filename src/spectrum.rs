@@ -0,0 +1,249 @@
+use num::Float;
+
+use crate::common::SVecElem;
+use crate::vec3::*;
+
+/// Visible range sampled by spectral rendering, in nanometres.
+pub const LAMBDA_MIN: f64 = 380.;
+pub const LAMBDA_MAX: f64 = 720.;
+
+/// Spacing of the uplifting basis tables, which run from `LAMBDA_MIN` to
+/// `LAMBDA_MAX` inclusive.
+const BASIS_STEP: f64 = 10.;
+
+// Smits-style basis for turning RGB into spectra: the smoothest reflectances
+// in [0, 1] whose colour under `cie_xyz` and `XYZ_TO_RGB` is each primary
+// and secondary, fitted offline. Complementary pairs sum to one.
+const CYAN: [f64; 35] = [
+    0.8247, 0.8333, 0.8433, 0.8606, 0.8925, 0.9360, 0.9760, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000,
+    1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 0.8287, 0.4352, 0.0894, 0.0000,
+    0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0020, 0.0056, 0.0095,
+];
+const MAGENTA: [f64; 35] = [
+    1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 0.9917, 0.8910, 0.6930, 0.4449,
+    0.2141, 0.0584, 0.0000, 0.0000, 0.0000, 0.0000, 0.0245, 0.1372, 0.3352, 0.5733, 0.7911, 0.9392,
+    1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 0.9999, 0.9998, 0.9996,
+];
+const YELLOW: [f64; 35] = [
+    0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0240, 0.0895, 0.2000, 0.3468, 0.5125,
+    0.6762, 0.8179, 0.9217, 0.9807, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 0.9992, 0.9946,
+    0.9865, 0.9764, 0.9656, 0.9550, 0.9448, 0.9352, 0.9259, 0.9168, 0.9077, 0.8987, 0.8897,
+];
+const RED: [f64; 35] = [
+    0.1752, 0.1667, 0.1566, 0.1393, 0.1075, 0.0640, 0.0240, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000,
+    0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.1713, 0.5648, 0.9106, 1.0000,
+    1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 0.9980, 0.9944, 0.9905,
+];
+const GREEN: [f64; 35] = [
+    0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0083, 0.1090, 0.3070, 0.5551,
+    0.7859, 0.9416, 1.0000, 1.0000, 1.0000, 1.0000, 0.9755, 0.8628, 0.6648, 0.4267, 0.2089, 0.0608,
+    0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0001, 0.0002, 0.0004,
+];
+const BLUE: [f64; 35] = [
+    1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 0.9760, 0.9105, 0.8000, 0.6532, 0.4875,
+    0.3238, 0.1821, 0.0783, 0.0193, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0000, 0.0009, 0.0055,
+    0.0136, 0.0237, 0.0344, 0.0449, 0.0550, 0.0645, 0.0737, 0.0827, 0.0915, 0.1004, 0.1092,
+];
+
+/// CIE XYZ to linear sRGB, white balanced by XYZ scaling so that a flat
+/// spectrum (the equal-energy illuminant) comes out as (1, 1, 1) and RGB
+/// white lights stay white.
+const XYZ_TO_RGB: [[f64; 3]; 3] = [
+    [2.6990144229899355, -1.2803016878415114, -0.41523297533826115],
+    [-1.0206694499105295, 1.9755019894045727, 0.0437598550454488],
+    [0.06128747165004847, -0.22472083952680147, 1.1644626222106633],
+];
+
+/// ∫ ȳ(λ) dλ over the sampled range, so a flat unit spectrum has Y = 1.
+const Y_INTEGRAL: f64 = 106.91186760848493;
+
+fn basis(table: &[f64; 35], lambda: f64) -> f64 {
+    let x = ((lambda - LAMBDA_MIN) / BASIS_STEP).clamp(0., (table.len() - 1) as f64);
+    let i = (x as usize).min(table.len() - 2);
+    let f = x - i as f64;
+    table[i] * (1. - f) + table[i + 1] * f
+}
+
+/// Value at `lambda` of a smooth spectrum with colour `rgb` (Smits 1999):
+/// white up to the smallest channel, then a secondary and a primary.
+/// Linear in `rgb`, so it serves reflectances and emission alike.
+pub fn rgb_to_spectrum(rgb: [f64; 3], lambda: f64) -> f64 {
+    let [r, g, b] = rgb.map(|c| c.max(0.));
+    let (white, secondary, primary) = if r <= g && r <= b {
+        if g <= b { (r, (g - r, &CYAN), (b - g, &BLUE)) } else { (r, (b - r, &CYAN), (g - b, &GREEN)) }
+    } else if g <= r && g <= b {
+        if r <= b { (g, (r - g, &MAGENTA), (b - r, &BLUE)) } else { (g, (b - g, &MAGENTA), (r - b, &RED)) }
+    } else if r <= g {
+        (b, (r - b, &YELLOW), (g - r, &GREEN))
+    } else {
+        (b, (g - b, &YELLOW), (r - g, &RED))
+    };
+    white + secondary.0 * basis(secondary.1, lambda) + primary.0 * basis(primary.1, lambda)
+}
+
+fn lobe(lambda: f64, mu: f64, below: f64, above: f64) -> f64 {
+    let t = (lambda - mu) / if lambda < mu { below } else { above };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 2° colour matching functions, from the multi-lobe Gaussian fit
+/// of Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(lambda: f64) -> [f64; 3] {
+    [
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7) - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    ]
+}
+
+/// The wavelengths one spectral path carries, in nanometres: a uniformly
+/// sampled hero and two more evenly spread around the visible range (Wilkie
+/// et al. 2014). A `Color3` on such a path holds one value per wavelength.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wavelengths {
+    pub lambda: [f64; 3],
+    /// Set once a dispersive interface has sent the path the way only the
+    /// hero wavelength would go.
+    pub hero_only: bool,
+}
+
+impl Wavelengths {
+    pub fn sample(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let lambda = [0., 1., 2.].map(|i| {
+            let l = hero + i * range / 3.;
+            if l > LAMBDA_MAX { l - range } else { l }
+        });
+        Self { lambda, hero_only: false }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// The same wavelengths, following the hero alone from here on.
+    pub fn terminate_secondary(self) -> Self {
+        Self { hero_only: true, ..self }
+    }
+
+    /// Weights that move a path's value onto the hero alone when it has just
+    /// been terminated, keeping the estimate unbiased; ones otherwise.
+    pub fn termination_weight<T: SVecElem + Float>(before: &Self, after: &Self) -> Color3<T> {
+        match !before.hero_only && after.hero_only {
+            true => Color3::new(3., 0., 0.),
+            false => Color3::new(1., 1., 1.),
+        }
+    }
+
+    /// The spectrum of colour `rgb` at these wavelengths.
+    pub fn uplift<T: SVecElem + Float>(&self, rgb: &Color3<T>) -> Color3<T> {
+        let rgb = [rgb.x(), rgb.y(), rgb.z()].map(|c| c.to_f64().unwrap());
+        let [a, b, c] = self.lambda.map(|lambda| rgb_to_spectrum(rgb, lambda));
+        Color3::new(a, b, c)
+    }
+
+    /// Linear RGB of radiance `values` sampled at these wavelengths: a one
+    /// sample estimate of the CIE XYZ integrals, converted to RGB.
+    pub fn to_rgb<T: SVecElem + Float>(self, values: &Color3<T>) -> Color3<T> {
+        let scale = (LAMBDA_MAX - LAMBDA_MIN) / (3. * Y_INTEGRAL);
+        let mut xyz = [0.; 3];
+        for (i, lambda) in self.lambda.iter().enumerate() {
+            let value = values[i].to_f64().unwrap() * scale;
+            for (sum, cmf) in xyz.iter_mut().zip(cie_xyz(*lambda)) {
+                *sum += value * cmf;
+            }
+        }
+        let [r, g, b] = XYZ_TO_RGB.map(|row| row[0] * xyz[0] + row[1] * xyz[1] + row[2] * xyz[2]);
+        Color3::new(r, g, b)
+    }
+}
+
+/// Wavelength-dependent index of refraction, with wavelengths in
+/// micrometres as the coefficients are usually tabulated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispersion {
+    /// n = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    /// n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// The sodium D line, where glasses quote their nominal index.
+    pub const D_LINE: f64 = 589.3;
+
+    /// Borosilicate crown glass (Schott N-BK7).
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+
+    /// Index at `lambda` nanometres.
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let l2 = (lambda * 1e-3).powi(2);
+        match *self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => (1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Colour of a spectrum, integrating at 1 nm steps.
+    fn color_of(spectrum: impl Fn(f64) -> f64) -> [f64; 3] {
+        let mut rgb = [0.; 3];
+        let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + i as f64 + 0.5;
+            let w = Wavelengths { lambda: [lambda; 3], hero_only: false };
+            let value = spectrum(lambda) / steps as f64;
+            let c = w.to_rgb(&Color3::<f64>::new(value, value, value));
+            rgb = [rgb[0] + c.x(), rgb[1] + c.y(), rgb[2] + c.z()];
+        }
+        rgb
+    }
+
+    #[test]
+    fn round_trips() {
+        for rgb in [[1., 1., 1.], [0.8, 0.2, 0.1], [0.1, 0.5, 0.9], [0.3, 0.9, 0.2], [0., 0., 1.], [4., 3., 2.]] {
+            let back = color_of(|lambda| rgb_to_spectrum(rgb, lambda));
+            for (a, b) in rgb.iter().zip(back) {
+                assert!((a - b).abs() < 2e-3 * a.max(1.), "{:?} came back as {:?}", rgb, back);
+            }
+        }
+        // Reflectances stay physical.
+        for lambda in [380., 455., 532.5, 600., 720.] {
+            let value = rgb_to_spectrum([0.9, 0.05, 0.3], lambda);
+            assert!((0. ..=1.).contains(&value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn wavelengths() {
+        let w = Wavelengths::sample(0.9);
+        assert!((w.hero() - 686.).abs() < 1e-9);
+        let mut sorted = w.lambda;
+        sorted.sort_by(f64::total_cmp);
+        assert!(sorted.iter().all(|l| (LAMBDA_MIN..=LAMBDA_MAX).contains(l)));
+        assert!((sorted[1] - sorted[0] - 340. / 3.).abs() < 1e-9 && (sorted[2] - sorted[1] - 340. / 3.).abs() < 1e-9);
+
+        let terminated = w.terminate_secondary();
+        assert_eq!(Wavelengths::termination_weight::<f64>(&w, &terminated), Color3::new(3., 0., 0.));
+        assert_eq!(Wavelengths::termination_weight::<f64>(&terminated, &terminated), Color3::new(1., 1., 1.));
+    }
+
+    #[test]
+    fn dispersion() {
+        // N-BK7 is quoted as n_d = 1.5168, and blue bends more than red.
+        let bk7 = Dispersion::BK7;
+        assert!((bk7.ior(Dispersion::D_LINE) - 1.5168).abs() < 1e-4);
+        assert!(bk7.ior(450.) > bk7.ior(650.));
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+        assert!((cauchy.ior(500.) - 1.516).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,334 @@
+use num::Float;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::common::SVecElem;
+use crate::image::*;
+use crate::perlin::*;
+use crate::tonemap::TransferFunction;
+use crate::vec3::*;
+
+/// Colour as a function of surface coordinates `(u, v)` and hit point `p`.
+pub trait Texture<T: SVecElem>: Send + Sync {
+    fn value(&self, u: T, v: T, p: &Point3<T>) -> Color3<T>;
+}
+
+pub struct SolidColor<T: SVecElem> {
+    pub color: Color3<T>,
+}
+
+impl<T: SVecElem> Texture<T> for SolidColor<T> {
+    fn value(&self, _u: T, _v: T, _p: &Point3<T>) -> Color3<T> {
+        self.color
+    }
+}
+
+/// Solid checker pattern: alternates between two textures in a 3D grid of
+/// cubes with edge `scale`, so it needs no UVs.
+pub struct Checker<T: SVecElem> {
+    pub scale: T,
+    pub even: Arc<dyn Texture<T>>,
+    pub odd: Arc<dyn Texture<T>>,
+}
+
+impl<T: SVecElem + Float> Texture<T> for Checker<T> {
+    fn value(&self, u: T, v: T, p: &Point3<T>) -> Color3<T> {
+        let cell = |x: T| (x / self.scale).floor().to_i64().unwrap_or(0);
+        if (cell(p.x()) + cell(p.y()) + cell(p.z())).rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GradientAxis {
+    U,
+    #[default]
+    V,
+}
+
+/// Linear blend from `start` at 0 to `end` at 1 along one UV axis.
+pub struct Gradient<T: SVecElem> {
+    pub start: Color3<T>,
+    pub end: Color3<T>,
+    pub axis: GradientAxis,
+}
+
+impl<T: SVecElem + Float> Texture<T> for Gradient<T> {
+    fn value(&self, u: T, v: T, _p: &Point3<T>) -> Color3<T> {
+        let t = match self.axis {
+            GradientAxis::U => u,
+            GradientAxis::V => v,
+        };
+        let t = t.max(T::zero()).min(T::one());
+        self.start * (T::one() - t) + self.end * t
+    }
+}
+
+/// How texel coordinates outside the image are brought back in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    fn apply(self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n { m } else { 2 * n - 1 - m }
+            }
+        };
+        i as usize
+    }
+
+    /// Brings texture coordinate `t` into a small range that `apply` treats
+    /// the same, so far away coordinates cannot overflow texel indices.
+    fn reduce<T: Float>(self, t: T) -> T {
+        let two = T::one() + T::one();
+        match self {
+            WrapMode::Repeat => t - t.floor(),
+            WrapMode::Clamp => t.max(-T::one()).min(two),
+            WrapMode::Mirror => t - (t / two).floor() * two,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+/// Image mapped over `u` (left to right) and `v` (bottom to top).
+pub struct ImageTexture<T: SVecElem> {
+    width: usize,
+    height: usize,
+    /// Linear texels, top row first.
+    texels: Vec<Color3<T>>,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+}
+
+impl<T: SVecElem + Float> ImageTexture<T> {
+    /// Takes 8-bit sRGB-encoded pixels and stores them as linear colours.
+    pub fn new(image: &RgbImage, wrap: WrapMode, filter: Filter) -> Self {
+        let decode = |c: u8| TransferFunction::Srgb.decode(c as f64 / 255.);
+        let texels = image.pixels.iter()
+            .map(|&Color3(r, g, b)| Color3::new(decode(r), decode(g), decode(b)))
+            .collect();
+        Self { width: image.width as usize, height: image.height as usize, texels, wrap, filter }
+    }
+
+    pub fn load(path: impl AsRef<Path>, wrap: WrapMode, filter: Filter) -> Result<Self, ImageError> {
+        Ok(Self::new(&read_image(path)?, wrap, filter))
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color3<T> {
+        self.texels[self.wrap.apply(y, self.height) * self.width + self.wrap.apply(x, self.width)]
+    }
+}
+
+impl<T: SVecElem + Float> Texture<T> for ImageTexture<T> {
+    fn value(&self, u: T, v: T, _p: &Point3<T>) -> Color3<T> {
+        let finite = |t: T| if t.is_finite() { self.wrap.reduce(t) } else { T::zero() };
+        let half = T::from_f64(0.5).unwrap();
+        // Continuous texel coordinates, with texel centres at integers.
+        let x = finite(u) * T::from_usize(self.width).unwrap() - half;
+        let y = (T::one() - finite(v)) * T::from_usize(self.height).unwrap() - half;
+
+        match self.filter {
+            Filter::Nearest => self.texel((x + half).floor().to_i64().unwrap(), (y + half).floor().to_i64().unwrap()),
+            Filter::Bilinear => {
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0.to_i64().unwrap(), y0.to_i64().unwrap());
+                let top = self.texel(x0, y0) * (T::one() - fx) + self.texel(x0 + 1, y0) * fx;
+                let bottom = self.texel(x0, y0 + 1) * (T::one() - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+                top * (T::one() - fy) + bottom * fy
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseKind {
+    #[default]
+    Fbm,
+    Turbulence,
+    Ridged,
+}
+
+fn to_f64<T: SVecElem + Float>(p: &Point3<T>, scale: f64) -> [f64; 3] {
+    [p.x(), p.y(), p.z()].map(|v| v.to_f64().unwrap() * scale)
+}
+
+fn mix<T: SVecElem + Float>(a: Color3<T>, b: Color3<T>, t: f64) -> Color3<T> {
+    let t = T::from_f64(t.clamp(0., 1.)).unwrap();
+    a * (T::one() - t) + b * t
+}
+
+/// Solid noise blending from `low` to `high`; `scale` is the base frequency.
+pub struct NoiseTexture<T: SVecElem> {
+    pub perlin: Perlin,
+    pub kind: NoiseKind,
+    pub scale: f64,
+    pub octaves: Octaves,
+    pub low: Color3<T>,
+    pub high: Color3<T>,
+}
+
+impl<T: SVecElem + Float> Texture<T> for NoiseTexture<T> {
+    fn value(&self, _u: T, _v: T, p: &Point3<T>) -> Color3<T> {
+        let p = to_f64(p, self.scale);
+        let t = match self.kind {
+            NoiseKind::Fbm => 0.5 * (1. + self.perlin.fbm(p, &self.octaves)),
+            NoiseKind::Turbulence => self.perlin.turbulence(p, &self.octaves),
+            NoiseKind::Ridged => self.perlin.ridged(p, &self.octaves),
+        };
+        mix(self.low, self.high, t)
+    }
+}
+
+/// Veins running across z, bent by turbulence.
+pub struct Marble<T: SVecElem> {
+    pub perlin: Perlin,
+    pub scale: f64,
+    pub base: Color3<T>,
+    pub vein: Color3<T>,
+}
+
+impl<T: SVecElem + Float> Texture<T> for Marble<T> {
+    fn value(&self, _u: T, _v: T, p: &Point3<T>) -> Color3<T> {
+        let p = to_f64(p, self.scale);
+        let octaves = Octaves { count: 7, ..Default::default() };
+        let phase = p[2] + 10. * self.perlin.turbulence(p, &octaves);
+        // Sharpen the troughs of the sine so veins are thin against the base.
+        let vein = (0.5 * (1. - phase.sin())).powi(3);
+        mix(self.base, self.vein, vein)
+    }
+}
+
+/// Growth rings around the y axis, `rings` per unit of radius, wobbled by
+/// noise of frequency `scale`.
+pub struct Wood<T: SVecElem> {
+    pub perlin: Perlin,
+    pub scale: f64,
+    pub rings: f64,
+    pub light: Color3<T>,
+    pub dark: Color3<T>,
+}
+
+impl<T: SVecElem + Float> Texture<T> for Wood<T> {
+    fn value(&self, _u: T, _v: T, p: &Point3<T>) -> Color3<T> {
+        let q = to_f64(p, 1.);
+        let octaves = Octaves { count: 3, ..Default::default() };
+        let radius = q[0].hypot(q[2]) + 0.1 * self.perlin.fbm(q.map(|v| v * self.scale), &octaves);
+        let ring = (radius * self.rings).fract().abs();
+        // Late wood: a quick ramp to dark near the end of each ring.
+        mix(self.light, self.dark, ring.powi(4))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin() -> Point3<f64> {
+        Point3::new(0., 0., 0.)
+    }
+
+    fn solid(c: f64) -> Arc<dyn Texture<f64>> {
+        Arc::new(SolidColor { color: Color3::new(c, c, c) })
+    }
+
+    #[test]
+    fn checker_cells() {
+        let checker = Checker { scale: 0.5, even: solid(1.), odd: solid(0.) };
+        let at = |x, y, z| checker.value(0., 0., &Point3::new(x, y, z)).x();
+        assert_eq!(at(0.1, 0.1, 0.1), 1.);
+        assert_eq!(at(0.6, 0.1, 0.1), 0.);
+        assert_eq!(at(-0.1, 0.1, 0.1), 0.);
+        assert_eq!(at(0.6, 0.6, -0.1), 0.);
+        assert_eq!(at(0.6, 0.6, 0.1), 1.);
+    }
+
+    #[test]
+    fn gradient() {
+        let g = Gradient { start: Color3::new(0., 0., 0.), end: Color3::new(1., 2., 4.), axis: GradientAxis::U };
+        assert_eq!(g.value(0.25, 0.9, &origin()), Color3::new(0.25, 0.5, 1.));
+        assert_eq!(g.value(2., 0., &origin()), Color3::new(1., 2., 4.));
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let wrapped = |mode: WrapMode| (-3..7).map(|i| mode.apply(i, 3)).collect::<Vec<_>>();
+        assert_eq!(wrapped(WrapMode::Repeat), [0, 1, 2, 0, 1, 2, 0, 1, 2, 0]);
+        assert_eq!(wrapped(WrapMode::Clamp), [0, 0, 0, 0, 1, 2, 2, 2, 2, 2]);
+        assert_eq!(wrapped(WrapMode::Mirror), [2, 1, 0, 0, 1, 2, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn image_lookup() {
+        // Black and white on the top row, mid grey and red below.
+        let image = RgbImage { width: 2, height: 2, pixels: vec![Color3(0, 0, 0), Color3(255, 255, 255), Color3(188, 188, 188), Color3(255, 0, 0)] };
+        let nearest = ImageTexture::<f64>::new(&image, WrapMode::Clamp, Filter::Nearest);
+        assert_eq!(nearest.value(0.9, 0.9, &origin()), Color3::new(1., 1., 1.));
+        assert_eq!(nearest.value(0.9, 0.1, &origin()), Color3::new(1., 0., 0.));
+        assert!((nearest.value(0.1, 0.1, &origin()).x() - 0.5).abs() < 0.005);
+
+        let bilinear = ImageTexture::<f64>::new(&image, WrapMode::Clamp, Filter::Bilinear);
+        assert_eq!(bilinear.value(0.25, 0.75, &origin()), Color3::new(0., 0., 0.));
+        assert_eq!(bilinear.value(0.5, 0.75, &origin()), Color3::new(0.5, 0.5, 0.5));
+        assert_eq!(bilinear.value(0.75, 0.5, &origin()), Color3::new(1., 0.5, 0.5));
+
+        // Halfway across the seam a repeating texture blends both edges.
+        let repeat = ImageTexture::<f64>::new(&image, WrapMode::Repeat, Filter::Bilinear);
+        assert_eq!(repeat.value(1., 0.75, &origin()), Color3::new(0.5, 0.5, 0.5));
+        assert_eq!(repeat.value(f64::NAN, 0.75, &origin()), repeat.value(0., 0.75, &origin()));
+
+        // Coordinates far beyond the texel index range, as from grazing
+        // hits on a plane.
+        for (wrap, same) in [(WrapMode::Repeat, (0., 0.)), (WrapMode::Clamp, (5., -5.)), (WrapMode::Mirror, (0., 0.))] {
+            let texture = ImageTexture::<f64>::new(&image, wrap, Filter::Bilinear);
+            assert_eq!(texture.value(1e30, -1e30, &origin()), texture.value(same.0, same.1, &origin()), "{:?}", wrap);
+        }
+        let mirror = ImageTexture::<f64>::new(&image, WrapMode::Mirror, Filter::Bilinear);
+        assert_eq!(mirror.value(-2.75, 3.25, &origin()), mirror.value(0.75, 0.75, &origin()));
+    }
+
+    #[test]
+    fn noise_textures_stay_between_colours() {
+        let (low, high) = (Color3::new(0.1, 0.2, 0.3), Color3::new(0.9, 0.8, 0.7));
+        let textures: Vec<Box<dyn Texture<f64>>> = vec![
+            Box::new(NoiseTexture { perlin: Perlin::new(1), kind: NoiseKind::Fbm, scale: 4., octaves: Octaves::default(), low, high }),
+            Box::new(NoiseTexture { perlin: Perlin::new(1), kind: NoiseKind::Turbulence, scale: 4., octaves: Octaves::default(), low, high }),
+            Box::new(NoiseTexture { perlin: Perlin::new(1), kind: NoiseKind::Ridged, scale: 4., octaves: Octaves::default(), low, high }),
+            Box::new(Marble { perlin: Perlin::new(2), scale: 3., base: high, vein: low }),
+            Box::new(Wood { perlin: Perlin::new(3), scale: 2., rings: 8., light: high, dark: low }),
+        ];
+        for texture in textures.iter() {
+            let values: Vec<f64> = (0..500)
+                .map(|i| texture.value(0., 0., &Point3::new(i as f64 * 0.071, (i as f64 * 0.13).cos(), i as f64 * -0.017)).x())
+                .collect();
+            assert!(values.iter().all(|&r| (0.1 - 1e-12..=0.9 + 1e-12).contains(&r)));
+            // Not constant: the pattern actually varies over space.
+            let (min, max) = values.iter().fold((1f64, 0f64), |(a, b), &r| (a.min(r), b.max(r)));
+            assert!(max - min > 0.2, "{} {}", min, max);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use clap::ValueEnum;
+
+use crate::vec3::*;
+
+type Mat3 = [[f64; 3]; 3];
+
+fn mul(m: &Mat3, c: [f64; 3]) -> [f64; 3] {
+    [
+        m[0][0] * c[0] + m[0][1] * c[1] + m[0][2] * c[2],
+        m[1][0] * c[0] + m[1][1] * c[1] + m[1][2] * c[2],
+        m[2][0] * c[0] + m[2][1] * c[1] + m[2][2] * c[2],
+    ]
+}
+
+fn mul_mat(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut m = [[0.; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+// Linear sRGB (D65) <-> CIE XYZ.
+const SRGB_TO_XYZ: Mat3 = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+];
+const XYZ_TO_SRGB: Mat3 = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+const BRADFORD: Mat3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+const BRADFORD_INV: Mat3 = [
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867],
+];
+const D65_XYZ: [f64; 3] = [0.95047, 1.0, 1.08883];
+
+// Stephen Hill's fit of the ACES RRT + sRGB ODT.
+const ACES_INPUT: Mat3 = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+const ACES_OUTPUT: Mat3 = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+// Inset/outset matrices of the minimal AgX approximation.
+const AGX_INSET: Mat3 = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+const AGX_OUTSET: Mat3 = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+pub fn luminance(c: [f64; 3]) -> f64 {
+    SRGB_TO_XYZ[1][0] * c[0] + SRGB_TO_XYZ[1][1] * c[1] + SRGB_TO_XYZ[1][2] * c[2]
+}
+
+pub fn xyz_to_srgb(c: [f64; 3]) -> [f64; 3] {
+    mul(&XYZ_TO_SRGB, c)
+}
+
+/// Chromaticity of a Planckian radiator (Kang et al. 2002), 1667 K to 25000 K.
+pub fn planckian_xy(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(1667., 25000.);
+    let x = if t < 4000. {
+        -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t.powi(3) + 2.1070379e6 / t.powi(2) + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t < 2222. {
+        -1.1063814 * x.powi(3) - 1.34811020 * x.powi(2) + 2.18555832 * x - 0.20219683
+    } else if t < 4000. {
+        -0.9549476 * x.powi(3) - 1.37418593 * x.powi(2) + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x.powi(3) - 5.87338670 * x.powi(2) + 3.75112997 * x - 0.37001483
+    };
+    (x, y)
+}
+
+/// Bradford chromatic adaptation from a scene illuminant to D65.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhiteBalance {
+    matrix: Mat3,
+}
+
+impl WhiteBalance {
+    /// Neutralises light of the given colour temperature.
+    pub fn from_temperature(kelvin: f64) -> Self {
+        let (x, y) = planckian_xy(kelvin);
+        let source = mul(&BRADFORD, [x / y, 1., (1. - x - y) / y]);
+        let target = mul(&BRADFORD, D65_XYZ);
+        let scale = [
+            [target[0] / source[0], 0., 0.],
+            [0., target[1] / source[1], 0.],
+            [0., 0., target[2] / source[2]],
+        ];
+        let adapt = mul_mat(&BRADFORD_INV, &mul_mat(&scale, &BRADFORD));
+        Self { matrix: mul_mat(&XYZ_TO_SRGB, &mul_mat(&adapt, &SRGB_TO_XYZ)) }
+    }
+
+    pub fn apply(&self, c: [f64; 3]) -> [f64; 3] {
+        mul(&self.matrix, c)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ToneMapOperator {
+    /// Clip at 1
+    None,
+    /// L / (1 + L) on luminance
+    Reinhard,
+    /// Reinhard with a white point that maps to 1
+    ReinhardExtended,
+    /// ACES RRT + sRGB ODT, Stephen Hill's fit
+    Aces,
+    /// Minimal AgX: log encoding and a sigmoid in an inset gamut
+    Agx,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferFunction {
+    /// The piecewise sRGB OETF.
+    Srgb,
+    /// A pure power law, `v^(1/gamma)`.
+    Gamma(f64),
+}
+
+impl TransferFunction {
+    pub fn encode(&self, v: f64) -> f64 {
+        match *self {
+            TransferFunction::Srgb if v <= 0.0031308 => 12.92 * v,
+            TransferFunction::Srgb => 1.055 * v.powf(1. / 2.4) - 0.055,
+            TransferFunction::Gamma(gamma) => v.powf(1. / gamma),
+        }
+    }
+
+    /// Inverse of `encode`, from display values back to linear.
+    pub fn decode(&self, v: f64) -> f64 {
+        match *self {
+            TransferFunction::Srgb if v <= 0.04045 => v / 12.92,
+            TransferFunction::Srgb => ((v + 0.055) / 1.055).powf(2.4),
+            TransferFunction::Gamma(gamma) => v.powf(gamma),
+        }
+    }
+}
+
+/// The display pipeline applied to averaged linear radiance:
+/// exposure, white balance, tone mapping, OETF and quantisation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTransform {
+    /// Exposure adjustment in stops.
+    pub exposure: f64,
+    pub white_balance: Option<WhiteBalance>,
+    pub operator: ToneMapOperator,
+    /// Luminance mapped to white by `ReinhardExtended`.
+    pub white_point: f64,
+    pub transfer: TransferFunction,
+    /// Add triangular noise of one code value before quantising.
+    pub dither: bool,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.,
+            white_balance: None,
+            operator: ToneMapOperator::None,
+            white_point: 4.,
+            transfer: TransferFunction::Srgb,
+            dither: false,
+        }
+    }
+}
+
+fn scale_luminance(c: [f64; 3], mapped: impl Fn(f64) -> f64) -> [f64; 3] {
+    let l = luminance(c);
+    if l <= 0. {
+        return [0.; 3];
+    }
+    let s = mapped(l) / l;
+    [c[0] * s, c[1] * s, c[2] * s]
+}
+
+fn aces(c: [f64; 3]) -> [f64; 3] {
+    let rrt_odt = |v: f64| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+    mul(&ACES_OUTPUT, mul(&ACES_INPUT, c).map(rrt_odt))
+}
+
+fn agx(c: [f64; 3]) -> [f64; 3] {
+    let sigmoid = |x: f64| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    };
+    let encoded = mul(&AGX_INSET, c)
+        .map(|v| ((v.max(1e-10).log2() - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV)).clamp(0., 1.))
+        .map(sigmoid);
+    // The sigmoid output is display-referred with a 2.2 power; undo it so the
+    // regular OETF can be applied afterwards.
+    mul(&AGX_OUTSET, encoded).map(|v| v.max(0.).powf(2.2))
+}
+
+/// Per-pixel noise in [-1, 1] with a triangular distribution.
+fn triangular_noise(index: usize, channel: usize) -> f64 {
+    let mut z = (index as u64).wrapping_mul(3).wrapping_add(channel as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut next = || {
+        // splitmix64
+        z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut x = z;
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((x ^ (x >> 31)) >> 11) as f64 / (1u64 << 53) as f64
+    };
+    next() + next() - 1.
+}
+
+impl DisplayTransform {
+    /// Maps linear scene radiance to display-encoded values in [0, 1].
+    pub fn display(&self, c: [f64; 3]) -> [f64; 3] {
+        let gain = self.exposure.exp2();
+        let mut c = c.map(|v| if v.is_nan() { 0. } else { v * gain });
+        if let Some(wb) = &self.white_balance {
+            c = wb.apply(c);
+        }
+        let c = c.map(|v| v.max(0.));
+        let c = match self.operator {
+            ToneMapOperator::None => c,
+            ToneMapOperator::Reinhard => scale_luminance(c, |l| l / (1. + l)),
+            ToneMapOperator::ReinhardExtended => {
+                let white2 = self.white_point * self.white_point;
+                scale_luminance(c, |l| l * (1. + l / white2) / (1. + l))
+            }
+            ToneMapOperator::Aces => aces(c),
+            ToneMapOperator::Agx => agx(c),
+        };
+        c.map(|v| self.transfer.encode(v.clamp(0., 1.)))
+    }
+
+    /// Quantises pixel `index` to 8 bits.
+    pub fn quantize(&self, c: [f64; 3], index: usize) -> Color3<u8> {
+        let d = self.display(c);
+        let q = |channel: usize| {
+            let noise = if self.dither { triangular_noise(index, channel) } else { 0. };
+            (d[channel] * 255. + 0.5 + noise).floor().clamp(0., 255.) as u8
+        };
+        Color3(q(0), q(1), q(2))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f64; 3], expected: [f64; 3], eps: f64) {
+        for i in 0..3 {
+            assert!((actual[i] - expected[i]).abs() < eps, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn linear(operator: ToneMapOperator) -> DisplayTransform {
+        DisplayTransform { operator, transfer: TransferFunction::Gamma(1.), ..Default::default() }
+    }
+
+    #[test]
+    fn srgb_oetf() {
+        let srgb = TransferFunction::Srgb;
+        assert_eq!(srgb.encode(0.), 0.);
+        assert!((srgb.encode(0.002) - 0.02584).abs() < 1e-12);
+        assert!((srgb.encode(0.18) - 0.461356).abs() < 1e-6);
+        assert!((srgb.encode(0.5) - 0.735357).abs() < 1e-6);
+        assert!((srgb.encode(1.) - 1.).abs() < 1e-12);
+        assert_eq!(TransferFunction::Gamma(2.).encode(0.25), 0.5);
+        for v in [0., 0.002, 0.18, 0.5, 1.] {
+            assert!((srgb.decode(srgb.encode(v)) - v).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn exposure() {
+        let t = DisplayTransform { exposure: 1., ..linear(ToneMapOperator::None) };
+        assert_close(t.display([0.1, 0.2, 0.3]), [0.2, 0.4, 0.6], 1e-12);
+        let t = DisplayTransform { exposure: -2., ..linear(ToneMapOperator::None) };
+        assert_close(t.display([2., 1., 0.]), [0.5, 0.25, 0.], 1e-12);
+    }
+
+    #[test]
+    fn reinhard() {
+        assert_close(linear(ToneMapOperator::Reinhard).display([1., 1., 1.]), [0.5, 0.5, 0.5], 1e-6);
+        assert_close(linear(ToneMapOperator::Reinhard).display([3., 3., 3.]), [0.75, 0.75, 0.75], 1e-6);
+
+        let t = linear(ToneMapOperator::ReinhardExtended);
+        assert_close(t.display([1., 1., 1.]), [0.53125, 0.53125, 0.53125], 1e-6);
+        assert_close(t.display([4., 4., 4.]), [1., 1., 1.], 1e-6);
+    }
+
+    #[test]
+    fn aces_reference() {
+        let t = linear(ToneMapOperator::Aces);
+        assert_close(t.display([0.18, 0.18, 0.18]), [0.105591, 0.105591, 0.105590], 1e-6);
+        assert_close(t.display([1., 1., 1.]), [0.619115, 0.619115, 0.619109], 1e-6);
+        assert_close(t.display([4., 1., 0.25]), [0.994891, 0.663381, 0.316685], 1e-6);
+    }
+
+    #[test]
+    fn agx_reference() {
+        let t = linear(ToneMapOperator::Agx);
+        assert_close(t.display([0.18, 0.18, 0.18]), [0.214467, 0.214533, 0.214537], 1e-6);
+        assert_close(t.display([1., 1., 1.]), [0.589977, 0.590207, 0.590221], 1e-6);
+        assert_close(t.display([4., 1., 0.25]), [0.917927, 0.615797, 0.374931], 1e-6);
+    }
+
+    #[test]
+    fn white_balance() {
+        let (x, y) = planckian_xy(5000.);
+        assert!((x - 0.344992).abs() < 1e-6 && (y - 0.351587).abs() < 1e-6);
+
+        // A grey card lit by 3200 K light comes out neutral.
+        let (x, y) = planckian_xy(3200.);
+        let tungsten = mul(&XYZ_TO_SRGB, [x / y, 1., (1. - x - y) / y]);
+        let balanced = WhiteBalance::from_temperature(3200.).apply(tungsten);
+        assert!(balanced[0] > 0.9);
+        assert_close(balanced, [balanced[0]; 3], 1e-6);
+
+        // D65 sits just off the Planckian locus, so 6504 K is only a slight shift.
+        let daylight = WhiteBalance::from_temperature(6504.).apply([0.5, 0.5, 0.5]);
+        assert_close(daylight, [0.5, 0.5, 0.5], 0.03);
+    }
+
+    #[test]
+    fn quantisation_and_dither() {
+        let t = DisplayTransform::default();
+        assert_eq!(t.quantize([0., 0.0625, 1.], 0), Color3(0, 71, 255));
+        assert_eq!(t.quantize([f64::NAN, 2., -1.], 0), Color3(0, 255, 0));
+
+        // Dithering is deterministic and unbiased halfway between two codes.
+        let t = DisplayTransform { dither: true, ..linear(ToneMapOperator::None) };
+        let v = 100.5 / 255.;
+        let mean = (0..10000).map(|i| t.quantize([v, v, v], i).0 as f64).sum::<f64>() / 10000.;
+        assert!((mean - 100.5).abs() < 0.05);
+        assert_eq!(t.quantize([v, v, v], 42), t.quantize([v, v, v], 42));
+    }
+}
@@ -0,0 +1,474 @@
+use std::ops::Mul;
+use num::Float;
+
+use crate::aabb::*;
+use crate::common::SVecElem;
+use crate::vec3::*;
+
+/// Row-major 4x4 matrix acting on column vectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4<T: SVecElem>(pub [[T; 4]; 4]);
+
+impl<T: SVecElem + Float> Mat4<T> {
+    pub fn identity() -> Self {
+        let mut m = [[T::zero(); 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = T::one();
+        }
+        Self(m)
+    }
+
+    /// Affine matrix from a linear 3x3 part and a translation.
+    pub fn affine(linear: [[T; 3]; 3], translation: Vec3<T>) -> Self {
+        let mut m = Self::identity().0;
+        for i in 0..3 {
+            m[i][..3].copy_from_slice(&linear[i]);
+            m[i][3] = translation[i];
+        }
+        Self(m)
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[T::zero(); 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.0[j][i];
+            }
+        }
+        Self(m)
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting; `None` if singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.0;
+        let mut inv = Self::identity().0;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())?;
+            if a[pivot][col].abs() < T::from_f64(1e-12).unwrap() {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let scale = T::one() / a[col][col];
+            for j in 0..4 {
+                a[col][j] = a[col][j] * scale;
+                inv[col][j] = inv[col][j] * scale;
+            }
+            for row in (0..4).filter(|&row| row != col) {
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] = a[row][j] - factor * a[col][j];
+                    inv[row][j] = inv[row][j] - factor * inv[col][j];
+                }
+            }
+        }
+        Some(Self(inv))
+    }
+
+    /// Determinant of the linear 3x3 part: how an affine matrix scales volumes.
+    pub fn linear_determinant(&self) -> T {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Applies the matrix to `(p, 1)`, ignoring the projective row.
+    pub fn mul_point(&self, p: &Point3<T>) -> Point3<T> {
+        let m = &self.0;
+        Point3(
+            m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3],
+            m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3],
+            m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3],
+        )
+    }
+
+    /// Applies the matrix to `(v, 0)`, so translation is dropped.
+    pub fn mul_vector(&self, v: &Vec3<T>) -> Vec3<T> {
+        let m = &self.0;
+        Vec3(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+impl<T: SVecElem + Float> Mul for Mat4<T> {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self::Output {
+        let mut m = [[T::zero(); 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).fold(T::zero(), |acc, k| acc + self.0[i][k] * other.0[k][j]);
+            }
+        }
+        Self(m)
+    }
+}
+
+/// Invertible affine transform, kept together with its inverse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform<T: SVecElem> {
+    pub matrix: Mat4<T>,
+    pub inverse: Mat4<T>,
+}
+
+impl<T: SVecElem + Float> Transform<T> {
+    pub fn identity() -> Self {
+        Self { matrix: Mat4::identity(), inverse: Mat4::identity() }
+    }
+
+    /// `None` if `matrix` is singular.
+    pub fn from_matrix(matrix: Mat4<T>) -> Option<Self> {
+        Some(Self { matrix, inverse: matrix.inverse()? })
+    }
+
+    pub fn translate(offset: Vec3<T>) -> Self {
+        let (zero, one) = (T::zero(), T::one());
+        let linear = [[one, zero, zero], [zero, one, zero], [zero, zero, one]];
+        Self {
+            matrix: Mat4::affine(linear, offset),
+            inverse: Mat4::affine(linear, -offset),
+        }
+    }
+
+    /// Scales along each axis; every factor must be non-zero.
+    pub fn scale(factors: Vec3<T>) -> Self {
+        let diagonal = |v: Vec3<T>| {
+            let zero = T::zero();
+            Mat4::affine([[v.x(), zero, zero], [zero, v.y(), zero], [zero, zero, v.z()]], Vec3(zero, zero, zero))
+        };
+        let one = T::one();
+        Self {
+            matrix: diagonal(factors),
+            inverse: diagonal(Vec3(one / factors.x(), one / factors.y(), one / factors.z())),
+        }
+    }
+
+    /// Counter-clockwise rotation by `degrees` about `axis`, looking down the
+    /// axis towards the origin.
+    pub fn rotate(axis: Vec3<T>, degrees: T) -> Self {
+        let Vec3(x, y, z) = axis.to_unit();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let c = T::one() - cos;
+        let linear = [
+            [cos + x * x * c, x * y * c - z * sin, x * z * c + y * sin],
+            [y * x * c + z * sin, cos + y * y * c, y * z * c - x * sin],
+            [z * x * c - y * sin, z * y * c + x * sin, cos + z * z * c],
+        ];
+        let matrix = Mat4::affine(linear, Vec3(T::zero(), T::zero(), T::zero()));
+        // Rotations are orthogonal.
+        Self { matrix, inverse: matrix.transpose() }
+    }
+
+    pub fn rotate_x(degrees: T) -> Self {
+        Self::rotate(Vec3(T::one(), T::zero(), T::zero()), degrees)
+    }
+
+    pub fn rotate_y(degrees: T) -> Self {
+        Self::rotate(Vec3(T::zero(), T::one(), T::zero()), degrees)
+    }
+
+    pub fn rotate_z(degrees: T) -> Self {
+        Self::rotate(Vec3(T::zero(), T::zero(), T::one()), degrees)
+    }
+
+    /// Applies `self` first and `next` after it.
+    pub fn then(&self, next: &Self) -> Self {
+        Self {
+            matrix: next.matrix * self.matrix,
+            inverse: self.inverse * next.inverse,
+        }
+    }
+
+    pub fn inverted(&self) -> Self {
+        Self { matrix: self.inverse, inverse: self.matrix }
+    }
+
+    pub fn point(&self, p: &Point3<T>) -> Point3<T> {
+        self.matrix.mul_point(p)
+    }
+
+    pub fn vector(&self, v: &Vec3<T>) -> Vec3<T> {
+        self.matrix.mul_vector(v)
+    }
+
+    /// Transforms a surface normal by the inverse transpose, which keeps it
+    /// perpendicular to the transformed surface. The result is not normalised.
+    pub fn normal(&self, n: &Vec3<T>) -> Vec3<T> {
+        self.inverse.transpose().mul_vector(n)
+    }
+
+    /// Axis-aligned box around the transformed corners of `bbox`.
+    pub fn bbox(&self, bbox: &Aabb<T>) -> Aabb<T> {
+        let corner = |i: usize| Point3(
+            if i & 1 == 0 { bbox.minimum.x() } else { bbox.maximum.x() },
+            if i & 2 == 0 { bbox.minimum.y() } else { bbox.maximum.y() },
+            if i & 4 == 0 { bbox.minimum.z() } else { bbox.maximum.z() },
+        );
+        let first = self.point(&corner(0));
+        (1..8).fold(Aabb::new(first, first), |acc, i| acc.grow(self.point(&corner(i))))
+    }
+}
+
+impl<T: SVecElem + Float> Default for Transform<T> {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+/// Unit quaternion `w + xi + yj + zk`, used to interpolate rotations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quat<T: SVecElem> {
+    pub w: T,
+    pub v: Vec3<T>,
+}
+
+impl<T: SVecElem + Float> Quat<T> {
+    pub fn identity() -> Self {
+        Self { w: T::one(), v: Vec3(T::zero(), T::zero(), T::zero()) }
+    }
+
+    /// Same sense of rotation as `Transform::rotate`.
+    pub fn from_axis_angle(axis: Vec3<T>, degrees: T) -> Self {
+        let half = degrees.to_radians() / T::from_f64(2.).unwrap();
+        Self { w: half.cos(), v: axis.to_unit() * half.sin() }
+    }
+
+    fn dot(&self, other: &Self) -> T {
+        self.w * other.w + dot(&self.v, &other.v)
+    }
+
+    /// Angle in radians of the rotation taking `self` to `other` the short way.
+    pub fn angle_to(&self, other: &Self) -> T {
+        let two = T::from_f64(2.).unwrap();
+        self.dot(other).abs().min(T::one()).acos() * two
+    }
+
+    /// Constant-speed interpolation along the shorter arc.
+    pub fn slerp(&self, other: &Self, s: T) -> Self {
+        let mut cos = self.dot(other);
+        let mut other = *other;
+        if cos < T::zero() {
+            cos = -cos;
+            other = Self { w: -other.w, v: -other.v };
+        }
+        let (a, b) = if cos > T::from_f64(0.9995).unwrap() {
+            // Nearly parallel: linear blend, renormalised below.
+            (T::one() - s, s)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((T::one() - s) * theta).sin() / sin, (s * theta).sin() / sin)
+        };
+        let (w, v) = (self.w * a + other.w * b, self.v * a + other.v * b);
+        let norm = (w * w + dot(&v, &v)).sqrt();
+        Self { w: w / norm, v: v / norm }
+    }
+
+    pub fn to_transform(self) -> Transform<T> {
+        let (w, Vec3(x, y, z)) = (self.w, self.v);
+        let (one, two) = (T::one(), T::from_f64(2.).unwrap());
+        let linear = [
+            [one - two * (y * y + z * z), two * (x * y - w * z), two * (x * z + w * y)],
+            [two * (x * y + w * z), one - two * (x * x + z * z), two * (y * z - w * x)],
+            [two * (x * z - w * y), two * (y * z + w * x), one - two * (x * x + y * y)],
+        ];
+        let matrix = Mat4::affine(linear, Vec3(T::zero(), T::zero(), T::zero()));
+        Transform { matrix, inverse: matrix.transpose() }
+    }
+}
+
+/// Placement at one instant: scale, then rotate, then translate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe<T: SVecElem> {
+    pub time: T,
+    pub translation: Vec3<T>,
+    pub rotation: Quat<T>,
+    pub scale: Vec3<T>,
+}
+
+impl<T: SVecElem + Float> Keyframe<T> {
+    /// The identity placement at `time`.
+    #[allow(dead_code, reason = "for motions built in code; scene files give every component")]
+    pub fn new(time: T) -> Self {
+        Self {
+            time,
+            translation: Vec3(T::zero(), T::zero(), T::zero()),
+            rotation: Quat::identity(),
+            scale: Vec3(T::one(), T::one(), T::one()),
+        }
+    }
+
+    pub fn transform(&self) -> Transform<T> {
+        Transform::scale(self.scale)
+            .then(&self.rotation.to_transform())
+            .then(&Transform::translate(self.translation))
+    }
+}
+
+/// Keyframed placement: components are blended between the surrounding
+/// keyframes and held at the first and last ones outside them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Motion<T: SVecElem> {
+    keys: Vec<Keyframe<T>>,
+}
+
+/// Samples per keyframe interval when bounding the swept path.
+const MOTION_BOUND_STEPS: usize = 32;
+
+impl<T: SVecElem + Float> Motion<T> {
+    /// `None` without keyframes.
+    pub fn new(mut keys: Vec<Keyframe<T>>) -> Option<Self> {
+        if keys.is_empty() {
+            return None;
+        }
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        Some(Self { keys })
+    }
+
+    #[allow(dead_code, reason = "accessor for code inspecting a motion; rendering only needs `at`")]
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    pub fn at(&self, time: T) -> Transform<T> {
+        let i = self.keys.partition_point(|key| key.time <= time);
+        if i == 0 {
+            return self.keys[0].transform();
+        }
+        if i == self.keys.len() {
+            return self.keys[i - 1].transform();
+        }
+        let (a, b) = (&self.keys[i - 1], &self.keys[i]);
+        let s = (time - a.time) / (b.time - a.time);
+        let lerp = |x: Vec3<T>, y: Vec3<T>| x + (y - x) * s;
+        Keyframe {
+            time,
+            translation: lerp(a.translation, b.translation),
+            rotation: a.rotation.slerp(&b.rotation, s),
+            scale: lerp(a.scale, b.scale),
+        }.transform()
+    }
+
+    /// Box around `bbox` swept along the whole motion. Each interval is
+    /// sampled and padded by how far a rotating corner can bow out between
+    /// samples.
+    pub fn bbox(&self, bbox: &Aabb<T>) -> Aabb<T> {
+        let mut swept = self.keys[0].transform().bbox(bbox);
+        // Distance of the farthest corner from the origin it rotates about.
+        let far = |a: usize| bbox.minimum[a].abs().max(bbox.maximum[a].abs());
+        let reach = Vec3(far(0), far(1), far(2)).length();
+        for pair in self.keys.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            let steps = T::from_usize(MOTION_BOUND_STEPS).unwrap();
+            for step in 1..=MOTION_BOUND_STEPS {
+                let time = a.time + (b.time - a.time) * T::from_usize(step).unwrap() / steps;
+                swept = surrounding_box(&swept, &self.at(time).bbox(bbox));
+            }
+            let largest = |s: Vec3<T>| s.x().abs().max(s.y().abs()).max(s.z().abs());
+            let half_step = a.rotation.angle_to(&b.rotation) / (steps + steps);
+            let pad = reach * largest(a.scale).max(largest(b.scale)) * (T::one() - half_step.cos());
+            swept = Aabb::new(swept.minimum - pad, swept.maximum + pad);
+        }
+        swept
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &Mat4<f64>, b: &Mat4<f64>) -> bool {
+        (0..4).all(|i| (0..4).all(|j| (a.0[i][j] - b.0[i][j]).abs() < 1e-12))
+    }
+
+    fn composite() -> Transform<f64> {
+        Transform::scale(Vec3(2., 0.5, 3.))
+            .then(&Transform::rotate(Vec3(1., 2., -1.), 37.))
+            .then(&Transform::translate(Vec3(4., -1., 2.)))
+    }
+
+    #[test]
+    fn inverse() {
+        let t = composite();
+        assert!(close(&(t.matrix * t.inverse), &Mat4::identity()));
+        assert!(close(&t.matrix.inverse().unwrap(), &t.inverse));
+        assert!(Mat4::affine([[1., 2., 3.], [2., 4., 6.], [0., 0., 1.]], Vec3(0., 0., 0.)).inverse().is_none());
+
+        let p = Point3(0.3, -1.2, 5.);
+        assert!(t.inverted().point(&t.point(&p)).is_close(p));
+    }
+
+    #[test]
+    fn composition_order() {
+        let t = Transform::rotate_z(90.).then(&Transform::translate(Vec3(1., 0., 0.)));
+        assert!(t.point(&Point3(1., 0., 0.)).is_close(Point3(1., 1., 0.)));
+        assert!(t.vector(&Vec3(1., 0., 0.)).is_close(Vec3(0., 1., 0.)));
+        assert!(Transform::rotate_y(90.).point(&Point3(0., 0., 1.)).is_close(Point3(1., 0., 0.)));
+        assert!(Transform::rotate_x(90.).point(&Point3(0., 1., 0.)).is_close(Point3(0., 0., 1.)));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let t = composite();
+        let (a, b) = (Vec3(1., 0.5, -2.), Vec3(0.2, 1., 1.));
+        let n = cross(&a, &b);
+        let n = t.normal(&n);
+        assert!(dot(&n, &t.vector(&a)).abs() < 1e-9);
+        assert!(dot(&n, &t.vector(&b)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quaternions_match_matrices() {
+        let axis = Vec3(1., 2., -1.);
+        let q = Quat::from_axis_angle(axis, 37.).to_transform();
+        assert!(close(&q.matrix, &Transform::rotate(axis, 37.).matrix));
+
+        let (a, b) = (Quat::from_axis_angle(Vec3(0., 1., 0.), 20.), Quat::from_axis_angle(Vec3(0., 1., 0.), 100.));
+        let mid = a.slerp(&b, 0.25).to_transform();
+        assert!(close(&mid.matrix, &Transform::rotate_y(40.).matrix));
+        assert!((a.angle_to(&b) - 80f64.to_radians()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn keyframed_motion() {
+        let start = Keyframe::new(0.);
+        let end = Keyframe {
+            translation: Vec3(4., 0., 0.),
+            rotation: Quat::from_axis_angle(Vec3(0., 0., 1.), 90.),
+            scale: Vec3(3., 3., 3.),
+            ..Keyframe::new(1.)
+        };
+        let motion = Motion::new(vec![end, start]).unwrap();
+        assert_eq!(motion.keys()[0].time, 0.);
+
+        let p = Point3(1., 0., 0.);
+        let h = 0.5f64.sqrt();
+        assert!(motion.at(-1.).point(&p).is_close(p));
+        assert!(motion.at(0.5).point(&p).is_close(Point3(2. + 2. * h, 2. * h, 0.)));
+        assert!(motion.at(2.).point(&p).is_close(Point3(4., 3., 0.)));
+
+        // Every sampled placement of the unit cube stays inside the sweep.
+        let cube = Aabb::new(Point3(-1., -1., -1.), Point3(1., 1., 1.));
+        let swept = motion.bbox(&cube);
+        for i in 0..=1000 {
+            let moved = motion.at(i as f64 / 1000.).bbox(&cube);
+            let inside = |p: Point3<f64>| (0..3).all(|a| swept.minimum[a] <= p[a] && p[a] <= swept.maximum[a]);
+            assert!(inside(moved.minimum) && inside(moved.maximum));
+        }
+        assert!(Motion::<f64>::new(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn transformed_bbox() {
+        let bbox = Aabb::new(Point3(0., 0., 0.), Point3(1., 1., 1.));
+        let t = Transform::rotate_z(45.).then(&Transform::translate(Vec3(0., 0., 2.)));
+        let moved = t.bbox(&bbox);
+        let h = 0.5f64.sqrt();
+        assert!(moved.minimum.is_close(Point3(-h, 0., 2.)));
+        assert!(moved.maximum.is_close(Point3(h, 2. * h, 3.)));
+    }
+}
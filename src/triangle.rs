@@ -0,0 +1,332 @@
+use num::Float;
+use rand::Rng;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::common::SVecElem;
+use crate::hittable::*;
+use crate::hittable_list::*;
+use crate::materials::*;
+use crate::ray::*;
+use crate::sampler::Sampler;
+use crate::vec3::*;
+
+pub type TexCoord<T> = (T, T);
+
+/// Möller–Trumbore ray/triangle test. Returns `t` and the barycentric
+/// weights of the second and third vertex.
+fn intersect<T>(p: &[Point3<T>; 3], ray: &Ray<T>, t_min: T, t_max: T) -> Option<(T, T, T)>
+where
+    T: SVecElem + Float,
+{
+    let edge1 = p[1] - p[0];
+    let edge2 = p[2] - p[0];
+    let pvec = cross(&ray.direction, &edge2);
+    let det = dot(&edge1, &pvec);
+    if det == T::zero() {
+        return None;
+    }
+    let inv_det = T::one() / det;
+
+    let tvec = ray.origin - p[0];
+    let b1 = dot(&tvec, &pvec) * inv_det;
+    if b1 < T::zero() || b1 > T::one() {
+        return None;
+    }
+
+    let qvec = cross(&tvec, &edge1);
+    let b2 = dot(&ray.direction, &qvec) * inv_det;
+    if b2 < T::zero() || b1 + b2 > T::one() {
+        return None;
+    }
+
+    let t = dot(&edge2, &qvec) * inv_det;
+    if t < t_min || t_max < t {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// Builds the hit record shared by `Triangle` and `MeshTriangle`.
+#[allow(clippy::too_many_arguments)]
+fn hit_record<'a, T>(
+    ray: &Ray<T>,
+    p: &[Point3<T>; 3],
+    normals: Option<[Vec3<T>; 3]>,
+    uvs: Option<[TexCoord<T>; 3]>,
+    material: &Arc<dyn Material<T> + 'a>,
+    t: T,
+    b1: T,
+    b2: T,
+) -> HitRecord<'a, T>
+where
+    T: SVecElem + Float,
+{
+    let b0 = T::one() - b1 - b2;
+    let geometric = cross(&(p[1] - p[0]), &(p[2] - p[0])).to_unit();
+    let front_face = dot(&ray.direction, &geometric) < T::zero();
+
+    let mut normal = match normals {
+        Some(n) => (n[0] * b0 + n[1] * b1 + n[2] * b2).to_unit(),
+        None => geometric,
+    };
+    // Exported meshes may store normals against the winding order; the
+    // winding decides which side is the front.
+    if dot(&normal, &geometric) < T::zero() { normal = -normal; }
+    if !front_face { normal = -normal; }
+
+    let (u, v) = match uvs {
+        Some(uv) => (
+            uv[0].0 * b0 + uv[1].0 * b1 + uv[2].0 * b2,
+            uv[0].1 * b0 + uv[1].1 * b1 + uv[2].1 * b2,
+        ),
+        None => (b1, b2),
+    };
+
+    HitRecord {
+        t,
+        u,
+        v,
+        p: ray.at(t),
+        material: Arc::clone(material),
+        normal,
+        front_face,
+    }
+}
+
+/// Direction from `origin` to a uniform point on the triangle.
+fn sample_triangle<T>(p: &[Point3<T>; 3], origin: &Point3<T>, sampler: &mut Sampler) -> Vec3<T>
+where
+    T: SVecElem + Float,
+{
+    // Folding the unit square onto the triangle keeps the density uniform.
+    let (mut b1, mut b2) = (T::from_f64(sampler.gen()).unwrap(), T::from_f64(sampler.gen()).unwrap());
+    if b1 + b2 > T::one() {
+        (b1, b2) = (T::one() - b1, T::one() - b2);
+    }
+    p[0] + (p[1] - p[0]) * b1 + (p[2] - p[0]) * b2 - *origin
+}
+
+/// Solid-angle density of `sample_triangle` picking the direction of `ray`.
+fn triangle_pdf<T>(p: &[Point3<T>; 3], ray: &Ray<T>) -> T
+where
+    T: SVecElem + Float,
+{
+    let Some((t, _, _)) = intersect(p, ray, T::from_f64(RAY_EPSILON).unwrap(), T::infinity()) else {
+        return T::zero();
+    };
+    let normal = cross(&(p[1] - p[0]), &(p[2] - p[0]));
+    let area = normal.length() / T::from_f64(2.).unwrap();
+    area_to_solid_angle(ray, t, &normal.to_unit(), area)
+}
+
+fn triangle_box<T>(p: &[Point3<T>; 3]) -> Aabb<T>
+where
+    T: SVecElem + Float,
+{
+    // Pad flat boxes so axis-aligned triangles still have a volume.
+    let pad = T::from_f64(1e-4).unwrap();
+    let bbox = Aabb::new(p[0], p[1]).grow(p[2]);
+    Aabb::new(bbox.minimum - pad, bbox.maximum + pad)
+}
+
+/// A standalone triangle with optional per-vertex normals and UVs.
+pub struct Triangle<'a, T: SVecElem + Float> {
+    pub vertices: [Point3<T>; 3],
+    pub normals: Option<[Vec3<T>; 3]>,
+    pub uvs: Option<[TexCoord<T>; 3]>,
+    pub material: Arc<dyn Material<T> + 'a>,
+}
+
+impl<T> Hittable<T> for Triangle<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        let (t, b1, b2) = intersect(&self.vertices, ray, t_min, t_max)?;
+        Some(hit_record(ray, &self.vertices, self.normals, self.uvs, &self.material, t, b1, b2))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        Some(triangle_box(&self.vertices))
+    }
+
+    fn sample_towards(&self, origin: &Point3<T>, _time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        Some(sample_triangle(&self.vertices, origin, sampler))
+    }
+
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        triangle_pdf(&self.vertices, ray)
+    }
+}
+
+/// Indexed triangle mesh. Vertex attributes are indexed by the same
+/// entries of `indices`, and the buffers are shared by every triangle.
+pub struct TriangleMesh<'a, T: SVecElem + Float> {
+    pub positions: Arc<Vec<Point3<T>>>,
+    pub normals: Option<Arc<Vec<Vec3<T>>>>,
+    pub uvs: Option<Arc<Vec<TexCoord<T>>>>,
+    pub indices: Arc<Vec<[usize; 3]>>,
+    pub material: Arc<dyn Material<T> + 'a>,
+}
+
+impl<'a, T> TriangleMesh<'a, T>
+where
+    T: 'a + SVecElem + Float,
+{
+    /// One hittable per face, ready to be put into a `Bvh`.
+    pub fn triangles(self: &Arc<Self>) -> HittableList<'a, T> {
+        (0..self.indices.len())
+            .map(|index| Box::new(MeshTriangle { mesh: Arc::clone(self), index }) as _)
+            .collect()
+    }
+}
+
+/// A face of a `TriangleMesh`, referring back to the shared buffers.
+pub struct MeshTriangle<'a, T: SVecElem + Float> {
+    pub mesh: Arc<TriangleMesh<'a, T>>,
+    pub index: usize,
+}
+
+impl<T> MeshTriangle<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn vertices(&self) -> [Point3<T>; 3] {
+        self.mesh.indices[self.index].map(|i| self.mesh.positions[i])
+    }
+}
+
+impl<T> Hittable<T> for MeshTriangle<'_, T>
+where
+    T: SVecElem + Float,
+{
+    fn hit(&self, ray: &Ray<T>, t_min: T, t_max: T) -> Option<HitRecord<'_, T>> {
+        let vertices = self.vertices();
+        let (t, b1, b2) = intersect(&vertices, ray, t_min, t_max)?;
+
+        let idx = self.mesh.indices[self.index];
+        let normals = self.mesh.normals.as_ref().map(|n| idx.map(|i| n[i]));
+        let uvs = self.mesh.uvs.as_ref().map(|uv| idx.map(|i| uv[i]));
+        Some(hit_record(ray, &vertices, normals, uvs, &self.mesh.material, t, b1, b2))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb<T>> {
+        Some(triangle_box(&self.vertices()))
+    }
+
+    fn sample_towards(&self, origin: &Point3<T>, _time: T, sampler: &mut Sampler) -> Option<Vec3<T>> {
+        Some(sample_triangle(&self.vertices(), origin, sampler))
+    }
+
+    fn pdf_towards(&self, ray: &Ray<T>) -> T {
+        triangle_pdf(&self.vertices(), ray)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::tests::sampled_solid_angle;
+
+    fn material() -> Arc<dyn Material<f64>> {
+        Arc::new(Lambertian::new(Color3::new(0.5, 0.5, 0.5)))
+    }
+
+    fn unit_triangle() -> Triangle<'static, f64> {
+        Triangle {
+            vertices: [Point3(0.0, 0.0, 0.0), Point3(1.0, 0.0, 0.0), Point3(0.0, 1.0, 0.0)],
+            normals: None,
+            uvs: None,
+            material: material(),
+        }
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let tri = unit_triangle();
+        let ray = Ray { origin: Point3(0.25, 0.25, 1.0), direction: Vec3(0.0, 0.0, -1.0), time: 0., wavelengths: None };
+        let rec = tri.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 1.0);
+        assert_eq!(rec.p, Point3(0.25, 0.25, 0.0));
+        assert_eq!((rec.u, rec.v), (0.25, 0.25));
+        assert_eq!(rec.normal, Vec3(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+
+        let outside = Ray { origin: Point3(0.75, 0.75, 1.0), direction: Vec3(0.0, 0.0, -1.0), time: 0., wavelengths: None };
+        assert!(tri.hit(&outside, 0.001, f64::INFINITY).is_none());
+        let parallel = Ray { origin: Point3(0.25, 0.25, 1.0), direction: Vec3(1.0, 0.0, 0.0), time: 0., wavelengths: None };
+        assert!(tri.hit(&parallel, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn light_sampling() {
+        // A unit square 1 unit away subtends 4 asin(1/5), and either half
+        // of it split along a diagonal half of that.
+        let corners = [Point3(-0.5, -0.5, -1.), Point3(0.5, -0.5, -1.), Point3(-0.5, 0.5, -1.), Point3(0.5, 0.5, -1.)];
+        let expected = 4. * (1f64 / 5.).asin();
+        let half = Triangle { vertices: [corners[0], corners[1], corners[2]], ..unit_triangle() };
+        let estimate = sampled_solid_angle(&half, Point3(0., 0., 0.));
+        assert!((estimate - expected / 2.).abs() < 0.01, "{} vs {}", estimate, expected / 2.);
+
+        let mesh = Arc::new(TriangleMesh {
+            positions: Arc::new(corners.to_vec()),
+            normals: None,
+            uvs: None,
+            indices: Arc::new(vec![[0, 1, 2], [1, 3, 2]]),
+            material: material(),
+        });
+        let estimate = sampled_solid_angle(&mesh.triangles(), Point3(0., 0., 0.));
+        assert!((estimate - expected).abs() < 0.01, "{} vs {}", estimate, expected);
+        let away = Ray { origin: Point3(0., 0., 0.), direction: Vec3(0., 0., 1.), time: 0., wavelengths: None };
+        assert_eq!(mesh.triangles()[0].pdf_towards(&away), 0.);
+    }
+
+    #[test]
+    fn back_face() {
+        let tri = unit_triangle();
+        let ray = Ray { origin: Point3(0.25, 0.25, -1.0), direction: Vec3(0.0, 0.0, 1.0), time: 0., wavelengths: None };
+        let rec = tri.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn interpolated_attributes() {
+        let mesh = Arc::new(TriangleMesh {
+            positions: Arc::new(vec![Point3(0.0, 0.0, 0.0), Point3(1.0, 0.0, 0.0), Point3(0.0, 1.0, 0.0), Point3(1.0, 1.0, 0.0)]),
+            normals: Some(Arc::new(vec![Vec3(0.0, 0.0, 1.0), Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0), Vec3(0.0, 0.0, 1.0)])),
+            uvs: Some(Arc::new(vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)])),
+            indices: Arc::new(vec![[0, 1, 2], [1, 3, 2]]),
+            material: material(),
+        });
+        let triangles = mesh.triangles();
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(Arc::strong_count(&mesh), 3);
+
+        let ray = Ray { origin: Point3(0.5, 0.0, 1.0), direction: Vec3(0.0, 0.0, -1.0), time: 0., wavelengths: None };
+        let rec = triangles.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.u - 0.5).abs() < 1e-12 && rec.v.abs() < 1e-12);
+        assert!(rec.normal.is_close(Vec3(0.5, 0.0, 0.5).to_unit()));
+
+        let ray = Ray { origin: Point3(0.75, 0.75, 1.0), direction: Vec3(0.0, 0.0, -1.0), time: 0., wavelengths: None };
+        let rec = triangles.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.u - 0.75).abs() < 1e-12 && (rec.v - 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn normals_against_winding() {
+        // Counter-clockwise seen from +z, with normals pointing to -z.
+        let tri = Triangle { normals: Some([Vec3(0.0, 0.0, -1.0), Vec3(0.6, 0.0, -0.8), Vec3(0.0, 0.0, -1.0)]), ..unit_triangle() };
+        let ray = Ray { origin: Point3(0.25, 0.25, 1.0), direction: Vec3(0.0, 0.0, -1.0), time: 0., wavelengths: None };
+        let rec = tri.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(rec.front_face);
+        assert!(rec.normal.z() > 0.9 && rec.normal.x() < 0., "{:?}", rec.normal);
+
+        let ray = Ray { origin: Point3(0.25, 0.25, -1.0), direction: Vec3(0.0, 0.0, 1.0), time: 0., wavelengths: None };
+        let rec = tri.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!(!rec.front_face);
+        assert!(rec.normal.z() < -0.9 && dot(&rec.normal, &ray.direction) < 0.);
+    }
+}
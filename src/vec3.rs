@@ -150,7 +150,7 @@ impl<T: SVecElem + Float> Vec3<T> {
         val.sqrt()
     }
 
-    pub fn to_unit(&self) -> Self {
+    pub fn to_unit(self) -> Self {
         let len = self.length();
         Self(self.x() / len, self.y() / len, self.z() / len)
     }
@@ -168,6 +168,19 @@ pub fn cross<T: SVecElem> (v1: &Vec3<T>, v2: &Vec3<T>) -> Vec3<T> {
     )
 }
 
+/// Two unit vectors `(t, b)` completing the unit vector `n` to a
+/// right-handed orthonormal basis with `cross(t, b) == n` (Duff et al. 2017).
+pub fn orthonormal_basis<T: SVecElem + Float>(n: &Vec3<T>) -> (Vec3<T>, Vec3<T>) {
+    let one = T::one();
+    let sign = if n.z() >= T::zero() { one } else { -one };
+    let a = -one / (sign + n.z());
+    let b = n.x() * n.y() * a;
+    (
+        Vec3(one + sign * n.x() * n.x() * a, sign * b, -sign * n.x()),
+        Vec3(b, sign + n.y() * n.y() * a, -n.y()),
+    )
+}
+
 pub use Vec3 as Point3;
 pub use Vec3 as Color3;
 
@@ -317,4 +330,14 @@ mod tests {
         assert!(v.is_close(0.));
         assert!(!v.is_close(1.))            
     }
- }
\ No newline at end of file
+ 
+    #[test]
+    fn basis() {
+        for n in [Vec3(0., 0., 1.), Vec3(0., 0., -1.), Vec3(1., 2., -3.).to_unit(), Vec3(-0.3, 0.9, 0.1).to_unit()] {
+            let (t, b) = orthonormal_basis(&n);
+            assert!((t.length() - 1.).abs() < 1e-12 && (b.length() - 1.).abs() < 1e-12);
+            assert!(dot(&t, &n).abs() < 1e-12 && dot(&b, &n).abs() < 1e-12 && dot(&t, &b).abs() < 1e-12);
+            assert!(cross(&t, &b).is_close(n));
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use num::Float;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::aabb::*;
+use crate::common::SVecElem;
+use crate::perlin::*;
+use crate::vec3::*;
+
+#[derive(Debug)]
+pub enum VolumeError {
+    Io { path: PathBuf, source: io::Error },
+    Decode { path: PathBuf, message: String },
+}
+
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VolumeError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            VolumeError::Decode { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for VolumeError {}
+
+/// Dense grid of densities spread over a box, one value per voxel centre,
+/// stored x fastest, then y, then z.
+#[derive(Debug, Clone)]
+pub struct DensityGrid<T: SVecElem> {
+    pub bbox: Aabb<T>,
+    resolution: [usize; 3],
+    values: Vec<f32>,
+    max: f32,
+}
+
+impl<T: SVecElem + Float> DensityGrid<T> {
+    pub fn new(bbox: Aabb<T>, resolution: [usize; 3], values: Vec<f32>) -> Result<Self, String> {
+        if resolution.contains(&0) {
+            return Err(format!("grid resolution must be positive, got {:?}", resolution));
+        }
+        if values.len() != resolution.iter().product() {
+            return Err(format!("{:?} grid needs {} values, got {}", resolution, resolution.iter().product::<usize>(), values.len()));
+        }
+        if let Some(bad) = values.iter().find(|v| !(v.is_finite() && **v >= 0.)) {
+            return Err(format!("densities must be finite and non-negative, got {}", bad));
+        }
+        let max = values.iter().cloned().fold(0., f32::max);
+        Ok(Self { bbox, resolution, values, max })
+    }
+
+    /// Samples `density` at every voxel centre; negative results become 0.
+    pub fn from_fn(bbox: Aabb<T>, resolution: [usize; 3], density: impl Fn(Point3<T>) -> f64) -> Self {
+        let [nx, ny, nz] = resolution;
+        let size = bbox.extent();
+        let mut values = Vec::with_capacity(nx * ny * nz);
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    let centre = |index: usize, n: usize| T::from_f64((index as f64 + 0.5) / n as f64).unwrap();
+                    let p = bbox.minimum + size * Vec3(centre(i, nx), centre(j, ny), centre(k, nz));
+                    values.push(density(p).max(0.) as f32);
+                }
+            }
+        }
+        Self::new(bbox, resolution, values).unwrap()
+    }
+
+    #[allow(dead_code, reason = "accessor for code inspecting a grid; lookups go through `density`")]
+    pub fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    /// Largest voxel value, which bounds every interpolated density.
+    pub fn max(&self) -> T {
+        T::from_f32(self.max).unwrap()
+    }
+
+    fn voxel(&self, [i, j, k]: [usize; 3]) -> f64 {
+        let [nx, ny, _] = self.resolution;
+        self.values[(k * ny + j) * nx + i] as f64
+    }
+
+    /// Trilinear interpolation between voxel centres, held constant out to
+    /// the faces and zero outside the box.
+    pub fn density(&self, p: &Point3<T>) -> T {
+        let local = (*p - self.bbox.minimum) / self.bbox.extent();
+        let mut cell = [0; 3];
+        let mut frac = [0.; 3];
+        for a in 0..3 {
+            let x = local[a].to_f64().unwrap();
+            if !(0. ..=1.).contains(&x) {
+                return T::zero();
+            }
+            let n = self.resolution[a];
+            let x = (x * n as f64 - 0.5).clamp(0., (n - 1) as f64);
+            cell[a] = (x as usize).min(n.saturating_sub(2));
+            frac[a] = x - cell[a] as f64;
+        }
+        let mut sum = 0.;
+        for corner in 0..8 {
+            let mut weight = 1.;
+            let mut index = cell;
+            for a in 0..3 {
+                let upper = corner >> a & 1 == 1;
+                if upper {
+                    index[a] = (index[a] + 1).min(self.resolution[a] - 1);
+                }
+                weight *= if upper { frac[a] } else { 1. - frac[a] };
+            }
+            if weight > 0. {
+                sum += weight * self.voxel(index);
+            }
+        }
+        T::from_f64(sum).unwrap()
+    }
+}
+
+/// Procedural cloud filling the box: a ball fading from 1 at the centre to
+/// 0 at the face centres, roughened by fBm noise of frequency `scale`.
+pub fn noise_cloud<T: SVecElem + Float>(bbox: Aabb<T>, resolution: [usize; 3], perlin: &Perlin, scale: f64, octaves: &Octaves) -> DensityGrid<T> {
+    let centre = bbox.centroid();
+    let half = bbox.extent() / T::from_f64(2.).unwrap();
+    DensityGrid::from_fn(bbox, resolution, |p| {
+        let q = (p - centre) / half;
+        let p = [p.x(), p.y(), p.z()].map(|v| v.to_f64().unwrap() * scale);
+        1. - dot(&q, &q).to_f64().unwrap() + perlin.fbm(p, octaves)
+    })
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+
+fn read_f32(bytes: &[u8], pos: usize) -> f32 {
+    f32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+
+/// Decodes a Mitsuba grid volume (`VOL` version 3) with 32-bit float or
+/// 8-bit samples. Multi-channel grids are averaged to one density.
+pub fn decode_vol<T: SVecElem + Float>(bytes: &[u8]) -> Result<DensityGrid<T>, String> {
+    const HEADER: usize = 48;
+    if bytes.len() < HEADER || &bytes[..3] != b"VOL" {
+        return Err("not a Mitsuba VOL file".into());
+    }
+    if bytes[3] != 3 {
+        return Err(format!("unsupported VOL version {}", bytes[3]));
+    }
+    let encoding = read_u32(bytes, 4);
+    let resolution = [8, 12, 16].map(|pos| read_u32(bytes, pos) as usize);
+    let channels = read_u32(bytes, 20) as usize;
+    let corner = |pos: usize| [pos, pos + 4, pos + 8].map(|pos| read_f32(bytes, pos) as f64);
+    let (min, max) = (corner(24), corner(36));
+    if !(0..3).all(|a| min[a] < max[a]) {
+        return Err(format!("invalid VOL bounds {:?} to {:?}", min, max));
+    }
+    if channels == 0 {
+        return Err("VOL file has no channels".into());
+    }
+
+    let sample_size = match encoding {
+        1 => 4,
+        3 => 1,
+        _ => return Err(format!("unsupported VOL encoding {}, expected float32 (1) or uint8 (3)", encoding)),
+    };
+    let count = resolution.iter().try_fold(channels, |acc, &n| acc.checked_mul(n))
+        .filter(|count| count.checked_mul(sample_size).is_some_and(|size| size <= bytes.len() - HEADER))
+        .ok_or("VOL data is truncated")?;
+    let sample = |i: usize| match encoding {
+        1 => read_f32(bytes, HEADER + 4 * i),
+        _ => bytes[HEADER + i] as f32 / 255.,
+    };
+    let values = (0..count / channels)
+        .map(|voxel| (0..channels).map(|c| sample(voxel * channels + c)).sum::<f32>() / channels as f32)
+        .collect();
+    let point = |p: [f64; 3]| Point3::<T>::new(p[0], p[1], p[2]);
+    DensityGrid::new(Aabb::new(point(min), point(max)), resolution, values)
+}
+
+pub fn read_vol<T: SVecElem + Float>(path: impl AsRef<Path>) -> Result<DensityGrid<T>, VolumeError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|source| VolumeError::Io { path: path.to_owned(), source })?;
+    decode_vol(&bytes).map_err(|message| VolumeError::Decode { path: path.to_owned(), message })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb<f64> {
+        Aabb::new(Point3(0., 0., 0.), Point3(1., 1., 1.))
+    }
+
+    #[test]
+    fn trilinear_lookup() {
+        // Density equal to x is reproduced exactly between voxel centres.
+        let grid = DensityGrid::from_fn(unit_box(), [4, 3, 2], |p| p.x());
+        assert_eq!(grid.max(), 0.875);
+        for x in [0.125, 0.3, 0.5, 0.8, 0.875] {
+            assert!((grid.density(&Point3(x, 0.4, 0.9)) - x).abs() < 1e-6, "{}", x);
+        }
+        // Held flat towards the faces, zero outside.
+        assert!((grid.density(&Point3(0.01, 0.5, 0.5)) - 0.125).abs() < 1e-6);
+        assert_eq!(grid.density(&Point3(1.01, 0.5, 0.5)), 0.);
+
+        let flat = DensityGrid::<f64>::new(unit_box(), [1, 1, 1], vec![2.]).unwrap();
+        assert_eq!(flat.density(&Point3(0.3, 0.9, 0.1)), 2.);
+        assert!(DensityGrid::<f64>::new(unit_box(), [2, 2, 2], vec![1.; 7]).is_err());
+        assert!(DensityGrid::<f64>::new(unit_box(), [1, 1, 1], vec![-1.]).is_err());
+    }
+
+    #[test]
+    fn cloud_fades_out() {
+        let bbox = Aabb::new(Point3(-2., -1., -1.), Point3(2., 1., 1.));
+        let cloud = noise_cloud(bbox, [32, 16, 16], &Perlin::new(1), 2., &Octaves::default());
+        assert!(cloud.density(&Point3(0., 0., 0.)) > 0.3);
+        assert_eq!(cloud.density(&Point3(1.99, 0.99, 0.99)), 0.);
+        assert!(cloud.max() <= 2.);
+    }
+
+    fn vol_header(encoding: u32, resolution: [u32; 3], channels: u32) -> Vec<u8> {
+        let mut bytes = b"VOL\x03".to_vec();
+        for word in [encoding, resolution[0], resolution[1], resolution[2], channels] {
+            bytes.extend(word.to_le_bytes());
+        }
+        for bound in [-1f32, 0., 0., 1., 2., 4.] {
+            bytes.extend(bound.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn decode_mitsuba_vol() {
+        let mut bytes = vol_header(1, [2, 1, 2], 1);
+        for v in [0f32, 1., 2., 3.] {
+            bytes.extend(v.to_le_bytes());
+        }
+        let grid = decode_vol::<f64>(&bytes).unwrap();
+        assert_eq!(grid.resolution(), [2, 1, 2]);
+        assert_eq!((grid.bbox.minimum, grid.bbox.maximum), (Point3(-1., 0., 0.), Point3(1., 2., 4.)));
+        assert_eq!(grid.max(), 3.);
+        // x fastest, then z: the voxel at x = 1, z = 0 holds 1.
+        assert!((grid.density(&Point3(0.5, 1., 1.)) - 1.).abs() < 1e-6);
+
+        let mut rgb = vol_header(3, [1, 1, 1], 3);
+        rgb.extend([0, 51, 255]);
+        assert!((decode_vol::<f64>(&rgb).unwrap().max() - 0.4).abs() < 1e-6);
+
+        assert!(decode_vol::<f64>(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_vol::<f64>(&vol_header(2, [1, 1, 1], 1)).is_err());
+        assert!(decode_vol::<f64>(b"VOX\x03").is_err());
+    }
+}
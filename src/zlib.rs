@@ -0,0 +1,480 @@
+//! Just enough zlib (RFC 1950) to produce valid streams without a
+//! compression dependency: either stored deflate blocks, or a single
+//! fixed-Huffman block with a simple hash-chain LZ77 matcher. The decoder
+//! handles all three deflate block types.
+
+const MAX_STORED_BLOCK: usize = 65535;
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_update(0, bytes)
+}
+
+/// Continues a CRC-32 (as used by PNG and gzip) over more bytes.
+pub fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn adler32(bytes: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest run that cannot overflow `b` before reduction.
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// LSB-first bit packer used by the deflate encoder.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit.
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+fn write_literal(w: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => w.write_code(0x30 + symbol, 8),
+        144..=255 => w.write_code(0x190 + symbol - 144, 9),
+        256..=279 => w.write_code(symbol - 256, 7),
+        _ => w.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let l = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap();
+    write_literal(w, 257 + l as u32);
+    w.write((len - LENGTH_BASE[l] as usize) as u32, LENGTH_EXTRA[l] as u32);
+
+    let d = DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap();
+    w.write_code(d as u32, 5);
+    w.write((dist - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d] as u32);
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses `data` into a zlib stream with one fixed-Huffman block.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter { out: vec![0x78, 0x01], bits: 0, count: 0 };
+    w.write(1, 1); // BFINAL
+    w.write(1, 2); // BTYPE = fixed Huffman
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |head: &mut [usize], prev: &mut [usize], pos: usize| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(&data[pos..]);
+            prev[pos % WINDOW_SIZE] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(&data[pos..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[candidate..].iter().zip(&data[pos..pos + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                // Entries older than the window may have been overwritten.
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut w, best_len, best_dist);
+            for p in pos..pos + best_len {
+                insert(&mut head, &mut prev, p);
+            }
+            pos += best_len;
+        } else {
+            write_literal(&mut w, data[pos] as u32);
+            insert(&mut head, &mut prev, pos);
+            pos += 1;
+        }
+    }
+    write_literal(&mut w, 256);
+
+    let mut out = w.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Wraps `data` in a zlib stream made of uncompressed deflate blocks.
+pub fn compress_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // CM = 8 (deflate), 32K window, no dictionary, fastest level; the
+    // header is a multiple of 31 as required.
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+
+/// LSB-first bit reader over a deflate stream.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, count: u32) -> Result<u32, String> {
+        while self.count < count {
+            let byte = *self.data.get(self.pos).ok_or("unexpected end of deflate stream")?;
+            self.bits |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.bits & ((1u64 << count) - 1) as u32;
+        self.bits >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Drops the bits left in the current byte.
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman decoding table, in the layout of zlib's `puff`.
+struct Huffman {
+    /// Number of codes of each length.
+    counts: [u16; 16],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, String> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err("over-subscribed Huffman code".into());
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, r: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= r.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".into())
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths).unwrap(), Huffman::new(&[5; 30]).unwrap())
+}
+
+fn dynamic_tables(r: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let nlen = r.read(5)? as usize + 257;
+    let ndist = r.read(5)? as usize + 1;
+    let ncode = r.read(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err("too many length or distance codes".into());
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &i in &ORDER[..ncode] {
+        code_lengths[i] = r.read(3)? as u8;
+    }
+    let code_table = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_table.decode(r)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..i].last().ok_or("repeat with no previous length")?;
+                (previous, 3 + r.read(2)? as usize)
+            }
+            17 => (0, 3 + r.read(3)? as usize),
+            _ => (0, 11 + r.read(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err("code lengths overflow the table".into());
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err("missing end-of-block code".into());
+    }
+    Ok((Huffman::new(&lengths[..nlen])?, Huffman::new(&lengths[nlen..])?))
+}
+
+fn inflate_block(r: &mut BitReader, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = lit.decode(r)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let l = symbol - 257;
+                let len = LENGTH_BASE[l] as usize + r.read(LENGTH_EXTRA[l] as u32)? as usize;
+                let d = dist.decode(r)? as usize;
+                if d >= 30 {
+                    return Err("invalid distance code".into());
+                }
+                let distance = DIST_BASE[d] as usize + r.read(DIST_EXTRA[d] as u32)? as usize;
+                if distance > out.len() {
+                    return Err("distance reaches before the start of the output".into());
+                }
+                let start = out.len() - distance;
+                // Byte by byte, since a match may overlap what it produces.
+                for k in 0..len {
+                    out.push(out[start + k]);
+                }
+            }
+            _ => return Err("invalid literal/length code".into()),
+        }
+    }
+}
+
+/// Decodes a zlib stream and checks its Adler-32 checksum.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 6 {
+        return Err("zlib stream too short".into());
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err("not a deflate zlib stream".into());
+    }
+    if flg & 0x20 != 0 {
+        return Err("preset dictionaries are not supported".into());
+    }
+
+    let mut r = BitReader { data: &data[2..], pos: 0, bits: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = r.read(1)? == 1;
+        match r.read(2)? {
+            0 => {
+                r.align();
+                let header = r.data.get(r.pos..r.pos + 4).ok_or("truncated stored block")?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err("stored block length mismatch".into());
+                }
+                r.pos += 4;
+                let block = r.data.get(r.pos..r.pos + len as usize).ok_or("truncated stored block")?;
+                out.extend_from_slice(block);
+                r.pos += len as usize;
+            }
+            1 => {
+                let (lit, dist) = fixed_tables();
+                inflate_block(&mut r, &mut out, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_tables(&mut r)?;
+                inflate_block(&mut r, &mut out, &lit, &dist)?;
+            }
+            _ => return Err("invalid deflate block type".into()),
+        }
+        if last {
+            break;
+        }
+    }
+
+    r.align();
+    let checksum = r.data.get(r.pos..r.pos + 4).ok_or("missing Adler-32 checksum")?;
+    if u32::from_be_bytes(checksum.try_into().unwrap()) != adler32(&out) {
+        return Err("Adler-32 checksum mismatch".into());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn stored_stream() {
+        assert_eq!(compress_stored(b"abc"), [0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x02, 0x4d, 0x01, 0x27]);
+        assert_eq!(compress_stored(b""), [0x78, 0x01, 0x01, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01]);
+
+        let big = vec![7u8; 70000];
+        let stream = compress_stored(&big);
+        assert_eq!(stream.len(), 2 + 5 + 65535 + 5 + (70000 - 65535) + 4);
+        assert_eq!(stream[2], 0);
+        assert_eq!(stream[2 + 5 + 65535], 1);
+    }
+
+    #[test]
+    fn fixed_huffman_stream() {
+        // "abc" followed by a 6 byte match at distance 3; zlib inflates it.
+        assert_eq!(compress(b"abcabcabc"), [0x78, 0x01, 0x4b, 0x4c, 0x4a, 0x86, 0x20, 0x00, 0x11, 0x3d, 0x03, 0x73]);
+        assert_eq!(compress(b""), [0x78, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+        let repetitive: Vec<u8> = (0..100_000).map(|i| (i % 7) as u8).collect();
+        assert!(compress(&repetitive).len() < repetitive.len() / 50);
+    }
+
+    #[test]
+    fn inflate_roundtrip() {
+        let text: Vec<u8> = (0..100_000u64).map(|i| (i * i % 251) as u8 ^ (i / 1000) as u8).collect();
+        for data in [&b""[..], b"abcabcabc", &[7u8; 70000], &text] {
+            assert_eq!(decompress(&compress(data)).unwrap(), data);
+            assert_eq!(decompress(&compress_stored(data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn inflate_dynamic_huffman() {
+        // zlib.compress(data, 9) picks a dynamic block for this skewed input.
+        let stream = [
+            0x78, 0xda, 0x5d, 0x8d, 0x81, 0x09, 0x00, 0x30, 0x08, 0xc3, 0x6e, 0x4d, 0xfe, 0x3f, 0x62, 0xe0, 0x50, 0x8b, 0x22,
+            0x28, 0x25, 0x6d, 0x01, 0xa4, 0x46, 0xb4, 0x1f, 0x5a, 0xaa, 0x75, 0x91, 0xb8, 0x92, 0x5e, 0xda, 0x3d, 0xb6, 0xa1,
+            0xbf, 0xd6, 0xa4, 0x27, 0xe4, 0x74, 0x4e, 0x99, 0x6e, 0xd2, 0x10, 0x59, 0x97, 0xe9, 0x3c, 0xd3, 0xf4, 0x4b, 0xf4,
+        ];
+        assert_eq!((stream[2] >> 1) & 3, 2);
+        let mut x = 12345u32;
+        let data: Vec<u8> = (0..200).map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7fff_ffff;
+            b"aaab"[(x >> 16) as usize & 3]
+        }).collect();
+        assert_eq!(decompress(&stream).unwrap(), data);
+    }
+
+    #[test]
+    fn inflate_rejects_corruption() {
+        let mut stream = compress(b"hello hello hello");
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        assert!(decompress(&stream).is_err());
+        assert!(decompress(&[0x78, 0x01, 0x07, 0x00, 0x00, 0x00]).is_err());
+        assert!(decompress(&compress(b"truncated")[..6]).is_err());
+    }
+}